@@ -1,52 +1,129 @@
 use core::time::Duration;
-use criterion::{criterion_group, criterion_main, Criterion, SamplingMode};
-use orderbooklib::{OrderBook, Side};
-use rand::Rng;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use orderbooklib::{
+    OrderBook, OrderId, OrderRequest, OrderType, Price, PriceType, Side, SystemClock, Ticks,
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
 use rand_distr::{Distribution, Normal};
 
-fn initialize_orderbook(
-    num_orders: i32,
-    rng: &mut rand::prelude::ThreadRng,
-    normal: Normal<f64>,
-) -> OrderBook {
-    let mut ob = OrderBook::default();
+const BOOK_ORDERS: usize = 100_000;
+const CANCELS: usize = 10_000;
+const MARKET_ORDERS: usize = 10_000;
+
+// Builds a book of resting limit orders, bids below 5000 and asks above so nothing matches.
+// The narrow price distribution gives deep levels with thousands of orders each.
+fn initialize_orderbook<P: PriceType>(
+    num_orders: usize,
+    rng: &mut StdRng,
+) -> (OrderBook<SystemClock, P>, Vec<OrderId>) {
+    let normal = Normal::<f64>::new(0.0, 10.0).unwrap();
+    let mut ob = OrderBook::with_scale(SystemClock, 0, 0);
+    let mut ids = Vec::with_capacity(num_orders);
     for _ in 0..num_orders {
-        if rng.random_bool(0.5) {
-            ob.add_limit_order(Side::Bid, normal.sample(rng), rng.random_range(1..=500));
+        let offset = normal.sample(rng).abs().round() as i64 + 1;
+        let (side, price) = if rng.random_bool(0.5) {
+            (Side::Bid, 5000 - offset)
         } else {
-            ob.add_limit_order(
-                Side::Ask,
-                normal.sample(rng) as u64,
-                rng.random_range(1..=500),
-            );
-        }
+            (Side::Ask, 5000 + offset)
+        };
+        let qty = rng.random_range(1..=500);
+        let order = OrderRequest::new(side, qty, OrderType::limit(price));
+        let (result, _) = ob.add_order(order).unwrap();
+        ids.push(result.get_id());
     }
-    ob
+    (ob, ids)
 }
 
-fn match_orders(ob: &mut OrderBook, rng: &mut rand::prelude::ThreadRng, normal: Normal<f64>) {
-    for _ in 0..10000 {
-        let _fr = ob.add_limit_order(Side::Ask, normal.sample(rng) as u64, rng.gen_range(1..=500));
-    }
-    //ob.get_bbo();
+// Picks orders from anywhere in their level, not just the oldest
+fn shuffled_ids(ids: &[OrderId], rng: &mut StdRng) -> Vec<OrderId> {
+    let mut ids = ids.to_vec();
+    ids.shuffle(rng);
+    ids.truncate(CANCELS);
+    ids
 }
-pub fn criterion_benchmark(c: &mut Criterion) {
-    let mut rng: rand::prelude::ThreadRng = rand::thread_rng();
-    let normal = Normal::new(5000.0, 500.0).unwrap();
-    let mut ob = initialize_orderbook(100_000, &mut rng, normal);
-    let mut group = c.benchmark_group("order-benchmark");
+
+// Runs every scenario against a book keyed by the given price type
+fn bench_price_type<P: PriceType>(c: &mut Criterion, name: &str) {
+    let mut rng = StdRng::seed_from_u64(42);
+    let (book, ids) = initialize_orderbook::<P>(BOOK_ORDERS, &mut rng);
+    // The matching scenarios need levels on both sides to trade against
+    let (asks, bids) = book.get_depth();
+    assert!(asks > 0 && bids > 0, "{} book has an empty side", name);
+
+    let mut group = c.benchmark_group(format!("order-benchmark/{}", name));
     group.sample_size(10);
     group.measurement_time(Duration::new(20, 0));
-    group.bench_function("Match 100k orders", |b| {
-        b.iter(|| initialize_orderbook(1_000_00, &mut rng, normal))
+
+    group.bench_function("Add 100k limit orders", |b| {
+        b.iter(|| initialize_orderbook::<P>(BOOK_ORDERS, &mut rng))
+    });
+
+    group.bench_function("Delete 10k orders from a book of 100k", |b| {
+        b.iter_batched(
+            || {
+                let mut rng = StdRng::seed_from_u64(42);
+                let (book, ids) = initialize_orderbook::<P>(BOOK_ORDERS, &mut rng);
+                (book, shuffled_ids(&ids, &mut rng))
+            },
+            |(mut book, ids)| {
+                for id in ids {
+                    book.delete_order(id).unwrap();
+                }
+                book
+            },
+            BatchSize::LargeInput,
+        )
     });
-    /*
-    group.bench_function("match 10000 orders on orderbook with 100k orders", |b| {
-        b.iter(|| match_orders(&mut ob, &mut rng, normal))
+
+    group.bench_function("Partially cancel 10k orders in a book of 100k", |b| {
+        b.iter_batched(
+            || {
+                let mut rng = StdRng::seed_from_u64(42);
+                let (book, ids) = initialize_orderbook::<P>(BOOK_ORDERS, &mut rng);
+                (book, shuffled_ids(&ids, &mut rng))
+            },
+            |(mut book, ids)| {
+                for id in ids {
+                    book.cancel_order(id, 1).unwrap();
+                }
+                book
+            },
+            BatchSize::LargeInput,
+        )
     });
-    */
+
+    group.bench_function("Match 10k market orders against a book of 100k", |b| {
+        b.iter_batched(
+            || initialize_orderbook::<P>(BOOK_ORDERS, &mut StdRng::seed_from_u64(42)).0,
+            |mut book| {
+                for i in 0..MARKET_ORDERS {
+                    let side = if i % 2 == 0 { Side::Bid } else { Side::Ask };
+                    book.add_order(OrderRequest::new(side, 5, OrderType::Market))
+                        .unwrap();
+                }
+                book
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let lookups = shuffled_ids(&ids, &mut rng);
+    group.bench_function("Get 10k orders from a book of 100k", |b| {
+        b.iter(|| {
+            lookups
+                .iter()
+                .filter(|id| book.get_order(**id).is_some())
+                .count()
+        })
+    });
+
     group.finish();
 }
 
+pub fn criterion_benchmark(c: &mut Criterion) {
+    bench_price_type::<Price>(c, "decimal");
+    bench_price_type::<Ticks>(c, "ticks");
+}
+
 criterion_group!(benches, criterion_benchmark);
 criterion_main!(benches);
@@ -3,14 +3,14 @@ use orderbooklib::{HalfBook, OrderBook, OrderRequest, OrderType, Side, TradeOrde
 fn halfbook() {
     let mut ask_book = HalfBook::new(Side::Ask);
     let order = TradeOrder::new(200);
-    ask_book.add_order(200, order);
+    ask_book.add_order(200.into(), order);
     let order = TradeOrder::new(300);
-    ask_book.add_order(200, order);
+    ask_book.add_order(200.into(), order);
     let order = TradeOrder::new(300);
-    ask_book.add_order(300, order);
+    ask_book.add_order(300.into(), order);
     let order = TradeOrder::new(100);
     let id = order.id;
-    ask_book.add_order(100, order);
+    ask_book.add_order(100.into(), order);
     println!("{:#?}", ask_book);
     println!("{:?}", ask_book.best_price());
 
@@ -18,8 +18,8 @@ fn halfbook() {
     println!("{:?}", ask_book.remove_order(&100.into(), id));
     println!("{:#?}", ask_book);
     println!("{:?}", ask_book.best_price());
-    println!("{:?}", ask_book.get_price_level(&200.into()));
-    println!("{:?}", ask_book.get_price_level(&300.into()));
+    println!("{:?}", ask_book.get_orders_at_price(200));
+    println!("{:?}", ask_book.get_orders_at_price(300));
     println!("{:?}", ask_book.get_total_qty(&200.into()));
     ask_book.show_depth()
 }
@@ -36,7 +36,7 @@ fn main() {
     let order = OrderRequest::new(Side::Ask, 200, OrderType::limit(200));
     println!("{:?}", orderbook.add_order(order));
     let order = OrderRequest::new(Side::Ask, 300, OrderType::limit(200));
-    let (fill_result, executions) = orderbook.add_order(order);
+    let (fill_result, executions) = orderbook.add_order(order).unwrap();
     let order = OrderRequest::new(Side::Ask, 400, OrderType::limit(250));
     println!("{:?}", orderbook.add_order(order));
     println!("{:#?}", fill_result);
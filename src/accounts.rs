@@ -0,0 +1,343 @@
+use std::collections::{HashMap, HashSet};
+
+use rust_decimal::Decimal;
+
+use crate::errors::{OrderBookError, Result};
+use crate::{AccountId, OrderId, RejectReason, Side, TradeExecution, TradingPair};
+
+/// Balance of one asset held by an account.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Balance {
+    // Free to withdraw or to reserve for new orders
+    pub available: Decimal,
+    // Held for the account's open orders
+    pub reserved: Decimal,
+}
+
+impl Balance {
+    pub fn total(&self) -> Decimal {
+        self.available + self.reserved
+    }
+}
+
+// Funds held for an open order, in the quote asset of its pair for a bid and in the base asset for an ask
+#[derive(Debug, Clone, PartialEq)]
+struct Reservation {
+    account: AccountId,
+    pair: TradingPair,
+    side: Side,
+    amount: Decimal,
+}
+
+impl Reservation {
+    fn asset(&self) -> &str {
+        match self.side {
+            Side::Bid => self.pair.quote(),
+            Side::Ask => self.pair.base(),
+        }
+    }
+}
+
+/// Accounts holds the balance of every asset of each account and the funds reserved for their open orders.
+///
+/// Funds only enter and leave through deposits and withdrawals, trades move them from one account to another, so the
+/// balances of an asset always add up to its net deposits. `check_invariants` verifies that they do.
+#[derive(Debug, Clone, Default)]
+pub struct Accounts {
+    balances: HashMap<AccountId, HashMap<String, Balance>>,
+    reservations: HashMap<OrderId, Reservation>,
+    // Orders of each pair holding a reservation
+    pair_orders: HashMap<TradingPair, HashSet<OrderId>>,
+    // Deposits less withdrawals of each asset
+    supply: HashMap<String, Decimal>,
+}
+
+impl Accounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn deposit(&mut self, account: AccountId, asset: &str, amount: Decimal) -> Result<()> {
+        if amount <= Decimal::ZERO {
+            return Err(OrderBookError::InvalidQuantity(amount));
+        }
+        self.balance_mut(account, asset).available += amount;
+        *self.supply.entry(asset.to_string()).or_default() += amount;
+        Ok(())
+    }
+
+    /// Withdraws from the available balance, funds reserved for open orders can not be withdrawn.
+    pub fn withdraw(&mut self, account: AccountId, asset: &str, amount: Decimal) -> Result<()> {
+        if amount <= Decimal::ZERO {
+            return Err(OrderBookError::InvalidQuantity(amount));
+        }
+        let balance = self.balance_mut(account, asset);
+        if balance.available < amount {
+            return Err(OrderBookError::InsufficientFunds {
+                account,
+                asset: asset.to_string(),
+            });
+        }
+        balance.available -= amount;
+        *self.supply.entry(asset.to_string()).or_default() -= amount;
+        Ok(())
+    }
+
+    pub fn balance(&self, account: AccountId, asset: &str) -> Balance {
+        self.balances
+            .get(&account)
+            .and_then(|balances| balances.get(asset))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns the funds still reserved for the order.
+    pub fn reserved(&self, order_id: &OrderId) -> Option<Decimal> {
+        self.reservations
+            .get(order_id)
+            .map(|reservation| reservation.amount)
+    }
+
+    /// Reserves `amount` of the account's available funds for an order of the pair, of the quote asset for a bid and
+    /// of the base asset for an ask. An order holds a single reservation, its id can not be reserved for twice.
+    pub fn reserve(
+        &mut self,
+        order_id: OrderId,
+        account: AccountId,
+        pair: &TradingPair,
+        side: Side,
+        amount: Decimal,
+    ) -> Result<(), RejectReason> {
+        // Replacing the reservation would strand the funds the other order holds
+        if self.reservations.contains_key(&order_id) {
+            return Err(RejectReason::DuplicateOrderId);
+        }
+        let reservation = Reservation {
+            account,
+            pair: pair.clone(),
+            side,
+            amount,
+        };
+        let balance = self.balance_mut(account, reservation.asset());
+        if balance.available < amount {
+            return Err(RejectReason::InsufficientFunds);
+        }
+        balance.available -= amount;
+        balance.reserved += amount;
+        self.pair_orders
+            .entry(pair.clone())
+            .or_default()
+            .insert(order_id);
+        self.reservations.insert(order_id, reservation);
+        Ok(())
+    }
+
+    /// Returns the reserved funds of the order above `keep` to its account's available balance.
+    pub fn release(&mut self, order_id: &OrderId, keep: Decimal) {
+        let Some(reservation) = self.reservations.get_mut(order_id) else {
+            return;
+        };
+        let excess = reservation.amount - keep;
+        if excess <= Decimal::ZERO {
+            return;
+        }
+        reservation.amount = keep;
+        let (account, asset) = (reservation.account, reservation.asset().to_string());
+        let balance = self.balance_mut(account, &asset);
+        balance.reserved -= excess;
+        balance.available += excess;
+    }
+
+    /// Releases all the funds of an order that left the book and drops its reservation.
+    pub fn close(&mut self, order_id: &OrderId) {
+        self.release(order_id, Decimal::ZERO);
+        let Some(reservation) = self.reservations.remove(order_id) else {
+            return;
+        };
+        if let Some(orders) = self.pair_orders.get_mut(&reservation.pair) {
+            orders.remove(order_id);
+            if orders.is_empty() {
+                self.pair_orders.remove(&reservation.pair);
+            }
+        }
+    }
+
+    /// Returns the orders of the pair holding a reservation.
+    pub fn orders_of(&self, pair: &TradingPair) -> Vec<OrderId> {
+        self.pair_orders
+            .get(pair)
+            .map(|orders| orders.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Moves the funds of a trade of the pair between the buyer and the seller. Each side pays out of the reservation
+    /// of its order and is credited to its available balance, a side whose order holds no reservation is not settled.
+    pub fn settle(&mut self, pair: &TradingPair, execution: &TradeExecution) {
+        let (buy_order, sell_order) = match execution.take_side {
+            Side::Bid => (execution.taker_order_id, execution.maker_order_id),
+            Side::Ask => (execution.maker_order_id, execution.taker_order_id),
+        };
+        let cost = execution.qty * execution.price;
+        if let Some(buyer) = self.spend(&buy_order, cost) {
+            self.balance_mut(buyer, pair.base()).available += execution.qty;
+        }
+        if let Some(seller) = self.spend(&sell_order, execution.qty) {
+            self.balance_mut(seller, pair.quote()).available += cost;
+        }
+    }
+
+    /// Checks that the balances of every asset add up to its net deposits, that no balance is negative and that the
+    /// reserved balances are those of the open orders' reservations.
+    pub fn check_invariants(&self) -> Result<()> {
+        let mut totals: HashMap<&str, Decimal> = HashMap::new();
+        let mut reserved: HashMap<(AccountId, &str), Decimal> = HashMap::new();
+        for (order_id, reservation) in &self.reservations {
+            if reservation.amount < Decimal::ZERO {
+                return Err(OrderBookError::BalancesNotConserved(format!(
+                    "order {} has a negative reservation",
+                    order_id
+                )));
+            }
+            *reserved
+                .entry((reservation.account, reservation.asset()))
+                .or_default() += reservation.amount;
+        }
+        for (account, balances) in &self.balances {
+            for (asset, balance) in balances {
+                if balance.available < Decimal::ZERO || balance.reserved < Decimal::ZERO {
+                    return Err(OrderBookError::BalancesNotConserved(format!(
+                        "account {} has a negative {} balance",
+                        account, asset
+                    )));
+                }
+                let held = reserved.remove(&(*account, asset.as_str()));
+                if held.unwrap_or_default() != balance.reserved {
+                    return Err(OrderBookError::BalancesNotConserved(format!(
+                        "account {} has {} {} reserved for its orders",
+                        account, balance.reserved, asset
+                    )));
+                }
+                *totals.entry(asset).or_default() += balance.total();
+            }
+        }
+        if let Some(((account, asset), _)) = reserved.into_iter().next() {
+            return Err(OrderBookError::BalancesNotConserved(format!(
+                "account {} has orders reserving {} it does not hold",
+                account, asset
+            )));
+        }
+        for (asset, supply) in &self.supply {
+            if totals.remove(asset.as_str()).unwrap_or_default() != *supply {
+                return Err(OrderBookError::BalancesNotConserved(format!(
+                    "balances of {} do not add up to its deposits",
+                    asset
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Takes the amount out of the order's reservation and its account's reserved balance, returning the account
+    fn spend(&mut self, order_id: &OrderId, amount: Decimal) -> Option<AccountId> {
+        let reservation = self.reservations.get_mut(order_id)?;
+        reservation.amount -= amount;
+        let (account, asset) = (reservation.account, reservation.asset().to_string());
+        self.balance_mut(account, &asset).reserved -= amount;
+        Some(account)
+    }
+
+    fn balance_mut(&mut self, account: AccountId, asset: &str) -> &mut Balance {
+        self.balances
+            .entry(account)
+            .or_default()
+            .entry(asset.to_string())
+            .or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OrderRequest, OrderType, TradeOrder};
+    use std::time::UNIX_EPOCH;
+
+    fn pair() -> TradingPair {
+        TradingPair::new("BTC".to_string(), "USD".to_string())
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw() {
+        let mut accounts = Accounts::new();
+        accounts.deposit(1, "USD", 100.into()).unwrap();
+        assert_eq!(
+            accounts.deposit(1, "USD", 0.into()).unwrap_err(),
+            OrderBookError::InvalidQuantity(0.into())
+        );
+        accounts.withdraw(1, "USD", 30.into()).unwrap();
+        assert_eq!(
+            accounts.withdraw(1, "USD", 80.into()).unwrap_err(),
+            OrderBookError::InsufficientFunds {
+                account: 1,
+                asset: "USD".to_string()
+            }
+        );
+        assert_eq!(accounts.balance(1, "USD").available, 70.into());
+        assert_eq!(accounts.balance(2, "USD"), Balance::default());
+        assert!(accounts.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn test_reserve_settle_and_release() {
+        let mut accounts = Accounts::new();
+        accounts.deposit(1, "USD", 1000.into()).unwrap();
+        accounts.deposit(2, "BTC", 10.into()).unwrap();
+
+        let bid = TradeOrder::from(OrderRequest::new(Side::Bid, 5, OrderType::limit(100)));
+        let ask = TradeOrder::from(OrderRequest::new(Side::Ask, 3, OrderType::limit(90)));
+        assert_eq!(
+            accounts.reserve(bid.id, 1, &pair(), Side::Bid, 1200.into()),
+            Err(RejectReason::InsufficientFunds)
+        );
+        accounts
+            .reserve(bid.id, 1, &pair(), Side::Bid, 500.into())
+            .unwrap();
+        assert_eq!(
+            accounts.reserve(bid.id, 1, &pair(), Side::Bid, 100.into()),
+            Err(RejectReason::DuplicateOrderId)
+        );
+        accounts
+            .reserve(ask.id, 2, &pair(), Side::Ask, 3.into())
+            .unwrap();
+        assert_eq!(
+            accounts.balance(1, "USD"),
+            Balance {
+                available: 500.into(),
+                reserved: 500.into()
+            }
+        );
+
+        // The ask takes 3 from the bid at 100
+        let execution =
+            TradeExecution::new(3.into(), 100.into(), &ask, &bid, Side::Ask, UNIX_EPOCH);
+        accounts.settle(&pair(), &execution);
+        assert_eq!(accounts.balance(1, "BTC").available, 3.into());
+        assert_eq!(accounts.balance(1, "USD").reserved, 200.into());
+        assert_eq!(accounts.balance(2, "BTC").total(), 7.into());
+        assert_eq!(accounts.balance(2, "USD").available, 300.into());
+        assert!(accounts.check_invariants().is_ok());
+
+        accounts.close(&ask.id);
+        accounts.release(&bid.id, 100.into());
+        assert_eq!(accounts.reserved(&bid.id), Some(100.into()));
+        assert_eq!(accounts.balance(1, "USD").available, 600.into());
+        assert_eq!(accounts.orders_of(&pair()), vec![bid.id]);
+        assert!(accounts.check_invariants().is_ok());
+
+        // Funds created out of nothing are caught
+        accounts.balance_mut(2, "BTC").available += Decimal::ONE;
+        assert!(matches!(
+            accounts.check_invariants(),
+            Err(OrderBookError::BalancesNotConserved(_))
+        ));
+    }
+}
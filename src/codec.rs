@@ -0,0 +1,321 @@
+// Encoding shared by the journal, order book snapshots and the serde support.
+// Values are written little endian, decimals as their 16 byte `Decimal::serialize` form, timestamps as nanoseconds
+// since the unix epoch and optional values behind a 0 (none) or 1 (some) tag.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+use crate::orderbook::Timestamp;
+use crate::{OrderType, PostOnly, SelfTradePrevention, Side};
+
+pub fn put_u8(buf: &mut Vec<u8>, value: u8) {
+    buf.push(value);
+}
+
+pub fn put_bool(buf: &mut Vec<u8>, value: bool) {
+    buf.push(value as u8);
+}
+
+pub fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn put_decimal(buf: &mut Vec<u8>, value: Decimal) {
+    buf.extend_from_slice(&value.serialize());
+}
+
+pub fn put_tagged(buf: &mut Vec<u8>, tag: u8, value: Decimal) {
+    buf.push(tag);
+    put_decimal(buf, value);
+}
+
+pub fn put_opt_decimal(buf: &mut Vec<u8>, value: Option<Decimal>) {
+    match value {
+        None => buf.push(0),
+        Some(value) => put_tagged(buf, 1, value),
+    }
+}
+
+pub fn put_opt_u64(buf: &mut Vec<u8>, value: Option<u64>) {
+    match value {
+        None => buf.push(0),
+        Some(value) => {
+            buf.push(1);
+            put_u64(buf, value);
+        }
+    }
+}
+
+pub fn put_timestamp(buf: &mut Vec<u8>, ts: Timestamp) {
+    put_u64(buf, to_nanos(ts));
+}
+
+pub fn put_opt_timestamp(buf: &mut Vec<u8>, ts: Option<Timestamp>) {
+    put_opt_u64(buf, ts.map(to_nanos));
+}
+
+pub fn put_uuid(buf: &mut Vec<u8>, id: Uuid) {
+    buf.extend_from_slice(id.as_bytes());
+}
+
+pub fn put_str(buf: &mut Vec<u8>, value: &str) {
+    put_u32(buf, value.len() as u32);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+pub fn put_side(buf: &mut Vec<u8>, side: Side) {
+    buf.push(match side {
+        Side::Ask => 0,
+        Side::Bid => 1,
+    });
+}
+
+pub fn put_order_type(buf: &mut Vec<u8>, order_type: OrderType) {
+    match order_type {
+        OrderType::Market => buf.push(0),
+        OrderType::Limit(price) => put_tagged(buf, 1, price),
+        OrderType::IOC(price) => put_tagged(buf, 2, price),
+        OrderType::FOK(price) => put_tagged(buf, 3, price),
+        OrderType::SystemLevel(price) => put_tagged(buf, 4, price),
+        OrderType::Stop(trigger) => put_tagged(buf, 5, trigger),
+        OrderType::StopLimit(trigger, limit) => {
+            put_tagged(buf, 6, trigger);
+            put_decimal(buf, limit);
+        }
+    }
+}
+
+pub fn put_post_only(buf: &mut Vec<u8>, post_only: Option<PostOnly>) {
+    match post_only {
+        None => buf.push(0),
+        Some(PostOnly::Reject) => buf.push(1),
+        Some(PostOnly::Reprice(tick)) => put_tagged(buf, 2, tick),
+    }
+}
+
+pub fn put_self_trade_prevention(buf: &mut Vec<u8>, mode: SelfTradePrevention) {
+    buf.push(match mode {
+        SelfTradePrevention::CancelNewest => 0,
+        SelfTradePrevention::CancelOldest => 1,
+        SelfTradePrevention::CancelBoth => 2,
+        SelfTradePrevention::DecrementAndCancel => 3,
+    });
+}
+
+pub fn to_nanos(ts: Timestamp) -> u64 {
+    ts.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+pub fn from_nanos(nanos: u64) -> Timestamp {
+    UNIX_EPOCH + Duration::from_nanos(nanos)
+}
+
+/// Serde encoding of timestamps as nanoseconds since the unix epoch, the same as the journal and snapshots use.
+/// Use with `#[serde(with = "crate::codec::nanos")]`.
+#[cfg(feature = "serde")]
+pub mod nanos {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::orderbook::Timestamp;
+
+    pub fn serialize<S: Serializer>(ts: &Timestamp, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(super::to_nanos(*ts))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Timestamp, D::Error> {
+        u64::deserialize(deserializer).map(super::from_nanos)
+    }
+}
+
+/// Cursor over encoded bytes, every read returns None once the bytes run out or hold an unknown tag.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, rest) = self.bytes.split_first_chunk::<N>()?;
+        self.bytes = rest;
+        Some(*head)
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        self.take::<1>().map(|[byte]| byte)
+    }
+
+    pub fn bool(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    pub fn decimal(&mut self) -> Option<Decimal> {
+        self.take().map(Decimal::deserialize)
+    }
+
+    pub fn opt_decimal(&mut self) -> Option<Option<Decimal>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => self.decimal().map(Some),
+            _ => None,
+        }
+    }
+
+    pub fn opt_u64(&mut self) -> Option<Option<u64>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => self.u64().map(Some),
+            _ => None,
+        }
+    }
+
+    pub fn timestamp(&mut self) -> Option<Timestamp> {
+        self.u64().map(from_nanos)
+    }
+
+    pub fn opt_timestamp(&mut self) -> Option<Option<Timestamp>> {
+        self.opt_u64().map(|nanos| nanos.map(from_nanos))
+    }
+
+    pub fn uuid(&mut self) -> Option<Uuid> {
+        self.take().map(Uuid::from_bytes)
+    }
+
+    pub fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        let bytes = self.bytes.get(..len)?;
+        self.bytes = &self.bytes[len..];
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+
+    pub fn side(&mut self) -> Option<Side> {
+        match self.u8()? {
+            0 => Some(Side::Ask),
+            1 => Some(Side::Bid),
+            _ => None,
+        }
+    }
+
+    pub fn order_type(&mut self) -> Option<OrderType> {
+        let order_type = match self.u8()? {
+            0 => OrderType::Market,
+            1 => OrderType::Limit(self.decimal()?),
+            2 => OrderType::IOC(self.decimal()?),
+            3 => OrderType::FOK(self.decimal()?),
+            4 => OrderType::SystemLevel(self.decimal()?),
+            5 => OrderType::Stop(self.decimal()?),
+            6 => OrderType::StopLimit(self.decimal()?, self.decimal()?),
+            _ => return None,
+        };
+        Some(order_type)
+    }
+
+    // The outer None is a decoding failure, the inner one an order that is not post only
+    pub fn post_only(&mut self) -> Option<Option<PostOnly>> {
+        let post_only = match self.u8()? {
+            0 => None,
+            1 => Some(PostOnly::Reject),
+            2 => Some(PostOnly::Reprice(self.decimal()?)),
+            _ => return None,
+        };
+        Some(post_only)
+    }
+
+    pub fn self_trade_prevention(&mut self) -> Option<SelfTradePrevention> {
+        match self.u8()? {
+            0 => Some(SelfTradePrevention::CancelNewest),
+            1 => Some(SelfTradePrevention::CancelOldest),
+            2 => Some(SelfTradePrevention::CancelBoth),
+            3 => Some(SelfTradePrevention::DecrementAndCancel),
+            _ => None,
+        }
+    }
+}
+
+// CRC-32 (IEEE) lookup table
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        CRC_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_reader_round_trip() {
+        let ts = from_nanos(1_700_000_000_123_456_789);
+        let mut buf = Vec::new();
+        put_order_type(&mut buf, OrderType::stop_limit(105, Decimal::new(1065, 1)));
+        put_side(&mut buf, Side::Bid);
+        put_opt_timestamp(&mut buf, Some(ts));
+        put_opt_decimal(&mut buf, None);
+        put_str(&mut buf, "BTC");
+        put_bool(&mut buf, true);
+
+        let mut reader = Reader::new(&buf);
+        assert_eq!(
+            reader.order_type(),
+            Some(OrderType::stop_limit(105, Decimal::new(1065, 1)))
+        );
+        assert_eq!(reader.side(), Some(Side::Bid));
+        assert_eq!(reader.opt_timestamp(), Some(Some(ts)));
+        assert_eq!(reader.opt_decimal(), Some(None));
+        assert_eq!(reader.string().as_deref(), Some("BTC"));
+        assert_eq!(reader.bool(), Some(true));
+        assert!(reader.is_empty());
+        assert_eq!(reader.u8(), None);
+    }
+}
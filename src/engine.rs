@@ -1,7 +1,18 @@
+use std::path::Path;
+
+use rust_decimal::Decimal;
+use tracing::warn;
 use uuid::Uuid;
 
+use crate::accounts::{Accounts, Balance};
+use crate::errors::{OrderBookError, Result};
+use crate::journal::{Command, Journal};
+use crate::notifications::{EventSink, Notification};
+use crate::orderbook::Timestamp;
 use crate::{
-    OrderBook, OrderBookState, OrderRequest, OrderResult, Price, Quantity, Side, TradeExecution,
+    AccountId, Clock, InstrumentSpec, OrderBook, OrderBookSnapshot, OrderBookState, OrderRequest,
+    OrderResult, OrderType, Price, PriceBands, PriceType, Quantity, QuantityType, RejectReason,
+    ReplayClock, Session, SessionPhase, Side, SystemClock, TradeExecution,
 };
 
 use std::{collections::HashMap, fmt::Display};
@@ -19,6 +30,14 @@ impl TradingPair {
     pub fn new(base: String, quote: String) -> TradingPair {
         TradingPair { base, quote }
     }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    pub fn quote(&self) -> &str {
+        &self.quote
+    }
 }
 impl Display for TradingPair {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -26,92 +45,427 @@ impl Display for TradingPair {
     }
 }
 
-pub struct MatchingEngine {
-    orderbooks: HashMap<TradingPair, OrderBook>,
+// A market is an order book, the trading rules its orders are checked against and its trading session
+#[derive(Debug)]
+struct Market<C: Clock, P: PriceType> {
+    book: OrderBook<C, P>,
+    spec: InstrumentSpec,
+    session: Session,
+}
+
+impl<C: Clock, P: PriceType> Market<C, P> {
+    // Moves the session to the phase, starting the book's call auction on entering an auction and uncrossing it on
+    // moving to continuous trading or the close. A halt suspends a running auction without uncrossing it.
+    fn transition(&mut self, phase: SessionPhase) -> Result<Vec<TradeExecution>> {
+        let from = self.session.phase();
+        let previous = self
+            .session
+            .transition(phase)
+            .ok_or(OrderBookError::InvalidSessionTransition { from, to: phase })?;
+        self.book
+            .emit(Notification::SessionChanged { previous, phase });
+        let executions = match phase {
+            SessionPhase::Auction if !self.book.in_auction() => {
+                self.book.start_auction();
+                Vec::new()
+            }
+            SessionPhase::Continuous | SessionPhase::Closed if self.book.in_auction() => {
+                self.book.uncross()
+            }
+            _ => Vec::new(),
+        };
+        Ok(executions)
+    }
+
+    // Follows the book into the volatility auction started by a circuit breaker during continuous trading, scheduling
+    // the return to continuous trading if the bands set the auction's duration
+    fn follow_volatility_auction(&mut self) {
+        if self.session.phase() != SessionPhase::Continuous || !self.book.in_auction() {
+            return;
+        }
+        if let Some(previous) = self.session.transition(SessionPhase::Auction) {
+            self.book.emit(Notification::SessionChanged {
+                previous,
+                phase: SessionPhase::Auction,
+            });
+        }
+        if let Some(duration) = self.book.price_bands().volatility_auction {
+            let end = self.book.clock().now() + duration;
+            self.session.schedule(end, SessionPhase::Continuous);
+        }
+    }
+}
+
+/// MatchingEngine runs an order book per trading pair.
+///
+/// Orders come in and results go out with `Decimal` prices and quantities, the price type `P` is only how the books
+/// hold them. With an integer price type each market stores prices as ticks of its `InstrumentSpec::price_scale` and
+/// quantities as lots of its `InstrumentSpec::qty_scale`.
+///
+/// Each market has a trading session whose phase decides which orders and cancels it accepts, markets start in
+/// continuous trading. Phases change when `set_session_phase` is called, or when a transition scheduled with
+/// `schedule_session_phase` falls due on the engine's clock.
+///
+/// Markets whose `InstrumentSpec` requires funds only take orders from accounts that can pay for them. Placing an order
+/// reserves its funds, trades move them from one account to the other and cancels release what is left, see
+/// `Accounts`.
+///
+/// With a `Journal` attached every command is written ahead to it before it is applied, and `recover` rebuilds the
+/// engine from the journal after a crash.
+pub struct MatchingEngine<C: Clock + Clone = SystemClock, P: PriceType = Price> {
+    markets: HashMap<TradingPair, Market<ReplayClock<C>, P>>,
+    // The books read the replayed time while the engine recovers, and `C` afterwards
+    clock: ReplayClock<C>,
+    journal: Option<Journal>,
+    accounts: Accounts,
 }
 
 impl MatchingEngine {
     pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+}
+
+impl<C: Clock + Clone> MatchingEngine<C> {
+    /// Creates an engine whose order books all share `clock`.
+    pub fn with_clock(clock: C) -> Self {
+        Self::with_price_type(clock)
+    }
+}
+
+impl<C: Clock + Clone, P: PriceType> MatchingEngine<C, P> {
+    /// Creates an engine whose order books share `clock` and key their price levels by `P`.
+    pub fn with_price_type(clock: C) -> Self {
         Self {
-            orderbooks: HashMap::new(),
+            markets: HashMap::new(),
+            clock: ReplayClock::new(clock),
+            journal: None,
+            accounts: Accounts::new(),
+        }
+    }
+
+    /// Journals every following command to `journal` before applying it.
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Rebuilds an engine by replaying the journal at `path` into a fresh engine, then keeps journaling to it.
+    ///
+    /// Commands replay with the ids they were placed with, so the books end up with the same orders in the same queue
+    /// positions. Each command replays at the time it was journaled, so orders, fills and expiries carry the times of
+    /// the original run, and `clock` takes over once the journal is replayed. Event sinks are not set until the engine
+    /// is returned so replayed events are not published again.
+    pub fn recover(clock: C, path: impl AsRef<Path>) -> Result<Self> {
+        let (journal, entries) = Journal::open(path)?;
+        let mut engine = Self::with_price_type(clock);
+        for entry in entries {
+            engine.clock.replay_at(entry.timestamp);
+            // Commands that failed when they were first applied fail the same way again
+            let _ = engine.apply(entry.command);
         }
+        engine.clock.go_live();
+        Ok(engine.with_journal(journal))
+    }
+
+    pub fn clock(&self) -> &C {
+        self.clock.live()
     }
 
-    pub fn add_market(&mut self, pair: TradingPair) -> Result<(), String> {
-        if self.orderbooks.contains_key(&pair) {
-            Err(format!("Market for {} already exists", pair))
+    /// Adds a market whose orders are checked against `spec` before they reach the book.
+    pub fn add_market(&mut self, pair: TradingPair, spec: InstrumentSpec) -> Result<()> {
+        self.write_ahead(|| Command::AddMarket {
+            pair: pair.clone(),
+            spec,
+        })?;
+        if self.markets.contains_key(&pair) {
+            Err(OrderBookError::MarketExists(pair))
         } else {
-            self.orderbooks.insert(pair.clone(), OrderBook::default());
+            let mut book =
+                OrderBook::with_scale(self.clock.clone(), spec.price_scale(), spec.qty_scale())
+                    .with_matching_policy(spec.matching.policy(spec.lot_size));
+            if spec.require_funds {
+                book.track_departed();
+            }
+            let session = Session::default();
+            self.markets.insert(
+                pair,
+                Market {
+                    book,
+                    spec,
+                    session,
+                },
+            );
             Ok(())
         }
     }
 
-    pub fn remove_market(&mut self, pair: &TradingPair) -> Result<(), String> {
-        if self.orderbooks.remove(pair).is_some() {
-            Ok(())
-        } else {
-            Err(format!("Market for {} does not exist", pair))
+    pub fn remove_market(&mut self, pair: &TradingPair) -> Result<()> {
+        self.write_ahead(|| Command::RemoveMarket { pair: pair.clone() })?;
+        self.markets
+            .remove(pair)
+            .ok_or_else(|| OrderBookError::UnknownMarket(pair.clone()))?;
+        // The orders went with the book
+        for order_id in self.accounts.orders_of(pair) {
+            self.accounts.close(&order_id);
         }
+        Ok(())
     }
 
-    pub fn place_order(
+    pub fn deposit(&mut self, account: AccountId, asset: &str, amount: Decimal) -> Result<()> {
+        self.write_ahead(|| Command::Deposit {
+            account,
+            asset: asset.to_string(),
+            amount,
+        })?;
+        self.accounts.deposit(account, asset, amount)
+    }
+
+    pub fn withdraw(&mut self, account: AccountId, asset: &str, amount: Decimal) -> Result<()> {
+        self.write_ahead(|| Command::Withdraw {
+            account,
+            asset: asset.to_string(),
+            amount,
+        })?;
+        self.accounts.withdraw(account, asset, amount)
+    }
+
+    pub fn get_balance(&self, account: AccountId, asset: &str) -> Balance {
+        self.accounts.balance(account, asset)
+    }
+
+    pub fn accounts(&self) -> &Accounts {
+        &self.accounts
+    }
+
+    /// Checks that the balances of the accounts are conserved, see `Accounts::check_invariants`.
+    pub fn check_balances(&self) -> Result<()> {
+        self.accounts.check_invariants()
+    }
+
+    /// Sends the events of the market's order book to `sink`.
+    pub fn set_event_sink(
         &mut self,
         pair: &TradingPair,
-        order: OrderRequest,
-    ) -> Result<(OrderResult, Vec<TradeExecution>), String> {
-        self.orderbooks
+        sink: impl EventSink + 'static,
+    ) -> Result<()> {
+        self.get_market_mut(pair)?.book.set_event_sink(sink);
+        Ok(())
+    }
+
+    fn get_market(&self, pair: &TradingPair) -> Result<&Market<ReplayClock<C>, P>> {
+        self.markets
+            .get(pair)
+            .ok_or_else(|| OrderBookError::UnknownMarket(pair.clone()))
+    }
+
+    fn get_market_mut(&mut self, pair: &TradingPair) -> Result<&mut Market<ReplayClock<C>, P>> {
+        self.markets
             .get_mut(pair)
-            .map(|ob| ob.add_order(order))
-            .ok_or_else(|| format!("Market for {} does not exist", pair))
+            .ok_or_else(|| OrderBookError::UnknownMarket(pair.clone()))
+    }
+
+    fn get_book(&self, pair: &TradingPair) -> Result<&OrderBook<ReplayClock<C>, P>> {
+        self.get_market(pair).map(|market| &market.book)
+    }
+
+    pub fn get_instrument_spec(&self, pair: &TradingPair) -> Result<InstrumentSpec> {
+        self.get_market(pair).map(|market| market.spec)
+    }
+
+    /// Protects the market's book with the price bands, see `PriceBands`.
+    pub fn set_price_bands(&mut self, pair: &TradingPair, bands: PriceBands) -> Result<()> {
+        self.write_ahead(|| Command::SetPriceBands {
+            pair: pair.clone(),
+            bands,
+        })?;
+        self.get_market_mut(pair)?.book.set_price_bands(bands);
+        Ok(())
+    }
+
+    pub fn get_session_phase(&self, pair: &TradingPair) -> Result<SessionPhase> {
+        self.get_market(pair).map(|market| market.session.phase())
     }
 
-    pub fn cancel_order(
+    /// Moves the market's session to `phase`, emitting a `SessionChanged` notification.
+    /// Returns the trades of the call auction uncrossed by leaving an auction for continuous trading or the close.
+    pub fn set_session_phase(
         &mut self,
         pair: &TradingPair,
-        order_id: Uuid,
-    ) -> Result<Option<OrderResult>, String> {
-        self.orderbooks
-            .get_mut(pair)
-            .map(|ob| ob.delete_order(order_id))
-            .ok_or_else(|| format!("Market for {} does not exist", pair))
+        phase: SessionPhase,
+    ) -> Result<Vec<TradeExecution>> {
+        self.write_ahead(|| Command::SetSessionPhase {
+            pair: pair.clone(),
+            phase,
+        })?;
+        let executions = self.get_market_mut(pair)?.transition(phase)?;
+        self.settle(pair, &executions, None);
+        Ok(executions)
     }
 
-    pub fn get_order_book_state(&self, pair: &TradingPair) -> Result<OrderBookState, String> {
-        self.orderbooks
-            .get(pair)
-            .map(|ob| ob.get_order_book_state())
-            .ok_or_else(|| format!("Market for {} does not exist", pair))
+    /// Schedules the market's session to move to `phase` once the engine's clock reaches `at`.
+    ///
+    /// Due transitions are applied by `advance_sessions`, and before any order or cancel of the market is processed.
+    /// The schedule itself is not journaled, only the transitions once they are applied, so it must be set again on a
+    /// recovered engine.
+    pub fn schedule_session_phase(
+        &mut self,
+        pair: &TradingPair,
+        at: Timestamp,
+        phase: SessionPhase,
+    ) -> Result<()> {
+        self.get_market_mut(pair)?.session.schedule(at, phase);
+        Ok(())
     }
 
-    pub fn get_best_bid_ask(
-        &self,
+    /// Applies the scheduled session transitions of every market that are due on the engine's clock.
+    /// Returns the trades of the call auctions they uncrossed, by market.
+    pub fn advance_sessions(&mut self) -> Result<Vec<(TradingPair, Vec<TradeExecution>)>> {
+        let mut uncrossed = Vec::new();
+        for pair in self.get_markets() {
+            let executions = self.advance_session(&pair)?;
+            if !executions.is_empty() {
+                uncrossed.push((pair, executions));
+            }
+        }
+        Ok(uncrossed)
+    }
+
+    // Applies the market's due transitions in the order they were scheduled, skipping any its phase does not allow
+    fn advance_session(&mut self, pair: &TradingPair) -> Result<Vec<TradeExecution>> {
+        let now = self.clock.now();
+        let mut executions = Vec::new();
+        while let Some(phase) = self.get_market_mut(pair)?.session.next_due(now) {
+            match self.set_session_phase(pair, phase) {
+                Ok(mut uncrossed) => executions.append(&mut uncrossed),
+                Err(err @ OrderBookError::InvalidSessionTransition { .. }) => {
+                    warn!("Scheduled transition of {} skipped: {}", pair, err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(executions)
+    }
+
+    /// Places the order in the market, once it has passed the checks of the session phase and the instrument spec.
+    /// The trades of an auction uncrossed by a scheduled transition that was due are not returned, they are only
+    /// reported to the market's event sink.
+    pub fn place_order(
+        &mut self,
         pair: &TradingPair,
-    ) -> Result<(Option<Price>, Option<Price>), String> {
-        self.orderbooks
-            .get(pair)
-            .map(|ob| ob.best_prices())
-            .ok_or_else(|| format!("Market for {} does not exist", pair))
+        order: OrderRequest,
+    ) -> Result<(OrderResult, Vec<TradeExecution>)> {
+        self.advance_session(pair)?;
+        self.write_ahead(|| Command::PlaceOrder {
+            pair: pair.clone(),
+            order,
+        })?;
+        // Borrowed apart from the accounts, which fund the order
+        let market = self
+            .markets
+            .get_mut(pair)
+            .ok_or_else(|| OrderBookError::UnknownMarket(pair.clone()))?;
+        if let Err(reason) = market.session.phase().check_order(&order.order_type) {
+            return Ok((market.book.reject(order, reason), Vec::new()));
+        }
+        let reference = market.book.last_trade_price();
+        if let Err(reason) = market.spec.validate(&order, reference) {
+            return Ok((market.book.reject(order, reason), Vec::new()));
+        }
+        // Prices must convert to the book's price type
+        let scale = market.spec.price_scale();
+        let prices = [order.price(), order.order_type.trigger_price()];
+        if prices
+            .into_iter()
+            .flatten()
+            .any(|price| P::from_price(price, scale).is_none())
+        {
+            let result = market.book.reject(order, RejectReason::PriceNotOnTick);
+            return Ok((result, Vec::new()));
+        }
+        // And quantities to its quantity type
+        let qty_scale = market.spec.qty_scale();
+        if [Some(order.qty), order.peak_qty]
+            .into_iter()
+            .flatten()
+            .any(|qty| P::Qty::from_qty(qty, qty_scale).is_none())
+        {
+            let result = market.book.reject(order, RejectReason::QuantityNotOnLot);
+            return Ok((result, Vec::new()));
+        }
+        if market.spec.require_funds {
+            // Each order holds its own reservation, so not even system level orders can share an id
+            if market.book.contains_order(&order.id()) {
+                return Err(OrderBookError::DuplicateOrderId(order.id()));
+            }
+            let reserved = match order.account {
+                Some(account) => funds_required(&market.book, &order).and_then(|amount| {
+                    self.accounts
+                        .reserve(order.id(), account, pair, order.side, amount)
+                }),
+                None => Err(RejectReason::AccountRequired),
+            };
+            if let Err(reason) = reserved {
+                return Ok((market.book.reject(order, reason), Vec::new()));
+            }
+        }
+        let placed = market.book.add_order(order);
+        market.follow_volatility_auction();
+        match &placed {
+            Ok((_, executions)) => self.settle(pair, executions, Some(order.id())),
+            Err(_) => self.accounts.close(&order.id()),
+        }
+        placed
     }
 
-    pub fn get_spread(&self, pair: &TradingPair) -> Result<Option<Price>, String> {
-        self.orderbooks
-            .get(pair)
-            .map(|ob| ob.spread())
-            .ok_or_else(|| format!("Market for {} does not exist", pair))
+    pub fn cancel_order(&mut self, pair: &TradingPair, order_id: Uuid) -> Result<OrderResult> {
+        self.advance_session(pair)?;
+        self.write_ahead(|| Command::CancelOrder {
+            pair: pair.clone(),
+            order_id,
+        })?;
+        let market = self.get_market_mut(pair)?;
+        if !market.session.phase().accepts_cancels() {
+            return Err(OrderBookError::MarketClosed(pair.clone()));
+        }
+        let cancelled = market.book.delete_order(order_id);
+        self.settle(pair, &[], Some(order_id));
+        cancelled
     }
 
-    pub fn get_volume(&self, pair: &TradingPair) -> Result<Quantity, String> {
-        self.orderbooks
-            .get(pair)
-            .map(|ob| ob.get_total_volume())
-            .ok_or_else(|| format!("Market for {} does not exist", pair))
+    /// Removes the market's orders that have expired by the engine clock, releasing the funds they held.
+    pub fn expire_orders(&mut self, pair: &TradingPair) -> Result<Vec<OrderResult>> {
+        self.advance_session(pair)?;
+        self.write_ahead(|| Command::ExpireOrders { pair: pair.clone() })?;
+        let now = self.clock.now();
+        let expired = self.get_market_mut(pair)?.book.expire_orders(now);
+        self.settle(pair, &[], None);
+        Ok(expired)
     }
 
-    pub fn get_depth(&self, pair: &TradingPair) -> Result<(usize, usize), String> {
-        self.orderbooks
-            .get(pair)
-            .map(|ob| ob.get_depth())
-            .ok_or_else(|| format!("Market for {} does not exist", pair))
+    /// Returns a full snapshot of the market's order book, see `OrderBookSnapshot`.
+    pub fn snapshot(&self, pair: &TradingPair) -> Result<OrderBookSnapshot> {
+        self.get_book(pair).map(|ob| ob.snapshot())
+    }
+
+    pub fn get_order_book_state(&self, pair: &TradingPair) -> Result<OrderBookState> {
+        self.get_book(pair).map(|ob| ob.get_order_book_state())
+    }
+
+    pub fn get_best_bid_ask(&self, pair: &TradingPair) -> Result<(Option<Price>, Option<Price>)> {
+        self.get_book(pair).map(|ob| ob.best_prices())
+    }
+
+    pub fn get_spread(&self, pair: &TradingPair) -> Result<Option<Price>> {
+        self.get_book(pair).map(|ob| ob.spread())
+    }
+
+    pub fn get_volume(&self, pair: &TradingPair) -> Result<Quantity> {
+        self.get_book(pair).map(|ob| ob.get_total_volume())
+    }
+
+    pub fn get_depth(&self, pair: &TradingPair) -> Result<(usize, usize)> {
+        self.get_book(pair).map(|ob| ob.get_depth())
     }
 
     pub fn get_volume_at_price(
@@ -119,22 +473,123 @@ impl MatchingEngine {
         pair: &TradingPair,
         side: Side,
         price: Price,
-    ) -> Result<Quantity, String> {
-        self.orderbooks
-            .get(pair)
-            .map(|ob| {
-                ob.get_volume_at_price(&side, &price)
-                    .unwrap_or(Quantity::ZERO)
-            })
-            .ok_or_else(|| format!("Market for {} does not exist", pair))
+    ) -> Result<Quantity> {
+        self.get_book(pair).map(|ob| {
+            ob.get_volume_at_price(&side, &price)
+                .unwrap_or(Quantity::ZERO)
+        })
     }
 
     pub fn get_markets(&self) -> Vec<TradingPair> {
-        self.orderbooks.keys().cloned().collect()
+        self.markets.keys().cloned().collect()
     }
 
     pub fn market_exists(&self, pair: &TradingPair) -> bool {
-        self.orderbooks.contains_key(pair)
+        self.markets.contains_key(pair)
+    }
+
+    // Settles the trades of a market that requires funds between the accounts, then releases the funds the orders
+    // touched by the operation no longer need: all of them for orders that left the book, and for resting orders what
+    // is above the cost of their open quantity. Stop orders keep their funds until they trigger.
+    // Only the order placed or cancelled, the orders of the trades and those the book reports as departed are looked at.
+    fn settle(
+        &mut self,
+        pair: &TradingPair,
+        executions: &[TradeExecution],
+        order_id: Option<Uuid>,
+    ) {
+        let Some(market) = self.markets.get_mut(pair) else {
+            return;
+        };
+        if !market.spec.require_funds {
+            return;
+        }
+        for execution in executions {
+            self.accounts.settle(pair, execution);
+        }
+        let touched = executions
+            .iter()
+            .flat_map(|execution| [execution.taker_order_id, execution.maker_order_id])
+            .chain(order_id)
+            .chain(market.book.take_departed());
+        let book = &market.book;
+        for order_id in touched {
+            if book.stop_loc.contains_key(&order_id) {
+                continue;
+            }
+            let keep = match book.open_order(order_id) {
+                Some((Side::Bid, price, qty)) => qty * price,
+                Some((Side::Ask, _, qty)) => qty,
+                None => {
+                    self.accounts.close(&order_id);
+                    continue;
+                }
+            };
+            self.accounts.release(&order_id, keep);
+        }
+    }
+
+    // Appends the command to the journal, if there is one, before it is applied
+    fn write_ahead(&mut self, command: impl FnOnce() -> Command) -> Result<()> {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.append(&command(), self.clock.now())?;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, command: Command) -> Result<()> {
+        match command {
+            Command::AddMarket { pair, spec } => self.add_market(pair, spec),
+            Command::RemoveMarket { pair } => self.remove_market(&pair),
+            Command::PlaceOrder { pair, order } => self.place_order(&pair, order).map(|_| ()),
+            Command::CancelOrder { pair, order_id } => {
+                self.cancel_order(&pair, order_id).map(|_| ())
+            }
+            Command::SetSessionPhase { pair, phase } => {
+                self.set_session_phase(&pair, phase).map(|_| ())
+            }
+            Command::SetPriceBands { pair, bands } => self.set_price_bands(&pair, bands),
+            Command::Deposit {
+                account,
+                asset,
+                amount,
+            } => self.deposit(account, &asset, amount),
+            Command::Withdraw {
+                account,
+                asset,
+                amount,
+            } => self.withdraw(account, &asset, amount),
+            Command::ExpireOrders { pair } => self.expire_orders(&pair).map(|_| ()),
+        }
+    }
+}
+
+// Funds an order needs to be placed: the cost of its quantity at its limit price for a bid, its quantity for an ask.
+// A market bid needs what taking its quantity from the asks would cost, the most it can trade before its remainder is
+// cancelled.
+fn funds_required<C: Clock, P: PriceType>(
+    book: &OrderBook<C, P>,
+    order: &OrderRequest,
+) -> Result<Decimal, RejectReason> {
+    match (order.side, &order.order_type) {
+        (Side::Ask, _) => Ok(order.qty),
+        (Side::Bid, OrderType::Market) => {
+            let mut left = order.qty;
+            let mut cost = Decimal::ZERO;
+            for (price, qty) in book.asks.iter_open_levels() {
+                let take = left.min(qty);
+                cost += take * price;
+                left -= take;
+                if left == Decimal::ZERO {
+                    break;
+                }
+            }
+            Ok(cost)
+        }
+        (Side::Bid, order_type) => order_type
+            .price()
+            .map(|price| order.qty * price)
+            .ok_or(RejectReason::LimitPriceRequired),
     }
 }
 
@@ -143,3 +598,520 @@ impl Default for MatchingEngine {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ManualClock, MatchingAlgorithm, NotificationKind, OrderStatus, OrderType, Quantity, Ticks,
+        TimeInForce,
+    };
+    use rust_decimal::Decimal;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn pair() -> TradingPair {
+        TradingPair::new("BTC".to_string(), "USD".to_string())
+    }
+
+    #[test]
+    fn test_engine_errors() {
+        let mut engine = MatchingEngine::new();
+        let order = OrderRequest::new(Side::Bid, 10, OrderType::limit(100));
+        assert_eq!(
+            engine.place_order(&pair(), order).unwrap_err(),
+            OrderBookError::UnknownMarket(pair())
+        );
+
+        engine
+            .add_market(pair(), InstrumentSpec::default())
+            .unwrap();
+        assert_eq!(
+            engine
+                .add_market(pair(), InstrumentSpec::default())
+                .unwrap_err(),
+            OrderBookError::MarketExists(pair())
+        );
+
+        let (result, _) = engine.place_order(&pair(), order).unwrap();
+        assert!(engine.cancel_order(&pair(), result.get_id()).is_ok());
+        assert_eq!(
+            engine.cancel_order(&pair(), result.get_id()).unwrap_err(),
+            OrderBookError::OrderNotFound(result.get_id())
+        );
+    }
+
+    #[test]
+    fn test_place_order_checks_instrument_spec() {
+        let mut engine = MatchingEngine::new();
+        let spec = InstrumentSpec::new(1, 10).min_qty(10);
+        engine.add_market(pair(), spec).unwrap();
+        assert_eq!(engine.get_instrument_spec(&pair()), Ok(spec));
+
+        let order = OrderRequest::new(Side::Bid, 15, OrderType::limit(100));
+        let (result, executions) = engine.place_order(&pair(), order).unwrap();
+        assert_eq!(
+            result.status,
+            OrderStatus::Rejected(RejectReason::QuantityNotOnLot)
+        );
+        assert!(executions.is_empty());
+        assert_eq!(engine.get_depth(&pair()), Ok((0, 0)));
+
+        let order = OrderRequest::new(Side::Bid, 20, OrderType::limit(100));
+        let (result, _) = engine.place_order(&pair(), order).unwrap();
+        assert_eq!(result.status, OrderStatus::Open);
+        assert_eq!(engine.get_depth(&pair()), Ok((0, 1)));
+    }
+
+    #[test]
+    fn test_place_order_with_ticks() {
+        let mut engine = MatchingEngine::<SystemClock, Ticks>::with_price_type(SystemClock);
+        engine
+            .add_market(pair(), InstrumentSpec::new(Decimal::new(5, 2), 1))
+            .unwrap();
+
+        let order = OrderRequest::new(Side::Bid, 10, OrderType::limit(Decimal::new(1003, 2)));
+        let (result, _) = engine.place_order(&pair(), order).unwrap();
+        assert_eq!(
+            result.status,
+            OrderStatus::Rejected(RejectReason::PriceNotOnTick)
+        );
+
+        let order = OrderRequest::new(Side::Bid, 10, OrderType::limit(Decimal::new(1005, 2)));
+        let (result, _) = engine.place_order(&pair(), order).unwrap();
+        assert_eq!(result.status, OrderStatus::Open);
+        assert_eq!(engine.get_depth(&pair()), Ok((0, 1)));
+
+        // Without a lot size the market's quantities are whole lots
+        let eth = TradingPair::new("ETH".to_string(), "USD".to_string());
+        let spec = InstrumentSpec {
+            lot_size: None,
+            ..InstrumentSpec::new(1, 1)
+        };
+        engine.add_market(eth.clone(), spec).unwrap();
+        let order = OrderRequest::new(Side::Bid, Decimal::new(15, 1), OrderType::limit(100));
+        let (result, _) = engine.place_order(&eth, order).unwrap();
+        assert_eq!(
+            result.status,
+            OrderStatus::Rejected(RejectReason::QuantityNotOnLot)
+        );
+    }
+
+    #[test]
+    fn test_engine_event_sink() {
+        let mut engine = MatchingEngine::new();
+        let (sender, events) = crossbeam_channel::unbounded();
+        assert!(engine.set_event_sink(&pair(), sender.clone()).is_err());
+        engine
+            .add_market(pair(), InstrumentSpec::new(1, 1))
+            .unwrap();
+        engine.set_event_sink(&pair(), sender).unwrap();
+
+        let order = OrderRequest::new(Side::Bid, 10, OrderType::limit(Decimal::new(1005, 1)));
+        let (result, _) = engine.place_order(&pair(), order).unwrap();
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [Notification::OrderRejected {
+                order_id: result.get_id(),
+                side: Side::Bid,
+                account: None,
+                reason: RejectReason::PriceNotOnTick,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_engine_recovers_from_journal() {
+        let path = std::env::temp_dir().join(format!("engine-{}.journal", Uuid::new_v4()));
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut engine =
+            MatchingEngine::with_clock(clock.clone()).with_journal(Journal::create(&path).unwrap());
+        engine
+            .add_market(pair(), InstrumentSpec::new(1, 1))
+            .unwrap();
+        let orders = [
+            OrderRequest::new(Side::Ask, 10, OrderType::limit(101)),
+            OrderRequest::new(Side::Ask, 20, OrderType::limit(102)).iceberg(5),
+            OrderRequest::new(Side::Bid, 10, OrderType::limit(99)).account(1),
+            OrderRequest::new(Side::Bid, 7, OrderType::limit(98)),
+            OrderRequest::new(Side::Bid, 3, OrderType::limit(97)).time_in_force(TimeInForce::DAY),
+            OrderRequest::new(Side::Bid, 15, OrderType::Market),
+            // Rejected, the quantity is not on a lot
+            OrderRequest::new(Side::Bid, Decimal::new(5, 1), OrderType::limit(98)),
+        ];
+        for order in orders {
+            clock.advance(Duration::from_secs(1));
+            engine.place_order(&pair(), order).unwrap();
+        }
+        engine.cancel_order(&pair(), orders[3].id()).unwrap();
+        assert!(engine.cancel_order(&pair(), orders[3].id()).is_err());
+        let snapshot = engine.snapshot(&pair()).unwrap();
+        drop(engine);
+
+        // Recovering a day later rebuilds the book as it was, with the timestamps and expiries of the original run
+        clock.advance(Duration::from_secs(24 * 60 * 60));
+        let mut recovered: MatchingEngine<ManualClock> =
+            MatchingEngine::recover(clock.clone(), &path).unwrap();
+        assert_eq!(recovered.snapshot(&pair()), Ok(snapshot));
+        assert_eq!(
+            recovered.get_order_book_state(&pair()),
+            Ok(OrderBookState {
+                asks: vec![(102.into(), 5.into())],
+                bids: vec![(99.into(), 10.into()), (97.into(), 3.into())],
+            })
+        );
+        assert_eq!(recovered.get_volume(&pair()), Ok(18.into()));
+        assert_eq!(recovered.clock().now(), clock.now());
+        // The resting orders kept their ids
+        let result = recovered.cancel_order(&pair(), orders[1].id()).unwrap();
+        assert_eq!(result.remaining_qty, 15.into());
+
+        // The recovered engine journals after the replayed commands
+        assert_eq!(Journal::read(&path).unwrap().len(), 11);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_session_phases_control_orders() {
+        let mut engine = MatchingEngine::new();
+        engine
+            .add_market(pair(), InstrumentSpec::default())
+            .unwrap();
+        assert_eq!(
+            engine.get_session_phase(&pair()),
+            Ok(SessionPhase::Continuous)
+        );
+        let (bid, _) = engine
+            .place_order(
+                &pair(),
+                OrderRequest::new(Side::Bid, 10, OrderType::limit(100)),
+            )
+            .unwrap();
+
+        engine
+            .set_session_phase(&pair(), SessionPhase::Halted)
+            .unwrap();
+        let order = OrderRequest::new(Side::Ask, 10, OrderType::limit(100));
+        let (result, _) = engine.place_order(&pair(), order).unwrap();
+        assert_eq!(
+            result.status,
+            OrderStatus::Rejected(RejectReason::MarketHalted)
+        );
+        assert_eq!(
+            engine.set_session_phase(&pair(), SessionPhase::PreOpen),
+            Err(OrderBookError::InvalidSessionTransition {
+                from: SessionPhase::Halted,
+                to: SessionPhase::PreOpen,
+            })
+        );
+
+        // Reopening through an auction uncrosses the orders it collected
+        engine
+            .set_session_phase(&pair(), SessionPhase::Auction)
+            .unwrap();
+        let (result, executions) = engine.place_order(&pair(), order).unwrap();
+        assert_eq!(result.status, OrderStatus::Open);
+        assert!(executions.is_empty());
+        let market = OrderRequest::new(Side::Ask, 10, OrderType::Market);
+        let (result, _) = engine.place_order(&pair(), market).unwrap();
+        assert_eq!(
+            result.status,
+            OrderStatus::Rejected(RejectReason::AuctionInProgress)
+        );
+        let executions = engine
+            .set_session_phase(&pair(), SessionPhase::Continuous)
+            .unwrap();
+        assert_eq!(executions.len(), 1);
+        assert_eq!(executions[0].qty, 10.into());
+
+        engine
+            .set_session_phase(&pair(), SessionPhase::Closed)
+            .unwrap();
+        assert_eq!(
+            engine.cancel_order(&pair(), bid.get_id()).unwrap_err(),
+            OrderBookError::MarketClosed(pair())
+        );
+    }
+
+    #[test]
+    fn test_scheduled_session_transitions() {
+        let open = UNIX_EPOCH + Duration::from_secs(34_200);
+        let clock = ManualClock::new(open - Duration::from_secs(600));
+        let mut engine = MatchingEngine::with_clock(clock.clone());
+        engine
+            .add_market(pair(), InstrumentSpec::default())
+            .unwrap();
+        let (sender, events) = crossbeam_channel::unbounded();
+        engine.set_event_sink(&pair(), sender).unwrap();
+        engine
+            .set_session_phase(&pair(), SessionPhase::Closed)
+            .unwrap();
+        engine
+            .set_session_phase(&pair(), SessionPhase::PreOpen)
+            .unwrap();
+        for (at, phase) in [
+            (open - Duration::from_secs(300), SessionPhase::Auction),
+            (open, SessionPhase::Continuous),
+            // Not allowed from continuous trading, skipped when it is due
+            (open, SessionPhase::PreOpen),
+        ] {
+            engine.schedule_session_phase(&pair(), at, phase).unwrap();
+        }
+
+        clock.set(open - Duration::from_secs(60));
+        let order = OrderRequest::new(Side::Bid, 5, OrderType::limit(100));
+        engine.place_order(&pair(), order).unwrap();
+        assert_eq!(engine.get_session_phase(&pair()), Ok(SessionPhase::Auction));
+        let order = OrderRequest::new(Side::Ask, 5, OrderType::limit(99));
+        engine.place_order(&pair(), order).unwrap();
+
+        clock.set(open);
+        let uncrossed = engine.advance_sessions().unwrap();
+        assert_eq!(uncrossed.len(), 1);
+        assert_eq!(uncrossed[0].1[0].price, 99.into());
+        assert_eq!(
+            engine.get_session_phase(&pair()),
+            Ok(SessionPhase::Continuous)
+        );
+
+        let transitions: Vec<_> = events
+            .try_iter()
+            .filter(|event| event.kind() == NotificationKind::SessionChanged)
+            .collect();
+        assert_eq!(transitions.len(), 4);
+        assert_eq!(
+            transitions[3],
+            Notification::SessionChanged {
+                previous: SessionPhase::Auction,
+                phase: SessionPhase::Continuous,
+            }
+        );
+    }
+
+    #[test]
+    fn test_circuit_breaker_halts_market() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(34_200));
+        let mut engine = MatchingEngine::with_clock(clock.clone());
+        engine
+            .add_market(pair(), InstrumentSpec::default())
+            .unwrap();
+        let bands = PriceBands::default()
+            .dynamic_band(Decimal::new(1, 1))
+            .volatility_auction(Duration::from_secs(300));
+        engine.set_price_bands(&pair(), bands).unwrap();
+        for order in [
+            OrderRequest::new(Side::Ask, 5, OrderType::limit(100)),
+            OrderRequest::new(Side::Bid, 1, OrderType::limit(100)),
+            OrderRequest::new(Side::Ask, 5, OrderType::limit(150)),
+        ] {
+            engine.place_order(&pair(), order).unwrap();
+        }
+
+        // The fat finger market order stops at the band instead of sweeping the book
+        let order = OrderRequest::new(Side::Bid, 10, OrderType::Market);
+        let (result, executions) = engine.place_order(&pair(), order).unwrap();
+        assert_eq!(executions.len(), 1);
+        assert_eq!(result.status, OrderStatus::Cancelled);
+        assert_eq!(engine.get_session_phase(&pair()), Ok(SessionPhase::Auction));
+        assert_eq!(
+            engine.get_best_bid_ask(&pair()),
+            Ok((None, Some(150.into())))
+        );
+
+        clock.advance(Duration::from_secs(300));
+        engine.advance_sessions().unwrap();
+        assert_eq!(
+            engine.get_session_phase(&pair()),
+            Ok(SessionPhase::Continuous)
+        );
+    }
+
+    #[test]
+    fn test_add_market_selects_matching_algorithm() {
+        let mut engine = MatchingEngine::new();
+        let spec = InstrumentSpec::new(1, 5).matching(MatchingAlgorithm::ProRata);
+        engine.add_market(pair(), spec).unwrap();
+        for qty in [10, 30] {
+            let order = OrderRequest::new(Side::Ask, qty, OrderType::limit(100));
+            engine.place_order(&pair(), order).unwrap();
+        }
+
+        // The orders of 10 and 30 share 20 as 5 and 15
+        let order = OrderRequest::new(Side::Bid, 20, OrderType::limit(100));
+        let (_, executions) = engine.place_order(&pair(), order).unwrap();
+        let fills: Vec<Quantity> = executions.iter().map(|e| e.qty).collect();
+        assert_eq!(fills, vec![Quantity::from(5), Quantity::from(15)]);
+
+        // Shares of 1.25 and 3.75 round down to no lot, the lot goes to the oldest order
+        let order = OrderRequest::new(Side::Bid, 5, OrderType::limit(100));
+        let (_, executions) = engine.place_order(&pair(), order).unwrap();
+        let fills: Vec<Quantity> = executions.iter().map(|e| e.qty).collect();
+        assert_eq!(fills, vec![Quantity::from(5)]);
+    }
+
+    #[test]
+    fn test_funded_market_reserves_and_settles() {
+        let path = std::env::temp_dir().join(format!("engine-{}.journal", Uuid::new_v4()));
+        let mut engine = MatchingEngine::new().with_journal(Journal::create(&path).unwrap());
+        engine
+            .add_market(pair(), InstrumentSpec::new(1, 1).require_funds())
+            .unwrap();
+        engine.deposit(1, "USD", 1000.into()).unwrap();
+        engine.deposit(2, "BTC", 10.into()).unwrap();
+        let place = |engine: &mut MatchingEngine, order: OrderRequest| {
+            engine.place_order(&pair(), order).unwrap()
+        };
+        let balance = |engine: &MatchingEngine, account, asset| {
+            let balance = engine.get_balance(account, asset);
+            (balance.available, balance.reserved)
+        };
+
+        let (result, _) = place(
+            &mut engine,
+            OrderRequest::new(Side::Bid, 5, OrderType::limit(100)),
+        );
+        assert_eq!(
+            result.status,
+            OrderStatus::Rejected(RejectReason::AccountRequired)
+        );
+        let (result, _) = place(
+            &mut engine,
+            OrderRequest::new(Side::Bid, 20, OrderType::limit(100)).account(1),
+        );
+        assert_eq!(
+            result.status,
+            OrderStatus::Rejected(RejectReason::InsufficientFunds)
+        );
+        let (result, _) = place(
+            &mut engine,
+            OrderRequest::new(Side::Bid, 5, OrderType::stop(105)).account(1),
+        );
+        assert_eq!(
+            result.status,
+            OrderStatus::Rejected(RejectReason::LimitPriceRequired)
+        );
+
+        let bid = OrderRequest::new(Side::Bid, 5, OrderType::limit(100)).account(1);
+        place(&mut engine, bid);
+        assert_eq!(balance(&engine, 1, "USD"), (500.into(), 500.into()));
+        assert!(engine.withdraw(1, "USD", 600.into()).is_err());
+
+        // The ask trades 3 at the bid's price, the bid pays out of its reservation
+        place(
+            &mut engine,
+            OrderRequest::new(Side::Ask, 3, OrderType::limit(90)).account(2),
+        );
+        assert_eq!(balance(&engine, 1, "USD"), (500.into(), 200.into()));
+        assert_eq!(balance(&engine, 1, "BTC"), (3.into(), 0.into()));
+        assert_eq!(balance(&engine, 2, "BTC"), (7.into(), 0.into()));
+        assert_eq!(balance(&engine, 2, "USD"), (300.into(), 0.into()));
+
+        engine.cancel_order(&pair(), bid.id()).unwrap();
+        assert_eq!(balance(&engine, 1, "USD"), (700.into(), 0.into()));
+
+        // A market bid reserves what taking the asks costs
+        place(
+            &mut engine,
+            OrderRequest::new(Side::Ask, 4, OrderType::limit(110)).account(2),
+        );
+        assert_eq!(balance(&engine, 2, "BTC"), (3.into(), 4.into()));
+        let (result, _) = place(
+            &mut engine,
+            OrderRequest::new(Side::Bid, 2, OrderType::Market).account(1),
+        );
+        assert_eq!(result.status, OrderStatus::Filled);
+        assert_eq!(balance(&engine, 1, "USD"), (480.into(), 0.into()));
+        assert_eq!(balance(&engine, 1, "BTC"), (5.into(), 0.into()));
+        assert_eq!(balance(&engine, 2, "BTC"), (3.into(), 2.into()));
+        assert_eq!(balance(&engine, 2, "USD"), (520.into(), 0.into()));
+        assert_eq!(engine.check_balances(), Ok(()));
+        drop(engine);
+
+        // Deposits are journaled with the orders, the recovered balances are the same
+        let recovered: MatchingEngine = MatchingEngine::recover(SystemClock, &path).unwrap();
+        assert_eq!(balance(&recovered, 1, "USD"), (480.into(), 0.into()));
+        assert_eq!(balance(&recovered, 2, "BTC"), (3.into(), 2.into()));
+        assert_eq!(recovered.check_balances(), Ok(()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_funded_market_rejects_duplicate_order_ids() {
+        let mut engine = MatchingEngine::new();
+        engine
+            .add_market(pair(), InstrumentSpec::new(1, 1).require_funds())
+            .unwrap();
+        engine.deposit(1, "USD", 1000.into()).unwrap();
+        engine.deposit(2, "BTC", 10.into()).unwrap();
+
+        let bid = OrderRequest::new(Side::Bid, 5, OrderType::limit(100)).account(1);
+        engine.place_order(&pair(), bid).unwrap();
+        assert_eq!(
+            engine.place_order(&pair(), bid).unwrap_err(),
+            OrderBookError::DuplicateOrderId(bid.id())
+        );
+        let level = OrderRequest::new_with_id(bid.id(), Side::Bid, 2, OrderType::system_level(100))
+            .account(1);
+        assert_eq!(
+            engine.place_order(&pair(), level).unwrap_err(),
+            OrderBookError::DuplicateOrderId(bid.id())
+        );
+        assert_eq!(engine.get_balance(1, "USD").reserved, 500.into());
+        assert_eq!(engine.check_balances(), Ok(()));
+
+        // The resting bid still pays for its fills out of its reservation
+        let ask = OrderRequest::new(Side::Ask, 5, OrderType::limit(100)).account(2);
+        engine.place_order(&pair(), ask).unwrap();
+        assert_eq!(engine.get_balance(1, "USD").total(), 500.into());
+        assert_eq!(engine.get_balance(1, "USD").reserved, 0.into());
+        assert_eq!(engine.get_balance(1, "BTC").available, 5.into());
+        assert_eq!(engine.check_balances(), Ok(()));
+    }
+
+    #[test]
+    fn test_funded_market_expires_orders() {
+        let path = std::env::temp_dir().join(format!("engine-{}.journal", Uuid::new_v4()));
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = ManualClock::new(start);
+        let mut engine =
+            MatchingEngine::with_clock(clock.clone()).with_journal(Journal::create(&path).unwrap());
+        engine
+            .add_market(pair(), InstrumentSpec::new(1, 1).require_funds())
+            .unwrap();
+        engine.deposit(1, "USD", 1000.into()).unwrap();
+        engine.deposit(2, "BTC", 10.into()).unwrap();
+
+        let expiry = TimeInForce::GTD(start + Duration::from_secs(10));
+        let bid = OrderRequest::new(Side::Bid, 5, OrderType::limit(100))
+            .time_in_force(expiry)
+            .account(1);
+        let stop = OrderRequest::new(Side::Ask, 2, OrderType::stop_limit(90, 90))
+            .time_in_force(expiry)
+            .account(2);
+        engine.place_order(&pair(), bid).unwrap();
+        engine.place_order(&pair(), stop).unwrap();
+        assert_eq!(engine.get_balance(1, "USD").reserved, 500.into());
+        assert_eq!(engine.get_balance(2, "BTC").reserved, 2.into());
+
+        assert!(engine.expire_orders(&pair()).unwrap().is_empty());
+        clock.advance(Duration::from_secs(20));
+        let expired = engine.expire_orders(&pair()).unwrap();
+        assert_eq!(expired.len(), 2);
+        assert!(expired
+            .iter()
+            .all(|result| result.status == OrderStatus::Expired));
+        // The stop order's funds are released along with the resting order's
+        assert_eq!(engine.get_balance(1, "USD").reserved, 0.into());
+        assert_eq!(engine.get_balance(2, "BTC").reserved, 0.into());
+        assert_eq!(engine.check_balances(), Ok(()));
+        drop(engine);
+
+        // The sweep is journaled and replayed at the time it ran, not at the recovering clock's time
+        let recovered: MatchingEngine<ManualClock> =
+            MatchingEngine::recover(ManualClock::new(start), &path).unwrap();
+        assert_eq!(recovered.get_volume(&pair()).unwrap(), Quantity::ZERO);
+        assert_eq!(recovered.get_balance(1, "USD").available, 1000.into());
+        assert_eq!(recovered.get_balance(2, "BTC").available, 10.into());
+        assert_eq!(recovered.check_balances(), Ok(()));
+        std::fs::remove_file(path).unwrap();
+    }
+}
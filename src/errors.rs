@@ -1 +1,103 @@
-pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+use std::fmt::Display;
+
+use crate::{AccountId, OrderId, Price, Quantity, RejectReason, SessionPhase, TradingPair};
+
+/// Errors returned by the order book and the matching engine.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderBookError {
+    // No market exists for the trading pair
+    UnknownMarket(TradingPair),
+    // A market already exists for the trading pair
+    MarketExists(TradingPair),
+    // The market is closed to every action
+    MarketClosed(TradingPair),
+    // The market's session can not move from one phase to the other
+    InvalidSessionTransition {
+        from: SessionPhase,
+        to: SessionPhase,
+    },
+    // An order with the same id is already on the book
+    DuplicateOrderId(OrderId),
+    // No order with the id is on the book
+    OrderNotFound(OrderId),
+    // Stop orders can only be cancelled until they trigger
+    StopOrderNotAmendable(OrderId),
+    // The amendment was refused for the reason, the order was left as it was
+    AmendRejected(RejectReason),
+    // Quantity is zero or negative
+    InvalidQuantity(Quantity),
+    // Peak quantity of an iceberg order is zero or negative
+    InvalidPeakQuantity(Quantity),
+    // Price is zero or negative
+    InvalidPrice(Price),
+    // A market data message did not follow the last one applied
+    SequenceGap {
+        expected: u64,
+        received: u64,
+    },
+    // Reading or writing a file failed
+    Io(String),
+    // A journal record failed its checksum or could not be decoded, offset is the byte it starts at
+    CorruptJournal {
+        offset: u64,
+    },
+    // An order book snapshot could not be decoded
+    InvalidSnapshot(String),
+    // The account's available balance of the asset is too small
+    InsufficientFunds {
+        account: AccountId,
+        asset: String,
+    },
+    // The balances of the accounts are inconsistent, funds were created or lost
+    BalancesNotConserved(String),
+}
+
+impl Display for OrderBookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderBookError::UnknownMarket(pair) => write!(f, "Market for {} does not exist", pair),
+            OrderBookError::MarketExists(pair) => write!(f, "Market for {} already exists", pair),
+            OrderBookError::MarketClosed(pair) => write!(f, "Market for {} is closed", pair),
+            OrderBookError::InvalidSessionTransition { from, to } => {
+                write!(f, "Session can not move from {:?} to {:?}", from, to)
+            }
+            OrderBookError::DuplicateOrderId(id) => write!(f, "Order {} already exists", id),
+            OrderBookError::OrderNotFound(id) => write!(f, "Order {} does not exist", id),
+            OrderBookError::StopOrderNotAmendable(id) => {
+                write!(f, "Stop order {} can not be amended before it triggers", id)
+            }
+            OrderBookError::AmendRejected(reason) => write!(f, "Amendment rejected: {:?}", reason),
+            OrderBookError::InvalidQuantity(qty) => write!(f, "Invalid quantity {}", qty),
+            OrderBookError::InvalidPeakQuantity(qty) => {
+                write!(f, "Invalid iceberg peak quantity {}", qty)
+            }
+            OrderBookError::InvalidPrice(price) => write!(f, "Invalid price {}", price),
+            OrderBookError::SequenceGap { expected, received } => write!(
+                f,
+                "Expected sequence number {} but received {}",
+                expected, received
+            ),
+            OrderBookError::Io(err) => write!(f, "I/O error: {}", err),
+            OrderBookError::CorruptJournal { offset } => {
+                write!(f, "Corrupt journal record at byte {}", offset)
+            }
+            OrderBookError::InvalidSnapshot(reason) => write!(f, "Invalid snapshot: {}", reason),
+            OrderBookError::InsufficientFunds { account, asset } => {
+                write!(f, "Account {} has insufficient {}", account, asset)
+            }
+            OrderBookError::BalancesNotConserved(reason) => {
+                write!(f, "Balances not conserved: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OrderBookError {}
+
+impl From<std::io::Error> for OrderBookError {
+    fn from(err: std::io::Error) -> Self {
+        OrderBookError::Io(err.to_string())
+    }
+}
+
+pub type Result<T, E = OrderBookError> = std::result::Result<T, E>;
@@ -0,0 +1,257 @@
+use rust_decimal::Decimal;
+
+use crate::{MatchingAlgorithm, OrderRequest, Price, Quantity, RejectReason};
+
+/// InstrumentSpec holds the trading rules of a market, checked by the `MatchingEngine` before an order reaches the book.
+///
+/// Every rule is optional, `InstrumentSpec::default()` accepts any order the book itself accepts.
+///
+/// The price band is the pre-trade check of the market: a limit order priced too far from the last trade is rejected
+/// before it reaches the book. Where trades may print is checked at trade time by the book's `PriceBands`, see
+/// `MatchingEngine::set_price_bands`. An order must pass both.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct InstrumentSpec {
+    // Prices must be a multiple of the tick size
+    pub tick_size: Option<Price>,
+    // Quantities must be a multiple of the lot size
+    pub lot_size: Option<Quantity>,
+    pub min_qty: Option<Quantity>,
+    pub max_qty: Option<Quantity>,
+    // Minimum price * quantity of an order
+    pub min_notional: Option<Decimal>,
+    // Pre-trade check, maximum distance of a limit price from the last trade price, as a fraction (0.1 is 10%)
+    pub price_band: Option<Decimal>,
+    // How the book shares incoming orders among the orders resting at a price, pro-rata shares are rounded to the lot
+    pub matching: MatchingAlgorithm,
+    // Orders must name an account, the funds they need are reserved from its balances, see `Accounts`.
+    // Each order must have an id of its own, system level orders are not merged.
+    pub require_funds: bool,
+}
+
+impl InstrumentSpec {
+    pub fn new(tick_size: impl Into<Price>, lot_size: impl Into<Quantity>) -> Self {
+        Self {
+            tick_size: Some(tick_size.into()),
+            lot_size: Some(lot_size.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn min_qty(mut self, min_qty: impl Into<Quantity>) -> Self {
+        self.min_qty = Some(min_qty.into());
+        self
+    }
+
+    pub fn max_qty(mut self, max_qty: impl Into<Quantity>) -> Self {
+        self.max_qty = Some(max_qty.into());
+        self
+    }
+
+    pub fn min_notional(mut self, min_notional: impl Into<Decimal>) -> Self {
+        self.min_notional = Some(min_notional.into());
+        self
+    }
+
+    pub fn price_band(mut self, price_band: impl Into<Decimal>) -> Self {
+        self.price_band = Some(price_band.into());
+        self
+    }
+
+    pub fn matching(mut self, matching: MatchingAlgorithm) -> Self {
+        self.matching = matching;
+        self
+    }
+
+    pub fn require_funds(mut self) -> Self {
+        self.require_funds = true;
+        self
+    }
+
+    /// Number of decimal places of the market's prices, taken from the tick size.
+    /// Books with an integer price type store prices as multiples of `10^-price_scale`.
+    pub fn price_scale(&self) -> u32 {
+        self.tick_size
+            .map_or(0, |tick_size| tick_size.normalize().scale())
+    }
+
+    /// Number of decimal places of the market's quantities, taken from the lot size.
+    /// Books with an integer price type store quantities as multiples of `10^-qty_scale`.
+    pub fn qty_scale(&self) -> u32 {
+        self.lot_size
+            .map_or(0, |lot_size| lot_size.normalize().scale())
+    }
+
+    /// Checks the order against the spec.
+    /// `reference` is the last trade price, the price band is measured from it and it values unpriced orders for the
+    /// minimum notional. When there is no reference price those checks are skipped.
+    pub fn validate(
+        &self,
+        order: &OrderRequest,
+        reference: Option<Price>,
+    ) -> Result<(), RejectReason> {
+        let prices = [order.price(), order.order_type.trigger_price()];
+        if let Some(tick_size) = self.tick_size {
+            if prices.iter().flatten().any(|p| !is_multiple(*p, tick_size)) {
+                return Err(RejectReason::PriceNotOnTick);
+            }
+        }
+        if let Some(lot_size) = self.lot_size {
+            let quantities = [Some(order.qty), order.peak_qty];
+            if quantities
+                .iter()
+                .flatten()
+                .any(|q| !is_multiple(*q, lot_size))
+            {
+                return Err(RejectReason::QuantityNotOnLot);
+            }
+        }
+        if self.min_qty.is_some_and(|min_qty| order.qty < min_qty) {
+            return Err(RejectReason::QuantityBelowMinimum);
+        }
+        if let Some(peak_qty) = order.peak_qty {
+            let min_qty = self.min_qty.unwrap_or(Decimal::ZERO);
+            if peak_qty <= Decimal::ZERO || peak_qty < min_qty {
+                return Err(RejectReason::PeakBelowMinimum);
+            }
+        }
+        if self.max_qty.is_some_and(|max_qty| order.qty > max_qty) {
+            return Err(RejectReason::QuantityAboveMaximum);
+        }
+        if let Some(min_notional) = self.min_notional {
+            if let Some(price) = order.price().or(reference) {
+                if price * order.qty < min_notional {
+                    return Err(RejectReason::NotionalBelowMinimum);
+                }
+            }
+        }
+        if let (Some(band), Some(reference)) = (self.price_band, reference) {
+            let low = reference * (Decimal::ONE - band);
+            let high = reference * (Decimal::ONE + band);
+            if let Some(price) = order.price() {
+                if price < low || price > high {
+                    return Err(RejectReason::PriceOutsideBand);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_multiple(value: Decimal, step: Decimal) -> bool {
+    step <= Decimal::ZERO || (value % step).is_zero()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OrderType, Side};
+
+    #[test]
+    fn test_tick_and_lot_size() {
+        let spec = InstrumentSpec::new(Decimal::new(5, 1), 10);
+        let order = OrderRequest::new(Side::Bid, 20, OrderType::limit(Decimal::new(105, 1)));
+        assert_eq!(spec.validate(&order, None), Ok(()));
+
+        let order = OrderRequest::new(Side::Bid, 20, OrderType::limit(Decimal::new(102, 1)));
+        assert_eq!(
+            spec.validate(&order, None),
+            Err(RejectReason::PriceNotOnTick)
+        );
+
+        let order = OrderRequest::new(Side::Bid, 25, OrderType::limit(10));
+        assert_eq!(
+            spec.validate(&order, None),
+            Err(RejectReason::QuantityNotOnLot)
+        );
+
+        let order = OrderRequest::new(Side::Bid, 20, OrderType::limit(10)).iceberg(5);
+        assert_eq!(
+            spec.validate(&order, None),
+            Err(RejectReason::QuantityNotOnLot)
+        );
+
+        let order = OrderRequest::new(
+            Side::Bid,
+            20,
+            OrderType::stop_limit(Decimal::new(101, 1), 10),
+        );
+        assert_eq!(
+            spec.validate(&order, None),
+            Err(RejectReason::PriceNotOnTick)
+        );
+    }
+
+    #[test]
+    fn test_price_scale() {
+        assert_eq!(InstrumentSpec::default().price_scale(), 0);
+        assert_eq!(InstrumentSpec::new(5, 1).price_scale(), 0);
+        assert_eq!(InstrumentSpec::new(Decimal::new(50, 3), 1).price_scale(), 2);
+    }
+
+    #[test]
+    fn test_qty_scale() {
+        assert_eq!(InstrumentSpec::default().qty_scale(), 0);
+        assert_eq!(InstrumentSpec::new(1, 10).qty_scale(), 0);
+        assert_eq!(InstrumentSpec::new(1, Decimal::new(250, 4)).qty_scale(), 3);
+    }
+
+    #[test]
+    fn test_quantity_and_notional_limits() {
+        let spec = InstrumentSpec::default()
+            .min_qty(10)
+            .max_qty(100)
+            .min_notional(500);
+        let order = OrderRequest::new(Side::Ask, 5, OrderType::limit(200));
+        assert_eq!(
+            spec.validate(&order, None),
+            Err(RejectReason::QuantityBelowMinimum)
+        );
+        let order = OrderRequest::new(Side::Ask, 150, OrderType::limit(200));
+        assert_eq!(
+            spec.validate(&order, None),
+            Err(RejectReason::QuantityAboveMaximum)
+        );
+        let order = OrderRequest::new(Side::Ask, 20, OrderType::limit(20));
+        assert_eq!(
+            spec.validate(&order, None),
+            Err(RejectReason::NotionalBelowMinimum)
+        );
+        let order = OrderRequest::new(Side::Ask, 20, OrderType::limit(200)).iceberg(5);
+        assert_eq!(
+            spec.validate(&order, None),
+            Err(RejectReason::PeakBelowMinimum)
+        );
+        let order = OrderRequest::new(Side::Ask, 20, OrderType::limit(200)).iceberg(0);
+        assert_eq!(
+            InstrumentSpec::new(1, 1).validate(&order, None),
+            Err(RejectReason::PeakBelowMinimum)
+        );
+
+        // Market orders are valued at the reference price
+        let order = OrderRequest::new(Side::Ask, 20, OrderType::Market);
+        assert_eq!(spec.validate(&order, None), Ok(()));
+        assert_eq!(
+            spec.validate(&order, Some(20.into())),
+            Err(RejectReason::NotionalBelowMinimum)
+        );
+        assert_eq!(spec.validate(&order, Some(30.into())), Ok(()));
+    }
+
+    #[test]
+    fn test_price_band() {
+        let spec = InstrumentSpec::default().price_band(Decimal::new(1, 1));
+        let order = OrderRequest::new(Side::Bid, 10, OrderType::limit(111));
+        assert_eq!(spec.validate(&order, None), Ok(()));
+        assert_eq!(
+            spec.validate(&order, Some(100.into())),
+            Err(RejectReason::PriceOutsideBand)
+        );
+        let order = OrderRequest::new(Side::Bid, 10, OrderType::limit(90));
+        assert_eq!(spec.validate(&order, Some(100.into())), Ok(()));
+        let order = OrderRequest::new(Side::Bid, 10, OrderType::limit(89));
+        assert_eq!(
+            spec.validate(&order, Some(100.into())),
+            Err(RejectReason::PriceOutsideBand)
+        );
+    }
+}
@@ -0,0 +1,571 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+
+use crate::codec::*;
+use crate::errors::{OrderBookError, Result};
+use crate::orderbook::Timestamp;
+use crate::{
+    AccountId, InstrumentSpec, MatchingAlgorithm, OrderId, OrderRequest, PriceBands,
+    RemainderPolicy, SessionPhase, TimeInForce, TradingPair,
+};
+
+// Start of every journal file, the trailing digits are the format version
+const HEADER: &[u8; 8] = b"OBJRNL03";
+// Length and checksum preceding each record's payload
+const RECORD_HEADER_LEN: usize = 8;
+
+/// Command is a call to the `MatchingEngine` that changes its state, the unit recorded in the journal.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    AddMarket {
+        pair: TradingPair,
+        spec: InstrumentSpec,
+    },
+    RemoveMarket {
+        pair: TradingPair,
+    },
+    PlaceOrder {
+        pair: TradingPair,
+        order: OrderRequest,
+    },
+    CancelOrder {
+        pair: TradingPair,
+        order_id: OrderId,
+    },
+    SetSessionPhase {
+        pair: TradingPair,
+        phase: SessionPhase,
+    },
+    SetPriceBands {
+        pair: TradingPair,
+        bands: PriceBands,
+    },
+    Deposit {
+        account: AccountId,
+        asset: String,
+        amount: Decimal,
+    },
+    Withdraw {
+        account: AccountId,
+        asset: String,
+        amount: Decimal,
+    },
+    ExpireOrders {
+        pair: TradingPair,
+    },
+}
+
+/// A command as read back from the journal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    // Position of the command in the journal, starting at 1
+    pub sequence: u64,
+    // Engine clock time the command was journaled at
+    pub timestamp: Timestamp,
+    pub command: Command,
+}
+
+/// Journal is the write-ahead log of a `MatchingEngine`.
+///
+/// Every command is appended as a length prefixed record, with a sequence number and a CRC-32 of its payload, before
+/// the engine applies it. On open the records are checked in order: a record cut short at the end of the file is a
+/// write interrupted by a crash and is truncated, any other damage is reported as `CorruptJournal`.
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+    // Sequence number of the next record
+    sequence: u64,
+    // Whether each append is flushed to disk before returning
+    sync: bool,
+}
+
+impl Journal {
+    /// Creates an empty journal at `path`, replacing any file already there.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let mut file = File::create(path)?;
+        file.write_all(HEADER)?;
+        Ok(Self {
+            file,
+            sequence: 1,
+            sync: false,
+        })
+    }
+
+    /// Opens the journal at `path`, creating it if it does not exist, and returns its entries.
+    /// Further appends continue after the last complete record.
+    pub fn open(path: impl AsRef<Path>) -> Result<(Self, Vec<JournalEntry>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        if bytes.is_empty() {
+            file.write_all(HEADER)?;
+            bytes.extend_from_slice(HEADER);
+        }
+        let (entries, end) = read_entries(&bytes)?;
+        // Drop a torn record left by a crash mid-append
+        file.set_len(end as u64)?;
+        file.seek(SeekFrom::End(0))?;
+        let sequence = entries.last().map_or(1, |entry| entry.sequence + 1);
+        Ok((
+            Self {
+                file,
+                sequence,
+                sync: false,
+            },
+            entries,
+        ))
+    }
+
+    /// Reads the entries of the journal at `path` without opening it for appends.
+    pub fn read(path: impl AsRef<Path>) -> Result<Vec<JournalEntry>> {
+        let bytes = std::fs::read(path)?;
+        read_entries(&bytes).map(|(entries, _)| entries)
+    }
+
+    /// Flushes every append to disk before returning, so a command survives a machine crash and not only a process
+    /// crash. This costs a disk sync per command.
+    pub fn with_sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    /// Sequence number the next appended command will get.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Appends the command, returns its sequence number.
+    pub fn append(&mut self, command: &Command, timestamp: Timestamp) -> Result<u64> {
+        let sequence = self.sequence;
+        let mut payload = Vec::with_capacity(128);
+        put_u64(&mut payload, sequence);
+        put_timestamp(&mut payload, timestamp);
+        encode_command(&mut payload, command);
+
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32(&payload).to_le_bytes());
+        record.extend_from_slice(&payload);
+        self.file.write_all(&record)?;
+        if self.sync {
+            self.file.sync_data()?;
+        }
+        self.sequence += 1;
+        Ok(sequence)
+    }
+}
+
+// Decodes the records of a journal file, returns them with the length of the valid prefix of the file
+fn read_entries(bytes: &[u8]) -> Result<(Vec<JournalEntry>, usize)> {
+    if bytes.len() < HEADER.len() || &bytes[..HEADER.len()] != HEADER {
+        return Err(OrderBookError::CorruptJournal { offset: 0 });
+    }
+    let mut entries: Vec<JournalEntry> = Vec::new();
+    let mut offset = HEADER.len();
+    while bytes.len() - offset >= RECORD_HEADER_LEN {
+        let len = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap());
+        let start = offset + RECORD_HEADER_LEN;
+        let Some(payload) = bytes.get(start..start + len) else {
+            break;
+        };
+        let corrupt = OrderBookError::CorruptJournal {
+            offset: offset as u64,
+        };
+        if crc32(payload) != checksum {
+            return Err(corrupt);
+        }
+        let entry = decode_entry(payload).ok_or(corrupt)?;
+        let expected = entries.last().map_or(1, |last| last.sequence + 1);
+        if entry.sequence != expected {
+            return Err(OrderBookError::SequenceGap {
+                expected,
+                received: entry.sequence,
+            });
+        }
+        entries.push(entry);
+        offset = start + len;
+    }
+    Ok((entries, offset))
+}
+
+fn decode_entry(payload: &[u8]) -> Option<JournalEntry> {
+    let mut reader = Reader::new(payload);
+    let entry = JournalEntry {
+        sequence: reader.u64()?,
+        timestamp: reader.timestamp()?,
+        command: read_command(&mut reader)?,
+    };
+    reader.is_empty().then_some(entry)
+}
+
+fn encode_command(buf: &mut Vec<u8>, command: &Command) {
+    match command {
+        Command::AddMarket { pair, spec } => {
+            put_u8(buf, 0);
+            put_pair(buf, pair);
+            for rule in [
+                spec.tick_size,
+                spec.lot_size,
+                spec.min_qty,
+                spec.max_qty,
+                spec.min_notional,
+                spec.price_band,
+            ] {
+                put_opt_decimal(buf, rule);
+            }
+            match spec.matching {
+                MatchingAlgorithm::Fifo => put_u8(buf, 0),
+                MatchingAlgorithm::ProRata => put_u8(buf, 1),
+                MatchingAlgorithm::TopOrderProRata => put_u8(buf, 2),
+                MatchingAlgorithm::ProRataMinimum(min_allocation) => {
+                    put_tagged(buf, 3, min_allocation)
+                }
+            }
+            put_u8(buf, spec.require_funds as u8);
+        }
+        Command::RemoveMarket { pair } => {
+            put_u8(buf, 1);
+            put_pair(buf, pair);
+        }
+        Command::PlaceOrder { pair, order } => {
+            put_u8(buf, 2);
+            put_pair(buf, pair);
+            put_order(buf, order);
+        }
+        Command::CancelOrder { pair, order_id } => {
+            put_u8(buf, 3);
+            put_pair(buf, pair);
+            put_uuid(buf, *order_id);
+        }
+        Command::SetSessionPhase { pair, phase } => {
+            put_u8(buf, 4);
+            put_pair(buf, pair);
+            put_u8(
+                buf,
+                match phase {
+                    SessionPhase::PreOpen => 0,
+                    SessionPhase::Auction => 1,
+                    SessionPhase::Continuous => 2,
+                    SessionPhase::Halted => 3,
+                    SessionPhase::Closed => 4,
+                },
+            );
+        }
+        Command::SetPriceBands { pair, bands } => {
+            put_u8(buf, 5);
+            put_pair(buf, pair);
+            put_opt_decimal(buf, bands.reference_price);
+            put_opt_decimal(buf, bands.static_band);
+            put_opt_decimal(buf, bands.dynamic_band);
+            put_u8(
+                buf,
+                match bands.remainder {
+                    RemainderPolicy::Cancel => 0,
+                    RemainderPolicy::Rest => 1,
+                },
+            );
+            put_opt_u64(
+                buf,
+                bands
+                    .volatility_auction
+                    .map(|duration| duration.as_nanos() as u64),
+            );
+        }
+        Command::Deposit {
+            account,
+            asset,
+            amount,
+        } => {
+            put_u8(buf, 6);
+            put_u64(buf, *account);
+            put_str(buf, asset);
+            put_decimal(buf, *amount);
+        }
+        Command::Withdraw {
+            account,
+            asset,
+            amount,
+        } => {
+            put_u8(buf, 7);
+            put_u64(buf, *account);
+            put_str(buf, asset);
+            put_decimal(buf, *amount);
+        }
+        Command::ExpireOrders { pair } => {
+            put_u8(buf, 8);
+            put_pair(buf, pair);
+        }
+    }
+}
+
+fn put_pair(buf: &mut Vec<u8>, pair: &TradingPair) {
+    put_str(buf, pair.base());
+    put_str(buf, pair.quote());
+}
+
+fn put_order(buf: &mut Vec<u8>, order: &OrderRequest) {
+    put_uuid(buf, order.id());
+    put_side(buf, order.side);
+    put_decimal(buf, order.qty);
+    put_order_type(buf, order.order_type);
+    put_opt_decimal(buf, order.peak_qty);
+    put_post_only(buf, order.post_only);
+    match order.time_in_force {
+        TimeInForce::GTC => put_u8(buf, 0),
+        TimeInForce::GTD(expiry) => {
+            put_u8(buf, 1);
+            put_timestamp(buf, expiry);
+        }
+        TimeInForce::DAY => put_u8(buf, 2),
+    }
+    put_opt_u64(buf, order.account);
+    put_self_trade_prevention(buf, order.self_trade_prevention);
+}
+
+fn read_pair(reader: &mut Reader) -> Option<TradingPair> {
+    Some(TradingPair::new(reader.string()?, reader.string()?))
+}
+
+fn read_command(reader: &mut Reader) -> Option<Command> {
+    let command = match reader.u8()? {
+        0 => Command::AddMarket {
+            pair: read_pair(reader)?,
+            spec: InstrumentSpec {
+                tick_size: reader.opt_decimal()?,
+                lot_size: reader.opt_decimal()?,
+                min_qty: reader.opt_decimal()?,
+                max_qty: reader.opt_decimal()?,
+                min_notional: reader.opt_decimal()?,
+                price_band: reader.opt_decimal()?,
+                matching: match reader.u8()? {
+                    0 => MatchingAlgorithm::Fifo,
+                    1 => MatchingAlgorithm::ProRata,
+                    2 => MatchingAlgorithm::TopOrderProRata,
+                    3 => MatchingAlgorithm::ProRataMinimum(reader.decimal()?),
+                    _ => return None,
+                },
+                require_funds: match reader.u8()? {
+                    0 => false,
+                    1 => true,
+                    _ => return None,
+                },
+            },
+        },
+        1 => Command::RemoveMarket {
+            pair: read_pair(reader)?,
+        },
+        2 => Command::PlaceOrder {
+            pair: read_pair(reader)?,
+            order: read_order(reader)?,
+        },
+        3 => Command::CancelOrder {
+            pair: read_pair(reader)?,
+            order_id: reader.uuid()?,
+        },
+        4 => Command::SetSessionPhase {
+            pair: read_pair(reader)?,
+            phase: match reader.u8()? {
+                0 => SessionPhase::PreOpen,
+                1 => SessionPhase::Auction,
+                2 => SessionPhase::Continuous,
+                3 => SessionPhase::Halted,
+                4 => SessionPhase::Closed,
+                _ => return None,
+            },
+        },
+        5 => Command::SetPriceBands {
+            pair: read_pair(reader)?,
+            bands: PriceBands {
+                reference_price: reader.opt_decimal()?,
+                static_band: reader.opt_decimal()?,
+                dynamic_band: reader.opt_decimal()?,
+                remainder: match reader.u8()? {
+                    0 => RemainderPolicy::Cancel,
+                    1 => RemainderPolicy::Rest,
+                    _ => return None,
+                },
+                volatility_auction: reader.opt_u64()?.map(Duration::from_nanos),
+            },
+        },
+        6 => Command::Deposit {
+            account: reader.u64()?,
+            asset: reader.string()?,
+            amount: reader.decimal()?,
+        },
+        7 => Command::Withdraw {
+            account: reader.u64()?,
+            asset: reader.string()?,
+            amount: reader.decimal()?,
+        },
+        8 => Command::ExpireOrders {
+            pair: read_pair(reader)?,
+        },
+        _ => return None,
+    };
+    Some(command)
+}
+
+fn read_order(reader: &mut Reader) -> Option<OrderRequest> {
+    let id = reader.uuid()?;
+    let side = reader.side()?;
+    let qty = reader.decimal()?;
+    let order_type = reader.order_type()?;
+    let mut order = OrderRequest::new_with_id(id, side, qty, order_type);
+    order.peak_qty = reader.opt_decimal()?;
+    order.post_only = reader.post_only()?;
+    order.time_in_force = match reader.u8()? {
+        0 => TimeInForce::GTC,
+        1 => TimeInForce::GTD(reader.timestamp()?),
+        2 => TimeInForce::DAY,
+        _ => return None,
+    };
+    order.account = reader.opt_u64()?;
+    order.self_trade_prevention = reader.self_trade_prevention()?;
+    Some(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::timestamp;
+    use crate::{OrderType, PostOnly, SelfTradePrevention, Side};
+    use rust_decimal::Decimal;
+    use std::time::{Duration, UNIX_EPOCH};
+    use uuid::Uuid;
+
+    fn pair() -> TradingPair {
+        TradingPair::new("BTC".to_string(), "USD".to_string())
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.journal", name, Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_journal_round_trip() {
+        let path = temp_path("round-trip");
+        let commands = [
+            Command::AddMarket {
+                pair: pair(),
+                spec: InstrumentSpec::new(Decimal::new(5, 2), 1)
+                    .price_band(Decimal::new(1, 1))
+                    .matching(MatchingAlgorithm::ProRataMinimum(2.into()))
+                    .require_funds(),
+            },
+            Command::PlaceOrder {
+                pair: pair(),
+                order: OrderRequest::new(Side::Bid, 10, OrderType::stop_limit(105, 106))
+                    .iceberg(2)
+                    .post_only(PostOnly::Reprice(Decimal::new(5, 2)))
+                    .time_in_force(TimeInForce::GTD(UNIX_EPOCH + Duration::from_nanos(7)))
+                    .account(42)
+                    .self_trade_prevention(SelfTradePrevention::CancelBoth),
+            },
+            Command::PlaceOrder {
+                pair: pair(),
+                order: OrderRequest::new(Side::Ask, Decimal::new(15, 1), OrderType::Market)
+                    .time_in_force(TimeInForce::DAY),
+            },
+            Command::CancelOrder {
+                pair: pair(),
+                order_id: Uuid::new_v4(),
+            },
+            Command::SetSessionPhase {
+                pair: pair(),
+                phase: SessionPhase::Halted,
+            },
+            Command::SetPriceBands {
+                pair: pair(),
+                bands: PriceBands::default()
+                    .static_band(100, Decimal::new(1, 1))
+                    .remainder(RemainderPolicy::Rest)
+                    .volatility_auction(Duration::from_secs(300)),
+            },
+            Command::Deposit {
+                account: 42,
+                asset: "USD".to_string(),
+                amount: Decimal::new(10_050, 2),
+            },
+            Command::Withdraw {
+                account: 42,
+                asset: "BTC".to_string(),
+                amount: 1.into(),
+            },
+            Command::ExpireOrders { pair: pair() },
+            Command::RemoveMarket { pair: pair() },
+        ];
+        let ts = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut journal = Journal::create(&path).unwrap();
+        for (i, command) in commands.iter().enumerate() {
+            assert_eq!(journal.append(command, ts).unwrap(), i as u64 + 1);
+        }
+
+        let entries = Journal::read(&path).unwrap();
+        assert_eq!(entries.len(), commands.len());
+        for (i, (entry, command)) in entries.iter().zip(&commands).enumerate() {
+            assert_eq!(entry.sequence, i as u64 + 1);
+            assert_eq!(entry.timestamp, ts);
+            assert_eq!(&entry.command, command);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_journal_truncates_torn_record() {
+        let path = temp_path("torn");
+        let command = Command::RemoveMarket { pair: pair() };
+        let mut journal = Journal::create(&path).unwrap();
+        journal.append(&command, timestamp()).unwrap();
+        journal.append(&command, timestamp()).unwrap();
+        drop(journal);
+
+        // Cut the last record short, as a crash in the middle of the write would
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 3).unwrap();
+
+        let (mut journal, entries) = Journal::open(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(journal.sequence(), 2);
+        journal.append(&command, timestamp()).unwrap();
+        let entries = Journal::read(&path).unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.sequence).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_journal_detects_corruption() {
+        let path = temp_path("corrupt");
+        let command = Command::RemoveMarket { pair: pair() };
+        let mut journal = Journal::create(&path).unwrap();
+        journal.append(&command, timestamp()).unwrap();
+        journal.append(&command, timestamp()).unwrap();
+        drop(journal);
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+        let record_len = (bytes.len() - HEADER.len()) / 2;
+        assert_eq!(
+            Journal::open(&path).unwrap_err(),
+            OrderBookError::CorruptJournal {
+                offset: (HEADER.len() + record_len) as u64
+            }
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
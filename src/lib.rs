@@ -1,27 +1,48 @@
+mod accounts;
+mod codec;
 mod engine;
 mod errors;
+mod instrument;
+mod journal;
+mod market_data;
 mod notifications;
 mod orderbook;
+mod session;
 mod tui;
 
+pub use accounts::{Accounts, Balance};
 pub use engine::{MatchingEngine, TradingPair};
-pub use errors::Result;
-pub use notifications::{Notification, NotificationHandler};
+pub use errors::{OrderBookError, Result};
+pub use instrument::InstrumentSpec;
+pub use journal::{Command, Journal, JournalEntry};
+pub use market_data::{
+    replay_l3, L1Publisher, L1Quote, L2Book, L2Delta, L2Message, L2Publisher, L3Event, L3Message,
+    L3Publisher,
+};
+pub use notifications::{
+    EventSink, Notification, NotificationHandler, NotificationKind, NullSink, Publisher,
+    SlowConsumerPolicy, Subscription,
+};
+pub use session::{Session, SessionPhase};
 
 pub use orderbook::{
-    HalfBook, OrderBook, OrderBookState, OrderId, OrderRequest, OrderResult, OrderStatus,
-    OrderType, Price, Quantity, Side, TradeExecution, TradeOrder,
+    AccountId, BandBreach, Clock, Equilibrium, Fifo, Fill, HalfBook, LevelSnapshot, Lots,
+    ManualClock, MatchingAlgorithm, MatchingPolicy, MonotonicClock, OrderBook, OrderBookSnapshot,
+    OrderBookState, OrderId, OrderRequest, OrderResult, OrderSnapshot, OrderStatus, OrderType,
+    PostOnly, Price, PriceBands, PriceLevel, PriceType, ProRata, Quantity, QuantityType,
+    RejectReason, RemainderPolicy, ReplayClock, SelfTradePrevention, Side, SystemClock, Ticks,
+    TimeInForce, TradeExecution, TradeOrder, TriggerBook, SNAPSHOT_VERSION,
 };
 
 use tracing_subscriber::fmt::format::FmtSpan;
 pub use tui::App;
 
-pub fn run() -> Result<()> {
+pub fn run() -> std::io::Result<()> {
     tracing_subscriber::fmt()
         .with_span_events(FmtSpan::CLOSE)
         .with_max_level(tracing::Level::DEBUG)
         .init();
 
     let mut tui = App::new();
-    Ok(tui.run()?)
+    tui.run()
 }
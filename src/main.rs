@@ -40,14 +40,18 @@
 //     app.run()
 // }
 
-use orderbooklib::{OrderBook, OrderRequest, OrderStatus, OrderType, Side};
+use orderbooklib::{ManualClock, OrderBook, OrderRequest, OrderStatus, OrderType, Side};
 use polars::{frame::row::Row, prelude::*};
-use std::time::Instant;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use tracing::debug;
 
+// Midnight (UTC) of the LOBSTER sample session, message times are seconds after midnight
+const SESSION_DATE_SECS: u64 = 1_340_236_800;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Create a new order book
-    let mut order_book = OrderBook::default();
+    // Create a new order book driven by the message times so replays reproduce the original event times
+    let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(SESSION_DATE_SECS));
+    let mut order_book = OrderBook::with_clock(clock.clone());
 
     let schema = Schema::from_iter(vec![
         Field::new("Time".into(), DataType::Float64),
@@ -73,8 +77,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("{}", df);
     // println!("{}", df.collect()?);
 
-    fn extract_data_out_of_row(row: Row) -> (i32, String, Side, u64, OrderType) {
+    fn extract_data_out_of_row(row: Row) -> (f64, i32, String, Side, u64, OrderType) {
         let row = row.0;
+        let time = match row[0] {
+            AnyValue::Float64(time) => time,
+            _ => panic!("Invalid time"),
+        };
         let event_type = match row[1] {
             AnyValue::Int32(event_type) => event_type,
             _ => panic!("Invalid event type"),
@@ -100,14 +108,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             _ => panic!("Invalid price"),
         });
 
-        (event_type, id, side, qty, order_type)
+        (time, event_type, id, side, qty, order_type)
     }
 
     // fn create_orders_from_df(df: &DataFrame) -> Vec<OrderRequest> {
     //     let mut orders = Vec::new();
     //     for i in 0..df.height() {
     //         if let Ok(row) = df.get_row(i) {
-    //             let (event_type, id, side, qty, order_type) = extract_data_out_of_row(row);
+    //             let (_, event_type, id, side, qty, order_type) = extract_data_out_of_row(row);
     //             let order = OrderRequest::new_with_other_id(id, side, qty, order_type);
     //             orders.push(order);
     //         }
@@ -121,26 +129,37 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let start_time = Instant::now();
     for i in 0..df.height() {
         if let Ok(row) = df.get_row(i) {
-            let (event_type, id, side, qty, order_type) = extract_data_out_of_row(row);
+            let (time, event_type, id, side, qty, order_type) = extract_data_out_of_row(row);
+            clock.set(
+                UNIX_EPOCH + Duration::from_secs(SESSION_DATE_SECS) + Duration::from_secs_f64(time),
+            );
 
             match event_type {
                 1 | 4 | 5 => {
                     let order = OrderRequest::new_with_other_id(id, side, qty, order_type);
-                    let (fill_result, trade_ex) = order_book.add_order(order);
-                    if fill_result.status != OrderStatus::Open {
-                        debug!("{:#?}", fill_result);
-                        debug!("-----------------------------");
-                        debug!("{:#?}", trade_ex);
-                        debug!("#############################");
+                    match order_book.add_order(order) {
+                        Ok((fill_result, trade_ex)) => {
+                            if fill_result.status != OrderStatus::Open {
+                                debug!("{:#?}", fill_result);
+                                debug!("-----------------------------");
+                                debug!("{:#?}", trade_ex);
+                                debug!("#############################");
+                            }
+                        }
+                        Err(err) => debug!("Skipping message {}: {}", i, err),
                     }
                 }
                 2 => {
                     let id = uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_DNS, id.as_ref());
-                    order_book.cancel_order(id, qty);
+                    if let Err(err) = order_book.cancel_order(id, qty) {
+                        debug!("Skipping message {}: {}", i, err);
+                    }
                 }
                 3 => {
                     let id = uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_DNS, id.as_ref());
-                    order_book.delete_order(id);
+                    if let Err(err) = order_book.delete_order(id) {
+                        debug!("Skipping message {}: {}", i, err);
+                    }
                 }
                 _ => continue,
             }
@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use crate::orderbook::Timestamp;
+use crate::{Clock, OrderBook, Price, PriceType, Quantity};
+
+/// Best bid and ask with the displayed quantity at each, and the price of the last trade.
+#[derive(Debug, Clone, PartialEq)]
+pub struct L1Quote {
+    pub sequence: u64,
+    pub timestamp: Timestamp,
+    pub bid: Option<(Price, Quantity)>,
+    pub ask: Option<(Price, Quantity)>,
+    pub last_trade_price: Option<Price>,
+}
+
+impl L1Quote {
+    // Whether both quotes show the same top of book, regardless of when they were taken
+    fn same_top(&self, other: &L1Quote) -> bool {
+        self.bid == other.bid
+            && self.ask == other.ask
+            && self.last_trade_price == other.last_trade_price
+    }
+}
+
+#[derive(Debug, Default)]
+/// L1Publisher emits a quote whenever the top of an order book changes.
+///
+/// With a conflation interval at most one quote is emitted per interval. Changes in between are not queued, the next
+/// quote simply shows the top of book as it is by then, so a slow consumer only ever sees the latest state.
+pub struct L1Publisher {
+    sequence: u64,
+    last: Option<L1Quote>,
+    conflation: Option<Duration>,
+}
+
+impl L1Publisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Emits at most one quote every `interval`, timed by the book's clock.
+    pub fn with_conflation(mut self, interval: Duration) -> Self {
+        self.conflation = Some(interval);
+        self
+    }
+
+    /// Returns the last quote emitted.
+    pub fn last_quote(&self) -> Option<&L1Quote> {
+        self.last.as_ref()
+    }
+
+    /// Returns a quote if the top of the book changed since the last one and the conflation interval has passed.
+    /// Should be called after every change to the book, and periodically when conflating so the last change is not held
+    /// back until the next one.
+    pub fn publish<C: Clock, P: PriceType>(&mut self, book: &OrderBook<C, P>) -> Option<L1Quote> {
+        let now = book.clock().now();
+        let (bid, ask) = book.best_prices();
+        let mut quote = L1Quote {
+            sequence: self.sequence + 1,
+            timestamp: now,
+            bid: bid.and_then(|price| Some((price, book.bids.get_total_qty(&price)?))),
+            ask: ask.and_then(|price| Some((price, book.asks.get_total_qty(&price)?))),
+            last_trade_price: book.last_trade_price(),
+        };
+        if let Some(last) = &self.last {
+            if last.same_top(&quote) {
+                return None;
+            }
+            let too_soon = self.conflation.is_some_and(|interval| {
+                now.duration_since(last.timestamp)
+                    .is_ok_and(|elapsed| elapsed < interval)
+            });
+            if too_soon {
+                return None;
+            }
+        } else if bid.is_none() && ask.is_none() && quote.last_trade_price.is_none() {
+            // Nothing to show for an empty book
+            return None;
+        }
+        self.sequence += 1;
+        quote.sequence = self.sequence;
+        self.last = Some(quote.clone());
+        Some(quote)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ManualClock, OrderRequest, OrderType, Side};
+
+    fn limit(side: Side, qty: u64, price: u64) -> OrderRequest {
+        OrderRequest::new(side, qty, OrderType::limit(price))
+    }
+
+    #[test]
+    fn test_quotes_only_on_top_of_book_changes() {
+        let mut book = OrderBook::default();
+        let mut publisher = L1Publisher::new();
+        assert!(publisher.publish(&book).is_none());
+
+        book.add_order(limit(Side::Bid, 5, 99)).unwrap();
+        let quote = publisher.publish(&book).unwrap();
+        assert_eq!(quote.sequence, 1);
+        assert_eq!(quote.bid, Some((99.into(), 5.into())));
+        assert_eq!(quote.ask, None);
+
+        // Below the best bid, the top of book is unchanged
+        book.add_order(limit(Side::Bid, 5, 98)).unwrap();
+        assert!(publisher.publish(&book).is_none());
+
+        // More size at the best bid is a change
+        book.add_order(limit(Side::Bid, 1, 99)).unwrap();
+        assert_eq!(
+            publisher.publish(&book).unwrap().bid,
+            Some((99.into(), 6.into()))
+        );
+
+        book.add_order(limit(Side::Ask, 2, 99)).unwrap();
+        let quote = publisher.publish(&book).unwrap();
+        assert_eq!(quote.sequence, 3);
+        assert_eq!(quote.bid, Some((99.into(), 4.into())));
+        assert_eq!(quote.last_trade_price, Some(99.into()));
+        assert_eq!(publisher.last_quote(), Some(&quote));
+    }
+
+    #[test]
+    fn test_conflation() {
+        let clock = ManualClock::new(std::time::UNIX_EPOCH);
+        let mut book = OrderBook::with_clock(clock.clone());
+        let mut publisher = L1Publisher::new().with_conflation(Duration::from_millis(100));
+
+        book.add_order(limit(Side::Ask, 5, 101)).unwrap();
+        assert!(publisher.publish(&book).is_some());
+
+        // Changes within the interval are held back
+        book.add_order(limit(Side::Ask, 5, 100)).unwrap();
+        assert!(publisher.publish(&book).is_none());
+        clock.advance(Duration::from_millis(50));
+        book.add_order(limit(Side::Ask, 5, 99)).unwrap();
+        assert!(publisher.publish(&book).is_none());
+
+        // Once it has passed only the latest top of book is sent
+        clock.advance(Duration::from_millis(50));
+        let quote = publisher.publish(&book).unwrap();
+        assert_eq!(quote.sequence, 2);
+        assert_eq!(quote.ask, Some((99.into(), 5.into())));
+        assert!(publisher.publish(&book).is_none());
+    }
+}
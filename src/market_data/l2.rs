@@ -0,0 +1,428 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use ahash::AHashMap as HashMap;
+
+use crate::errors::{OrderBookError, Result};
+use crate::notifications::Notification;
+use crate::{Clock, OrderBook, OrderBookState, OrderId, Price, PriceType, Quantity, Side};
+
+/// A change of the displayed quantity at one price level, a quantity of zero removes the level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct L2Delta {
+    pub sequence: u64,
+    pub side: Side,
+    pub price: Price,
+    pub qty: Quantity,
+}
+
+/// Message of the level 2 feed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum L2Message {
+    Delta(L2Delta),
+    // Every level of the book, as of the delta with the same sequence number
+    Snapshot {
+        sequence: u64,
+        state: OrderBookState,
+    },
+}
+
+impl L2Message {
+    pub fn sequence(&self) -> u64 {
+        match self {
+            L2Message::Delta(delta) => delta.sequence,
+            L2Message::Snapshot { sequence, .. } => *sequence,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// L2Publisher turns the state of an order book into an incremental per-level feed.
+///
+/// The publisher must be given every notification of the book through `on_notification`, from when the book was
+/// empty, and keeps the levels they touched. `publish` then reads the displayed quantity of those levels only and emits a
+/// delta for every one that changed, each with the next sequence number. A snapshot of the whole book is added every
+/// `snapshot_interval` deltas so consumers can recover from a gap without a request back to the publisher.
+pub struct L2Publisher {
+    sequence: u64,
+    bids: BTreeMap<Price, Quantity>,
+    asks: BTreeMap<Price, Quantity>,
+    // Side and price of every order resting on the book, fills and cancels only carry the order's id
+    resting: HashMap<OrderId, (Side, Price)>,
+    // Levels changed since the last publish
+    touched_bids: BTreeSet<Price>,
+    touched_asks: BTreeSet<Price>,
+    snapshot_interval: Option<u64>,
+    // Deltas published since the last snapshot
+    since_snapshot: u64,
+}
+
+impl L2Publisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Publishes a snapshot after every `interval` deltas.
+    pub fn with_snapshot_interval(mut self, interval: u64) -> Self {
+        self.snapshot_interval = Some(interval);
+        self
+    }
+
+    /// Sequence number of the last published delta, zero before the first one.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Records the level a notification of the book changed, to be published by the next `publish`.
+    pub fn on_notification(&mut self, notification: &Notification) {
+        let touched = match *notification {
+            Notification::OrderAdded {
+                order_id,
+                side,
+                price,
+                ..
+            } => {
+                self.resting.insert(order_id, (side, price));
+                Some((side, price))
+            }
+            Notification::OrderCancelled {
+                order_id,
+                remaining_qty,
+                ..
+            } if remaining_qty > Quantity::ZERO => self.resting.get(&order_id).copied(),
+            Notification::OrderPartiallyFilled { order_id, .. }
+            | Notification::OrderReplenished { order_id, .. }
+            | Notification::OrderAmended {
+                order_id,
+                kept_priority: true,
+                ..
+            } => self.resting.get(&order_id).copied(),
+            Notification::OrderFilled { order_id, .. }
+            | Notification::OrderCancelled { order_id, .. }
+            | Notification::OrderExpired { order_id, .. }
+            | Notification::OrderAmended { order_id, .. } => self.resting.remove(&order_id),
+            Notification::OrderAccepted { .. }
+            | Notification::OrderRejected { .. }
+            | Notification::StopTriggered { .. }
+            | Notification::TradeExecuted(_)
+            | Notification::IndicativeUncross(_)
+            | Notification::CircuitBreakerTripped { .. }
+            | Notification::SessionChanged { .. } => None,
+        };
+        match touched {
+            Some((Side::Bid, price)) => self.touched_bids.insert(price),
+            Some((Side::Ask, price)) => self.touched_asks.insert(price),
+            None => false,
+        };
+    }
+
+    /// Returns the deltas of the levels touched since the last publish, followed by a snapshot when one is due.
+    pub fn publish<C: Clock, P: PriceType>(&mut self, book: &OrderBook<C, P>) -> Vec<L2Message> {
+        let mut messages = Vec::new();
+        for side in [Side::Bid, Side::Ask] {
+            let (published, touched, levels) = match side {
+                Side::Bid => (&mut self.bids, &mut self.touched_bids, &book.bids),
+                Side::Ask => (&mut self.asks, &mut self.touched_asks, &book.asks),
+            };
+            for price in std::mem::take(touched) {
+                let qty = levels.get_total_qty(&price).unwrap_or_default();
+                if published.get(&price).copied().unwrap_or_default() == qty {
+                    continue;
+                }
+                if qty.is_zero() {
+                    published.remove(&price);
+                } else {
+                    published.insert(price, qty);
+                }
+                self.sequence += 1;
+                messages.push(L2Message::Delta(L2Delta {
+                    sequence: self.sequence,
+                    side,
+                    price,
+                    qty,
+                }));
+            }
+        }
+
+        self.since_snapshot += messages.len() as u64;
+        if self
+            .snapshot_interval
+            .is_some_and(|interval| self.since_snapshot >= interval)
+        {
+            messages.push(self.snapshot());
+        }
+        messages
+    }
+
+    /// Returns a snapshot of the levels published so far, e.g. for a consumer that has just connected.
+    pub fn snapshot(&mut self) -> L2Message {
+        self.since_snapshot = 0;
+        L2Message::Snapshot {
+            sequence: self.sequence,
+            state: OrderBookState {
+                asks: self.asks.iter().rev().map(|(p, q)| (*p, *q)).collect(),
+                bids: self.bids.iter().rev().map(|(p, q)| (*p, *q)).collect(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// L2Book is a consumer's mirror of an order book built from the level 2 feed.
+///
+/// The mirror is only valid once a snapshot has been applied. A delta that does not follow the last applied sequence
+/// number is a gap, the mirror then ignores deltas until the next snapshot.
+pub struct L2Book {
+    sequence: Option<u64>,
+    bids: BTreeMap<Price, Quantity>,
+    asks: BTreeMap<Price, Quantity>,
+}
+
+impl L2Book {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sequence number of the last applied message, None until a snapshot has been applied or after a gap.
+    pub fn sequence(&self) -> Option<u64> {
+        self.sequence
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.sequence.is_some()
+    }
+
+    /// Applies a message of the feed, returns an error on a gap in the sequence numbers.
+    /// Deltas up to the sequence of the last snapshot are already included in it and skipped.
+    pub fn apply(&mut self, message: &L2Message) -> Result<()> {
+        match message {
+            L2Message::Snapshot { sequence, state } => {
+                self.bids = state.bids.iter().copied().collect();
+                self.asks = state.asks.iter().copied().collect();
+                self.sequence = Some(*sequence);
+                Ok(())
+            }
+            L2Message::Delta(delta) => {
+                let Some(sequence) = self.sequence else {
+                    return Ok(());
+                };
+                if delta.sequence <= sequence {
+                    return Ok(());
+                }
+                if delta.sequence != sequence + 1 {
+                    self.sequence = None;
+                    return Err(OrderBookError::SequenceGap {
+                        expected: sequence + 1,
+                        received: delta.sequence,
+                    });
+                }
+                let levels = match delta.side {
+                    Side::Bid => &mut self.bids,
+                    Side::Ask => &mut self.asks,
+                };
+                if delta.qty.is_zero() {
+                    levels.remove(&delta.price);
+                } else {
+                    levels.insert(delta.price, delta.qty);
+                }
+                self.sequence = Some(delta.sequence);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<(Price, Quantity)> {
+        self.bids.last_key_value().map(|(p, q)| (*p, *q))
+    }
+
+    pub fn best_ask(&self) -> Option<(Price, Quantity)> {
+        self.asks.first_key_value().map(|(p, q)| (*p, *q))
+    }
+
+    /// Returns the levels in the layout of `OrderBook::get_order_book_state`, highest price first on both sides.
+    pub fn state(&self) -> OrderBookState {
+        OrderBookState {
+            asks: self.asks.iter().rev().map(|(p, q)| (*p, *q)).collect(),
+            bids: self.bids.iter().rev().map(|(p, q)| (*p, *q)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OrderRequest, OrderType};
+    use crossbeam_channel::Receiver;
+
+    fn limit(side: Side, qty: u64, price: u64) -> OrderRequest {
+        OrderRequest::new(side, qty, OrderType::limit(price))
+    }
+
+    // A book sending its notifications to the returned receiver
+    fn book() -> (OrderBook, Receiver<Notification>) {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        (OrderBook::default().with_event_sink(sender), receiver)
+    }
+
+    fn publish(
+        publisher: &mut L2Publisher,
+        book: &OrderBook,
+        receiver: &Receiver<Notification>,
+    ) -> Vec<L2Message> {
+        for notification in receiver.try_iter() {
+            publisher.on_notification(&notification);
+        }
+        publisher.publish(book)
+    }
+
+    #[test]
+    fn test_publish_deltas() {
+        let (mut book, receiver) = book();
+        let mut publisher = L2Publisher::new();
+        assert!(publish(&mut publisher, &book, &receiver).is_empty());
+
+        book.add_order(limit(Side::Bid, 5, 99)).unwrap();
+        book.add_order(limit(Side::Ask, 3, 101)).unwrap();
+        assert_eq!(
+            publish(&mut publisher, &book, &receiver),
+            [
+                L2Message::Delta(L2Delta {
+                    sequence: 1,
+                    side: Side::Bid,
+                    price: 99.into(),
+                    qty: 5.into(),
+                }),
+                L2Message::Delta(L2Delta {
+                    sequence: 2,
+                    side: Side::Ask,
+                    price: 101.into(),
+                    qty: 3.into(),
+                }),
+            ]
+        );
+        // Nothing changed since the last publish
+        assert!(publish(&mut publisher, &book, &receiver).is_empty());
+
+        book.add_order(OrderRequest::new(Side::Bid, 3, OrderType::Market))
+            .unwrap();
+        assert_eq!(
+            publish(&mut publisher, &book, &receiver),
+            [L2Message::Delta(L2Delta {
+                sequence: 3,
+                side: Side::Ask,
+                price: 101.into(),
+                qty: 0.into(),
+            })]
+        );
+        assert_eq!(publisher.sequence(), 3);
+    }
+
+    #[test]
+    fn test_mirror_follows_book() {
+        let (mut book, receiver) = book();
+        let mut publisher = L2Publisher::new();
+        let mut mirror = L2Book::new();
+        mirror.apply(&publisher.snapshot()).unwrap();
+
+        let (bid, _) = book.add_order(limit(Side::Bid, 5, 99)).unwrap();
+        book.add_order(limit(Side::Bid, 2, 98)).unwrap();
+        book.add_order(limit(Side::Ask, 4, 101).iceberg(2)).unwrap();
+        for message in publish(&mut publisher, &book, &receiver) {
+            mirror.apply(&message).unwrap();
+        }
+        book.cancel_order(bid.get_id(), 1).unwrap();
+        book.add_order(OrderRequest::new(Side::Bid, 3, OrderType::Market))
+            .unwrap();
+        for message in publish(&mut publisher, &book, &receiver) {
+            mirror.apply(&message).unwrap();
+        }
+
+        assert_eq!(mirror.state(), book.get_order_book_state());
+        assert_eq!(mirror.best_bid(), Some((99.into(), 4.into())));
+        // Only the refreshed iceberg peak is displayed
+        assert_eq!(mirror.best_ask(), Some((101.into(), 1.into())));
+    }
+
+    #[test]
+    fn test_gap_recovered_by_snapshot() {
+        let (mut book, receiver) = book();
+        let mut publisher = L2Publisher::new().with_snapshot_interval(3);
+        let mut mirror = L2Book::new();
+
+        // Deltas before the first snapshot are ignored
+        book.add_order(limit(Side::Bid, 1, 97)).unwrap();
+        let messages = publish(&mut publisher, &book, &receiver);
+        assert_eq!(messages.len(), 1);
+        mirror.apply(&messages[0]).unwrap();
+        assert!(!mirror.is_synced());
+
+        book.add_order(limit(Side::Bid, 1, 98)).unwrap();
+        book.add_order(limit(Side::Bid, 1, 99)).unwrap();
+        let messages = publish(&mut publisher, &book, &receiver);
+        // The third delta makes a snapshot due
+        assert!(matches!(
+            messages.last(),
+            Some(L2Message::Snapshot { sequence: 3, .. })
+        ));
+        for message in &messages {
+            mirror.apply(message).unwrap();
+        }
+        assert_eq!(mirror.sequence(), Some(3));
+
+        book.add_order(limit(Side::Ask, 1, 101)).unwrap();
+        book.add_order(limit(Side::Ask, 1, 102)).unwrap();
+        let messages = publish(&mut publisher, &book, &receiver);
+        // The first delta is lost
+        assert_eq!(
+            mirror.apply(&messages[1]),
+            Err(OrderBookError::SequenceGap {
+                expected: 4,
+                received: 5
+            })
+        );
+        assert!(!mirror.is_synced());
+
+        mirror.apply(&publisher.snapshot()).unwrap();
+        assert_eq!(mirror.sequence(), Some(5));
+        assert_eq!(mirror.state(), book.get_order_book_state());
+    }
+
+    #[test]
+    fn test_publish_only_touched_levels() {
+        let (mut book, receiver) = book();
+        let mut publisher = L2Publisher::new();
+        let (bid, _) = book.add_order(limit(Side::Bid, 5, 99)).unwrap();
+        book.add_order(limit(Side::Bid, 2, 98)).unwrap();
+        book.add_order(limit(Side::Ask, 4, 101)).unwrap();
+        assert_eq!(publish(&mut publisher, &book, &receiver).len(), 3);
+
+        // Moving the bid touches its old and new level only
+        book.amend_order(bid.get_id(), 5, 100).unwrap();
+        let deltas: Vec<_> = publish(&mut publisher, &book, &receiver)
+            .into_iter()
+            .map(|message| match message {
+                L2Message::Delta(delta) => (delta.side, delta.price, delta.qty),
+                L2Message::Snapshot { .. } => panic!("unexpected snapshot"),
+            })
+            .collect();
+        assert_eq!(
+            deltas,
+            [
+                (Side::Bid, 99.into(), 0.into()),
+                (Side::Bid, 100.into(), 5.into()),
+            ]
+        );
+
+        // A level added and removed again between two publishes is not published
+        book.add_order(limit(Side::Ask, 1, 101)).unwrap();
+        let (order, _) = book.add_order(limit(Side::Ask, 1, 102)).unwrap();
+        book.delete_order(order.get_id()).unwrap();
+        assert_eq!(publish(&mut publisher, &book, &receiver).len(), 1);
+        assert_eq!(
+            publisher.snapshot(),
+            L2Message::Snapshot {
+                sequence: 6,
+                state: book.get_order_book_state(),
+            }
+        );
+    }
+}
@@ -0,0 +1,490 @@
+use ahash::AHashMap as HashMap;
+use crossbeam_channel::Sender;
+
+use crate::errors::{OrderBookError, Result};
+use crate::notifications::{EventSink, Notification};
+use crate::{Clock, OrderBook, OrderId, OrderType, Price, PriceType, Quantity, Side, TradeOrder};
+
+/// Order by order change to the book, keyed by the id of the resting order. Quantities are those shown on the book.
+#[derive(Debug, Clone, PartialEq)]
+pub enum L3Event {
+    // The order was added to the back of the queue at `price` showing `qty`
+    Add {
+        order_id: OrderId,
+        side: Side,
+        price: Price,
+        qty: Quantity,
+    },
+    // `qty` of the resting order traded, the order is removed once `remaining_qty` is zero
+    Execute {
+        order_id: OrderId,
+        side: Side,
+        price: Price,
+        qty: Quantity,
+        remaining_qty: Quantity,
+    },
+    // `qty` of the resting order was cancelled, `remaining_qty` stays on the book
+    Cancel {
+        order_id: OrderId,
+        side: Side,
+        price: Price,
+        qty: Quantity,
+        remaining_qty: Quantity,
+    },
+    // The order now has `qty` open at `price`, if it did not keep its priority it moved to the back of the queue
+    Replace {
+        order_id: OrderId,
+        side: Side,
+        price: Price,
+        qty: Quantity,
+        kept_priority: bool,
+    },
+    // The order left the book without trading
+    Delete {
+        order_id: OrderId,
+        side: Side,
+        price: Price,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct L3Message {
+    pub sequence: u64,
+    pub event: L3Event,
+}
+
+#[derive(Debug, Default)]
+/// L3Publisher derives the order by order feed of one market from the notifications of its order book.
+///
+/// Only orders resting on the book are reported, with the quantity they show, iceberg reserves are never published. An
+/// iceberg whose peak is taken is published as executed, then added back to the queue once the peak is refreshed.
+/// An amend that costs an order its priority is published as a delete followed by an add once the order rests again.
+pub struct L3Publisher {
+    sequence: u64,
+    // Side, price and shown quantity of every order resting on the book
+    resting: HashMap<OrderId, (Side, Price, Quantity)>,
+    sender: Option<Sender<L3Message>>,
+}
+
+impl L3Publisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a publisher that can be installed as the book's event sink, sending its messages to `sender`.
+    pub fn with_sender(sender: Sender<L3Message>) -> Self {
+        Self {
+            sender: Some(sender),
+            ..Self::default()
+        }
+    }
+
+    /// Sequence number of the last message, zero before the first one.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Translates a notification of the book, returning the message if it changed a resting order.
+    pub fn on_notification(&mut self, notification: &Notification) -> Option<L3Message> {
+        let event = match *notification {
+            Notification::OrderAdded {
+                order_id,
+                side,
+                price,
+                displayed_qty,
+                ..
+            } => {
+                self.resting.insert(order_id, (side, price, displayed_qty));
+                L3Event::Add {
+                    order_id,
+                    side,
+                    price,
+                    qty: displayed_qty,
+                }
+            }
+            Notification::OrderPartiallyFilled {
+                order_id,
+                qty,
+                displayed_qty,
+                ..
+            } => {
+                let (side, price, shown) = self.resting.get_mut(&order_id)?;
+                let (side, price) = (*side, *price);
+                // An iceberg with its peak taken leaves the feed until the peak is refreshed
+                if displayed_qty == Quantity::ZERO {
+                    self.resting.remove(&order_id);
+                } else {
+                    *shown = displayed_qty;
+                }
+                L3Event::Execute {
+                    order_id,
+                    side,
+                    price,
+                    qty,
+                    remaining_qty: displayed_qty,
+                }
+            }
+            Notification::OrderFilled { order_id, qty, .. } => {
+                let (side, price, _) = self.resting.remove(&order_id)?;
+                L3Event::Execute {
+                    order_id,
+                    side,
+                    price,
+                    qty,
+                    remaining_qty: Quantity::ZERO,
+                }
+            }
+            Notification::OrderCancelled {
+                order_id,
+                remaining_qty,
+                displayed_qty,
+                ..
+            } if remaining_qty > Quantity::ZERO => {
+                let (side, price, shown) = self.resting.get_mut(&order_id)?;
+                // Cancelling out of an iceberg's reserve does not change what the book shows
+                if displayed_qty == *shown {
+                    return None;
+                }
+                let qty = *shown - displayed_qty;
+                *shown = displayed_qty;
+                L3Event::Cancel {
+                    order_id,
+                    side: *side,
+                    price: *price,
+                    qty,
+                    remaining_qty: displayed_qty,
+                }
+            }
+            Notification::OrderCancelled { order_id, .. }
+            | Notification::OrderExpired { order_id, .. }
+            | Notification::OrderAmended {
+                order_id,
+                kept_priority: false,
+                ..
+            } => {
+                let (side, price, _) = self.resting.remove(&order_id)?;
+                L3Event::Delete {
+                    order_id,
+                    side,
+                    price,
+                }
+            }
+            Notification::OrderAmended {
+                order_id,
+                side,
+                price,
+                displayed_qty,
+                ..
+            } => {
+                let (_, _, shown) = self.resting.get_mut(&order_id)?;
+                if displayed_qty == *shown {
+                    return None;
+                }
+                *shown = displayed_qty;
+                L3Event::Replace {
+                    order_id,
+                    side,
+                    price,
+                    qty: displayed_qty,
+                    kept_priority: true,
+                }
+            }
+            Notification::OrderReplenished {
+                order_id,
+                side,
+                price,
+                displayed_qty,
+                ..
+            } => match self.resting.insert(order_id, (side, price, displayed_qty)) {
+                Some(_) => L3Event::Replace {
+                    order_id,
+                    side,
+                    price,
+                    qty: displayed_qty,
+                    kept_priority: false,
+                },
+                None => L3Event::Add {
+                    order_id,
+                    side,
+                    price,
+                    qty: displayed_qty,
+                },
+            },
+            Notification::OrderAccepted { .. }
+            | Notification::OrderRejected { .. }
+            | Notification::StopTriggered { .. }
+            | Notification::TradeExecuted(_)
+            | Notification::IndicativeUncross(_)
+            | Notification::CircuitBreakerTripped { .. }
+            | Notification::SessionChanged { .. } => return None,
+        };
+        self.sequence += 1;
+        Some(L3Message {
+            sequence: self.sequence,
+            event,
+        })
+    }
+}
+
+impl EventSink for L3Publisher {
+    fn emit(&mut self, notification: Notification) {
+        if let Some(message) = self.on_notification(&notification) {
+            if let Some(sender) = &self.sender {
+                let _ = sender.send(message);
+            }
+        }
+    }
+}
+
+/// Replays messages of the feed into a book, which ends up with the same resting orders in the same queue order as
+/// the book the feed was published from, showing the same quantities. Orders are replayed as plain limit orders
+/// holding the quantity they show, without an iceberg reserve or a time in force.
+/// Returns an error on a gap in the sequence numbers or a message the book cannot apply.
+pub fn replay_l3<'a, C: Clock, P: PriceType>(
+    book: &mut OrderBook<C, P>,
+    messages: impl IntoIterator<Item = &'a L3Message>,
+) -> Result<u64> {
+    let mut last = None;
+    for message in messages {
+        if let Some(last) = last {
+            if message.sequence != last + 1 {
+                return Err(OrderBookError::SequenceGap {
+                    expected: last + 1,
+                    received: message.sequence,
+                });
+            }
+        }
+        apply_l3(book, &message.event)?;
+        last = Some(message.sequence);
+    }
+    Ok(last.unwrap_or_default())
+}
+
+fn apply_l3<C: Clock, P: PriceType>(book: &mut OrderBook<C, P>, event: &L3Event) -> Result<()> {
+    match *event {
+        L3Event::Add {
+            order_id,
+            side,
+            price,
+            qty,
+        } => book.add_limit_order(side, price, resting_order(order_id, side, price, qty)),
+        L3Event::Execute { order_id, qty, .. } | L3Event::Cancel { order_id, qty, .. } => {
+            book.cancel_order(order_id, qty).map(|_| ())
+        }
+        L3Event::Replace {
+            order_id,
+            price,
+            qty,
+            kept_priority: true,
+            ..
+        } => book.amend_order(order_id, qty, price).map(|_| ()),
+        L3Event::Replace {
+            order_id,
+            side,
+            price,
+            qty,
+            kept_priority: false,
+        } => {
+            book.delete_order(order_id)?;
+            book.add_limit_order(side, price, resting_order(order_id, side, price, qty))
+        }
+        L3Event::Delete { order_id, .. } => book.delete_order(order_id).map(|_| ()),
+    }
+}
+
+fn resting_order(order_id: OrderId, side: Side, price: Price, qty: Quantity) -> TradeOrder {
+    let mut order = TradeOrder::new(qty);
+    order.id = order_id;
+    order.side = side;
+    order.order_type = OrderType::limit(price);
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ManualClock, OrderRequest, TimeInForce};
+    use std::time::Duration;
+
+    type Queue = Vec<(Price, Vec<(OrderId, Quantity)>)>;
+
+    // Resting orders of each side by level, best price first, in queue order with the quantity they show
+    fn queues<C: Clock>(book: &OrderBook<C>) -> (Queue, Queue) {
+        let side = |side: Side, prices: Vec<Price>| -> Queue {
+            prices
+                .into_iter()
+                .map(|price| {
+                    let orders = book.get_orders_at_price(side, price).unwrap();
+                    let orders = orders.iter().map(|o| (o.id, o.visible_qty())).collect();
+                    (price, orders)
+                })
+                .collect()
+        };
+        (
+            side(Side::Bid, book.bids.iter_prices().collect()),
+            side(Side::Ask, book.asks.iter_prices().collect()),
+        )
+    }
+
+    fn limit(side: Side, qty: u64, price: u64) -> OrderRequest {
+        OrderRequest::new(side, qty, OrderType::limit(price))
+    }
+
+    #[test]
+    fn test_replay_reproduces_book() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let clock = ManualClock::new(std::time::UNIX_EPOCH);
+        let mut book =
+            OrderBook::with_clock(clock.clone()).with_event_sink(L3Publisher::with_sender(sender));
+
+        let (a, _) = book.add_order(limit(Side::Bid, 10, 99)).unwrap();
+        let (b, _) = book.add_order(limit(Side::Bid, 5, 99)).unwrap();
+        let (c, _) = book.add_order(limit(Side::Bid, 7, 98)).unwrap();
+        book.add_order(limit(Side::Ask, 9, 101).iceberg(3)).unwrap();
+        book.add_order(limit(Side::Ask, 4, 101)).unwrap();
+        let expiry = std::time::UNIX_EPOCH + Duration::from_secs(10);
+        book.add_order(limit(Side::Ask, 2, 103).time_in_force(TimeInForce::GTD(expiry)))
+            .unwrap();
+        book.add_order(OrderRequest::new(
+            Side::Ask,
+            1,
+            OrderType::system_level(104),
+        ))
+        .unwrap();
+        book.add_order(OrderRequest::new(
+            Side::Ask,
+            2,
+            OrderType::system_level(104),
+        ))
+        .unwrap();
+
+        // Takes the iceberg peak, which moves behind the other order at 101, then part of that order
+        book.add_order(OrderRequest::new(Side::Bid, 4, OrderType::Market))
+            .unwrap();
+        book.cancel_order(a.get_id(), 2).unwrap();
+        book.amend_order(b.get_id(), 3, 99).unwrap();
+        book.amend_order(a.get_id(), 8, 100).unwrap();
+        book.delete_order(c.get_id()).unwrap();
+        // Fills the bid at 100 and part of the one at 99
+        book.add_order(limit(Side::Ask, 10, 99)).unwrap();
+        clock.advance(Duration::from_secs(10));
+        book.expire_orders(clock.now());
+
+        let messages: Vec<_> = receiver.try_iter().collect();
+        let mut replayed = OrderBook::default();
+        assert_eq!(
+            replay_l3(&mut replayed, &messages).unwrap(),
+            messages.len() as u64
+        );
+        let (bids, asks) = queues(&book);
+        assert_eq!(bids, [(99.into(), vec![(b.get_id(), 1.into())])]);
+        assert_eq!(asks.len(), 2);
+        assert_eq!(queues(&replayed), (bids, asks));
+        assert_eq!(replayed.get_order_count(), book.get_order_count());
+    }
+
+    #[test]
+    fn test_replay_shows_icebergs_without_reserves() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let mut book = OrderBook::default().with_event_sink(L3Publisher::with_sender(sender));
+
+        let (iceberg, _) = book
+            .add_order(limit(Side::Ask, 20, 101).iceberg(5))
+            .unwrap();
+        book.add_order(limit(Side::Ask, 4, 101)).unwrap();
+        // Takes the peak, which is refreshed behind the other order, then part of that order
+        book.add_order(limit(Side::Bid, 7, 101)).unwrap();
+        // Comes out of the reserve, the book shows the same
+        book.cancel_order(iceberg.get_id(), 10).unwrap();
+        book.cancel_order(iceberg.get_id(), 3).unwrap();
+        // Takes liquidity on arrival and rests showing a full peak
+        book.add_order(limit(Side::Bid, 30, 101).iceberg(6))
+            .unwrap();
+
+        let messages: Vec<_> = receiver.try_iter().collect();
+        for message in &messages {
+            if let L3Event::Add { qty, .. } | L3Event::Replace { qty, .. } = message.event {
+                assert!(qty <= 6.into(), "reserve published in {:?}", message);
+            }
+        }
+        let mut replayed = OrderBook::default();
+        replay_l3(&mut replayed, &messages).unwrap();
+        assert_eq!(
+            book.get_order_book_state().bids,
+            vec![(101.into(), 6.into())]
+        );
+        assert_eq!(replayed.get_order_book_state(), book.get_order_book_state());
+        assert_eq!(queues(&replayed), queues(&book));
+    }
+
+    #[test]
+    fn test_l3_messages() {
+        let mut publisher = L3Publisher::new();
+        let mut book = OrderBook::default();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        book.set_event_sink(sender);
+
+        let (ask, _) = book.add_order(limit(Side::Ask, 5, 101)).unwrap();
+        let ask_id = ask.get_id();
+        book.add_order(limit(Side::Bid, 2, 101)).unwrap();
+        book.cancel_order(ask_id, 1).unwrap();
+        book.delete_order(ask_id).unwrap();
+        let events: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|n| publisher.on_notification(&n))
+            .map(|m| m.event)
+            .collect();
+        assert_eq!(
+            events,
+            [
+                L3Event::Add {
+                    order_id: ask_id,
+                    side: Side::Ask,
+                    price: 101.into(),
+                    qty: 5.into(),
+                },
+                L3Event::Execute {
+                    order_id: ask_id,
+                    side: Side::Ask,
+                    price: 101.into(),
+                    qty: 2.into(),
+                    remaining_qty: 3.into(),
+                },
+                L3Event::Cancel {
+                    order_id: ask_id,
+                    side: Side::Ask,
+                    price: 101.into(),
+                    qty: 1.into(),
+                    remaining_qty: 2.into(),
+                },
+                L3Event::Delete {
+                    order_id: ask_id,
+                    side: Side::Ask,
+                    price: 101.into(),
+                },
+            ]
+        );
+        assert_eq!(publisher.sequence(), 4);
+    }
+
+    #[test]
+    fn test_replay_detects_gap() {
+        let add = |sequence| L3Message {
+            sequence,
+            event: L3Event::Add {
+                order_id: OrderId::new_v4(),
+                side: Side::Bid,
+                price: 10.into(),
+                qty: 1.into(),
+            },
+        };
+        let mut book = OrderBook::default();
+        assert_eq!(
+            replay_l3(&mut book, &[add(1), add(3)]),
+            Err(OrderBookError::SequenceGap {
+                expected: 2,
+                received: 3
+            })
+        );
+    }
+}
@@ -0,0 +1,7 @@
+mod l1;
+mod l2;
+mod l3;
+
+pub use l1::*;
+pub use l2::*;
+pub use l3::*;
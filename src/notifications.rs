@@ -1,30 +1,376 @@
-use crossbeam_channel::{unbounded, Receiver, Sender};
-use std::collections::HashMap;
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender, TrySendError};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use tracing::warn;
 use uuid::Uuid;
 
-use crate::{orderbook::TradeExecution, Side};
+use crate::{
+    orderbook::{Equilibrium, TradeExecution},
+    AccountId, OrderId, OrderType, Price, Quantity, RejectReason, SessionPhase, Side, TradingPair,
+};
 
-#[derive(Debug, Clone)]
+/// Notification is an event emitted by an order book, in the order the book processed it.
+///
+/// Events of an order that rests on the book follow its lifecycle: it is accepted, may be (partially) filled as it
+/// takes liquidity, is added to the book, then leaves it by being filled, cancelled or expiring.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Notification {
+    // The order passed validation and is being processed
+    OrderAccepted {
+        order_id: OrderId,
+        side: Side,
+        account: Option<AccountId>,
+        order_type: OrderType,
+        qty: Quantity,
+    },
+    // The order was rejected without reaching the book
+    OrderRejected {
+        order_id: OrderId,
+        side: Side,
+        account: Option<AccountId>,
+        reason: RejectReason,
+    },
+    // The order now rests on the book at `price` with `qty` remaining, of which `displayed_qty` is shown.
+    // Only iceberg orders show less than their open quantity.
     OrderAdded {
-        order_id: Uuid,
-        price: u64,
-        qty: u64,
+        order_id: OrderId,
+        side: Side,
+        account: Option<AccountId>,
+        price: Price,
+        qty: Quantity,
+        displayed_qty: Quantity,
+    },
+    // `qty` of the order traded at `price`, `remaining_qty` is still open with `displayed_qty` of it shown
+    OrderPartiallyFilled {
+        order_id: OrderId,
+        side: Side,
+        account: Option<AccountId>,
+        price: Price,
+        qty: Quantity,
+        remaining_qty: Quantity,
+        displayed_qty: Quantity,
+    },
+    // The last `qty` of the order traded at `price`
+    OrderFilled {
+        order_id: OrderId,
+        side: Side,
+        account: Option<AccountId>,
+        price: Price,
+        qty: Quantity,
+    },
+    // `qty` of the order was cancelled, it stays on the book if `remaining_qty` is not zero, with `displayed_qty` shown.
+    // `price` is the level the order rested at, None if it was not on the book.
+    OrderCancelled {
+        order_id: OrderId,
+        side: Side,
+        account: Option<AccountId>,
+        price: Option<Price>,
+        qty: Quantity,
+        remaining_qty: Quantity,
+        displayed_qty: Quantity,
+    },
+    // A resting order was amended. If it kept its priority it stays on the book at `price` with `qty`, `displayed_qty`
+    // of it shown, otherwise it left its level and is matched again, any part that rests again is reported by a
+    // following OrderAdded.
+    OrderAmended {
+        order_id: OrderId,
+        side: Side,
+        account: Option<AccountId>,
+        old_price: Price,
+        old_qty: Quantity,
+        price: Price,
+        qty: Quantity,
+        displayed_qty: Quantity,
+        kept_priority: bool,
+    },
+    // The displayed peak of an iceberg order was refreshed from its reserve to `displayed_qty`. It stays at `price`
+    // with `qty` open but loses its time priority.
+    OrderReplenished {
+        order_id: OrderId,
         side: Side,
+        account: Option<AccountId>,
+        price: Price,
+        qty: Quantity,
+        displayed_qty: Quantity,
     },
-    OrderRemoved {
-        order_id: Uuid,
-        price: u64,
-        qty: u64,
+    // The order expired with `qty` open, `price` is the level it rested at, None if it was a waiting stop order
+    OrderExpired {
+        order_id: OrderId,
         side: Side,
+        account: Option<AccountId>,
+        price: Option<Price>,
+        qty: Quantity,
+    },
+    // The stop order's trigger price was reached and it is matched as its triggered order type
+    StopTriggered {
+        order_id: OrderId,
+        side: Side,
+        account: Option<AccountId>,
+        trigger: Price,
     },
     TradeExecuted(TradeExecution),
+    // The price and volume the call auction would uncross at if it ended now changed, None once the book no longer
+    // crosses
+    IndicativeUncross(Option<Equilibrium>),
+    // A trade at `price` would have printed beyond the dynamic price band around the `reference` last trade price.
+    // Matching halted and the book switched to a volatility auction.
+    CircuitBreakerTripped {
+        price: Price,
+        reference: Option<Price>,
+    },
+    // The market's session moved from the `previous` phase to `phase`
+    SessionChanged {
+        previous: SessionPhase,
+        phase: SessionPhase,
+    },
+}
+
+/// The kind of a notification, used to subscribe to some events only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NotificationKind {
+    OrderAccepted,
+    OrderRejected,
+    OrderAdded,
+    OrderPartiallyFilled,
+    OrderFilled,
+    OrderCancelled,
+    OrderAmended,
+    OrderReplenished,
+    OrderExpired,
+    StopTriggered,
+    TradeExecuted,
+    IndicativeUncross,
+    CircuitBreakerTripped,
+    SessionChanged,
+}
+
+impl Notification {
+    pub fn kind(&self) -> NotificationKind {
+        match self {
+            Notification::OrderAccepted { .. } => NotificationKind::OrderAccepted,
+            Notification::OrderRejected { .. } => NotificationKind::OrderRejected,
+            Notification::OrderAdded { .. } => NotificationKind::OrderAdded,
+            Notification::OrderPartiallyFilled { .. } => NotificationKind::OrderPartiallyFilled,
+            Notification::OrderFilled { .. } => NotificationKind::OrderFilled,
+            Notification::OrderCancelled { .. } => NotificationKind::OrderCancelled,
+            Notification::OrderAmended { .. } => NotificationKind::OrderAmended,
+            Notification::OrderReplenished { .. } => NotificationKind::OrderReplenished,
+            Notification::OrderExpired { .. } => NotificationKind::OrderExpired,
+            Notification::StopTriggered { .. } => NotificationKind::StopTriggered,
+            Notification::TradeExecuted(_) => NotificationKind::TradeExecuted,
+            Notification::IndicativeUncross(_) => NotificationKind::IndicativeUncross,
+            Notification::CircuitBreakerTripped { .. } => NotificationKind::CircuitBreakerTripped,
+            Notification::SessionChanged { .. } => NotificationKind::SessionChanged,
+        }
+    }
+
+    /// Returns the orders the event concerns with their accounts, a trade concerns both the taker and the maker.
+    /// Returns None for events of the whole market.
+    pub fn parties(&self) -> Option<[(OrderId, Option<AccountId>); 2]> {
+        match self {
+            Notification::OrderAccepted {
+                order_id, account, ..
+            }
+            | Notification::OrderRejected {
+                order_id, account, ..
+            }
+            | Notification::OrderAdded {
+                order_id, account, ..
+            }
+            | Notification::OrderPartiallyFilled {
+                order_id, account, ..
+            }
+            | Notification::OrderFilled {
+                order_id, account, ..
+            }
+            | Notification::OrderCancelled {
+                order_id, account, ..
+            }
+            | Notification::OrderAmended {
+                order_id, account, ..
+            }
+            | Notification::OrderReplenished {
+                order_id, account, ..
+            }
+            | Notification::OrderExpired {
+                order_id, account, ..
+            }
+            | Notification::StopTriggered {
+                order_id, account, ..
+            } => Some([(*order_id, *account); 2]),
+            Notification::TradeExecuted(trade) => Some([
+                (trade.taker_order_id, trade.taker_account),
+                (trade.maker_order_id, trade.maker_account),
+            ]),
+            Notification::IndicativeUncross(_)
+            | Notification::CircuitBreakerTripped { .. }
+            | Notification::SessionChanged { .. } => None,
+        }
+    }
+}
+
+/// EventSink receives the notifications of an order book as they happen.
+pub trait EventSink: Debug + Send {
+    fn emit(&mut self, notification: Notification);
+}
+
+/// Discards every notification, the sink an order book starts with.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullSink;
+
+impl EventSink for NullSink {
+    fn emit(&mut self, _notification: Notification) {}
+}
+
+impl EventSink for Vec<Notification> {
+    fn emit(&mut self, notification: Notification) {
+        self.push(notification);
+    }
+}
+
+impl EventSink for Sender<Notification> {
+    fn emit(&mut self, notification: Notification) {
+        // Nobody is listening once the receiver is dropped
+        let _ = self.send(notification);
+    }
+}
+
+/// Publisher is the event sink of one market's order book, forwarding its notifications to a NotificationHandler.
+#[derive(Debug, Clone)]
+pub struct Publisher {
+    pair: TradingPair,
+    sender: Sender<(TradingPair, Notification)>,
+}
+
+impl EventSink for Publisher {
+    fn emit(&mut self, notification: Notification) {
+        // The handler has gone away, the book keeps trading without it
+        let _ = self.sender.send((self.pair.clone(), notification));
+    }
+}
+
+/// What happens when a subscriber's channel is full.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SlowConsumerPolicy {
+    // Drop the notification for this subscriber and count it
+    #[default]
+    Drop,
+    // Wait until the subscriber makes room, holding up every other subscriber
+    Block,
+    // Remove the subscriber, its receiver is disconnected once it has drained the channel
+    Disconnect,
+}
+
+/// Subscription selects the notifications a subscriber receives and how they are delivered.
+///
+/// A filter that is left empty matches everything, otherwise a notification must match one of its values. A notification
+/// is delivered if it matches every filter.
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    markets: HashSet<TradingPair>,
+    kinds: HashSet<NotificationKind>,
+    orders: HashSet<OrderId>,
+    accounts: HashSet<AccountId>,
+    // Notifications buffered for the subscriber before the policy applies
+    capacity: usize,
+    policy: SlowConsumerPolicy,
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Self::all()
+    }
 }
 
+impl Subscription {
+    pub const DEFAULT_CAPACITY: usize = 1024;
+
+    /// Subscribes to every notification of every market.
+    pub fn all() -> Self {
+        Self {
+            markets: HashSet::new(),
+            kinds: HashSet::new(),
+            orders: HashSet::new(),
+            accounts: HashSet::new(),
+            capacity: Self::DEFAULT_CAPACITY,
+            policy: SlowConsumerPolicy::default(),
+        }
+    }
+
+    pub fn market(mut self, pair: TradingPair) -> Self {
+        self.markets.insert(pair);
+        self
+    }
+
+    pub fn kind(mut self, kind: NotificationKind) -> Self {
+        self.kinds.insert(kind);
+        self
+    }
+
+    pub fn order(mut self, order_id: OrderId) -> Self {
+        self.orders.insert(order_id);
+        self
+    }
+
+    pub fn account(mut self, account: AccountId) -> Self {
+        self.accounts.insert(account);
+        self
+    }
+
+    /// Buffers at most `capacity` notifications for the subscriber, applying `policy` once they are not read fast enough.
+    pub fn bounded(mut self, capacity: usize, policy: SlowConsumerPolicy) -> Self {
+        self.capacity = capacity;
+        self.policy = policy;
+        self
+    }
+
+    pub fn matches(&self, pair: &TradingPair, notification: &Notification) -> bool {
+        let parties = notification.parties();
+        let parties = parties.as_ref().map_or(&[][..], |parties| &parties[..]);
+        (self.markets.is_empty() || self.markets.contains(pair))
+            && (self.kinds.is_empty() || self.kinds.contains(&notification.kind()))
+            && (self.orders.is_empty() || parties.iter().any(|(id, _)| self.orders.contains(id)))
+            && (self.accounts.is_empty()
+                || parties
+                    .iter()
+                    .any(|(_, account)| account.is_some_and(|a| self.accounts.contains(&a))))
+    }
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    subscription: Subscription,
+    sender: Sender<(TradingPair, Notification)>,
+    // Notifications dropped because the channel was full
+    dropped: u64,
+}
+
+impl Subscriber {
+    // Delivers the notification according to the policy, returns false once the subscriber should be removed
+    fn deliver(&mut self, event: (TradingPair, Notification)) -> bool {
+        match self.subscription.policy {
+            SlowConsumerPolicy::Block => self.sender.send(event).is_ok(),
+            SlowConsumerPolicy::Drop => match self.sender.try_send(event) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    self.dropped += 1;
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            },
+            SlowConsumerPolicy::Disconnect => self.sender.try_send(event).is_ok(),
+        }
+    }
+}
+
+/// NotificationHandler fans the notifications published by order books out to the subscribers they match.
+///
+/// Books publish through a `Publisher` per market, subscribers receive `(TradingPair, Notification)` pairs on a bounded
+/// channel of their own. Subscribers whose receiver has been dropped are removed on the next delivery.
 pub struct NotificationHandler {
-    sender: Sender<(Uuid, Notification)>,
-    receiver: Receiver<(Uuid, Notification)>,
-    subscribers: HashMap<Uuid, Sender<Notification>>,
+    sender: Sender<(TradingPair, Notification)>,
+    receiver: Receiver<(TradingPair, Notification)>,
+    subscribers: HashMap<Uuid, Subscriber>,
 }
 
 impl Default for NotificationHandler {
@@ -43,14 +389,32 @@ impl NotificationHandler {
         }
     }
 
-    pub fn get_sender(&self) -> Sender<(Uuid, Notification)> {
+    pub fn get_sender(&self) -> Sender<(TradingPair, Notification)> {
         self.sender.clone()
     }
 
-    pub fn subscribe(&mut self) -> (Uuid, Receiver<Notification>) {
+    /// Returns the event sink for the order book of `pair`.
+    pub fn publisher(&self, pair: TradingPair) -> Publisher {
+        Publisher {
+            pair,
+            sender: self.get_sender(),
+        }
+    }
+
+    pub fn subscribe(
+        &mut self,
+        subscription: Subscription,
+    ) -> (Uuid, Receiver<(TradingPair, Notification)>) {
         let id = Uuid::new_v4();
-        let (sender, receiver) = unbounded();
-        self.subscribers.insert(id, sender);
+        let (sender, receiver) = bounded(subscription.capacity);
+        self.subscribers.insert(
+            id,
+            Subscriber {
+                subscription,
+                sender,
+                dropped: 0,
+            },
+        );
         (id, receiver)
     }
 
@@ -58,11 +422,164 @@ impl NotificationHandler {
         self.subscribers.remove(&id);
     }
 
-    pub fn run(&mut self) {
-        while let Ok((subscriber_id, notification)) = self.receiver.recv() {
-            if let Some(sender) = self.subscribers.get(&subscriber_id) {
-                sender.send(notification).unwrap();
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    /// Returns the number of notifications dropped for a subscriber with the `Drop` policy.
+    pub fn dropped(&self, id: Uuid) -> Option<u64> {
+        self.subscribers
+            .get(&id)
+            .map(|subscriber| subscriber.dropped)
+    }
+
+    /// Delivers the notification to every subscriber it matches.
+    pub fn publish(&mut self, pair: &TradingPair, notification: &Notification) {
+        self.subscribers.retain(|id, subscriber| {
+            if !subscriber.subscription.matches(pair, notification) {
+                return true;
             }
+            let keep = subscriber.deliver((pair.clone(), notification.clone()));
+            if !keep {
+                warn!("Removing subscriber {}", id);
+            }
+            keep
+        });
+    }
+
+    /// Publishes the notifications queued by the publishers without waiting for more, returns how many there were.
+    pub fn process_pending(&mut self) -> usize {
+        let mut count = 0;
+        while let Ok((pair, notification)) = self.receiver.try_recv() {
+            self.publish(&pair, &notification);
+            count += 1;
+        }
+        count
+    }
+
+    pub fn run(&mut self) {
+        while let Ok((pair, notification)) = self.receiver.recv() {
+            self.publish(&pair, &notification);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OrderBook, OrderRequest};
+
+    fn pair(base: &str) -> TradingPair {
+        TradingPair::new(base.to_string(), "USD".to_string())
+    }
+
+    fn added(account: Option<AccountId>) -> Notification {
+        Notification::OrderAdded {
+            order_id: Uuid::new_v4(),
+            side: Side::Bid,
+            account,
+            price: 10.into(),
+            qty: 1.into(),
+            displayed_qty: 1.into(),
+        }
+    }
+
+    #[test]
+    fn test_subscription_filters() {
+        let btc = pair("BTC");
+        let notification = added(Some(7));
+        assert!(Subscription::all().matches(&btc, &notification));
+        assert!(Subscription::all()
+            .market(pair("ETH"))
+            .market(btc.clone())
+            .kind(NotificationKind::OrderAdded)
+            .account(7)
+            .matches(&btc, &notification));
+        assert!(!Subscription::all()
+            .market(pair("ETH"))
+            .matches(&btc, &notification));
+        assert!(!Subscription::all()
+            .kind(NotificationKind::TradeExecuted)
+            .matches(&btc, &notification));
+        assert!(!Subscription::all()
+            .order(Uuid::new_v4())
+            .matches(&btc, &notification));
+        assert!(!Subscription::all().account(8).matches(&btc, &added(None)));
+    }
+
+    #[test]
+    fn test_fan_out_from_order_books() {
+        let mut handler = NotificationHandler::new();
+        let (_, all) = handler.subscribe(Subscription::all());
+        let (_, trades) =
+            handler.subscribe(Subscription::all().kind(NotificationKind::TradeExecuted));
+        let (_, maker) = handler.subscribe(Subscription::all().account(1));
+
+        let mut book = OrderBook::default().with_event_sink(handler.publisher(pair("BTC")));
+        book.add_order(OrderRequest::new(Side::Ask, 5, OrderType::limit(10)).account(1))
+            .unwrap();
+        book.add_order(OrderRequest::new(Side::Bid, 5, OrderType::Market).account(2))
+            .unwrap();
+
+        let published = handler.process_pending();
+        assert_eq!(all.len(), published);
+        let trade: Vec<_> = trades.try_iter().collect();
+        assert_eq!(trade.len(), 1);
+        assert_eq!(trade[0].0, pair("BTC"));
+        // The account sees its own order's events and the trade it was part of
+        assert_eq!(
+            maker
+                .try_iter()
+                .map(|(_, notification)| notification.kind())
+                .collect::<Vec<_>>(),
+            [
+                NotificationKind::OrderAccepted,
+                NotificationKind::OrderAdded,
+                NotificationKind::TradeExecuted,
+                NotificationKind::OrderFilled,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dropped_receivers_are_removed() {
+        let mut handler = NotificationHandler::new();
+        let (_, receiver) = handler.subscribe(Subscription::all());
+        let (_, other) = handler.subscribe(Subscription::all().market(pair("ETH")));
+        drop(receiver);
+        drop(other);
+
+        handler.publish(&pair("BTC"), &added(None));
+        // Only subscribers a notification was delivered to notice the receiver is gone
+        assert_eq!(handler.subscriber_count(), 1);
+        handler.publish(&pair("ETH"), &added(None));
+        assert_eq!(handler.subscriber_count(), 0);
+    }
+
+    #[test]
+    fn test_slow_consumer_policies() {
+        let mut handler = NotificationHandler::new();
+        let (dropping, drop_receiver) =
+            handler.subscribe(Subscription::all().bounded(2, SlowConsumerPolicy::Drop));
+        let (disconnected, disconnect_receiver) =
+            handler.subscribe(Subscription::all().bounded(2, SlowConsumerPolicy::Disconnect));
+
+        for _ in 0..3 {
+            handler.publish(&pair("BTC"), &added(None));
+        }
+        assert_eq!(handler.dropped(dropping), Some(1));
+        assert_eq!(drop_receiver.len(), 2);
+        assert_eq!(handler.dropped(disconnected), None);
+        // The buffered notifications can still be read before the channel reports the disconnect
+        assert_eq!(disconnect_receiver.try_iter().count(), 2);
+        assert!(disconnect_receiver.recv().is_err());
+
+        let (_, block_receiver) =
+            handler.subscribe(Subscription::all().bounded(1, SlowConsumerPolicy::Block));
+        let reader = std::thread::spawn(move || block_receiver.iter().take(3).count());
+        for _ in 0..3 {
+            handler.publish(&pair("BTC"), &added(None));
         }
+        assert_eq!(reader.join().unwrap(), 3);
     }
 }
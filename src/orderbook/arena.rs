@@ -0,0 +1,263 @@
+use ahash::AHashMap as HashMap;
+
+use super::orders::TradeOrder;
+use super::types::*;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+/// PriceLevel is the FIFO queue of orders resting at one price.
+/// The orders live in an `OrderArena`, the level only holds the ends of the doubly linked list through them.
+pub struct PriceLevel {
+    head: Option<usize>,
+    tail: Option<usize>,
+    len: usize,
+}
+
+impl PriceLevel {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[derive(Debug)]
+struct Slot<P: PriceType> {
+    order: TradeOrder<P::Qty>,
+    // Price of the level the order is queued at
+    price: P,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+#[derive(Debug)]
+/// OrderArena is a slab holding every order of a HalfBook.
+///
+/// Orders of a price level are linked to their neighbours in the queue, and looked up by id through an index into the
+/// slab, so an order can be found, modified or unlinked from anywhere in its level in O(1).
+/// Freed slots are reused by later orders.
+pub struct OrderArena<P: PriceType> {
+    slots: Vec<Option<Slot<P>>>,
+    free: Vec<usize>,
+    index: HashMap<OrderId, usize>,
+}
+
+impl<P: PriceType> Default for OrderArena<P> {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl<P: PriceType> OrderArena<P> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(capacity),
+            free: Vec::new(),
+            index: HashMap::with_capacity(capacity),
+        }
+    }
+
+    /// Queues the order at the back of the level.
+    pub fn push_back(&mut self, level: &mut PriceLevel, price: P, order: TradeOrder<P::Qty>) {
+        let key = self.insert(Slot {
+            order,
+            price,
+            prev: None,
+            next: None,
+        });
+        self.attach_back(level, key);
+    }
+
+    /// Removes the order from its level, `level` must be the level the order is queued at.
+    pub fn remove(
+        &mut self,
+        level: &mut PriceLevel,
+        order_id: &OrderId,
+    ) -> Option<TradeOrder<P::Qty>> {
+        let key = *self.index.get(order_id)?;
+        Some(self.unlink(level, key))
+    }
+
+    /// Moves the order to the back of its level, losing time priority. `level` must be the level the order is queued at.
+    pub fn move_to_back(&mut self, level: &mut PriceLevel, order_id: &OrderId) {
+        if let Some(key) = self.index.get(order_id).copied() {
+            self.detach(level, key);
+            self.attach_back(level, key);
+        }
+    }
+
+    pub fn front(&self, level: &PriceLevel) -> Option<&TradeOrder<P::Qty>> {
+        level.head.map(|key| &self.slot(key).order)
+    }
+
+    pub fn front_mut(&mut self, level: &PriceLevel) -> Option<&mut TradeOrder<P::Qty>> {
+        level.head.map(|key| &mut self.slot_mut(key).order)
+    }
+
+    pub fn get(&self, order_id: &OrderId) -> Option<&TradeOrder<P::Qty>> {
+        self.index.get(order_id).map(|key| &self.slot(*key).order)
+    }
+
+    pub fn get_mut(&mut self, order_id: &OrderId) -> Option<&mut TradeOrder<P::Qty>> {
+        let key = *self.index.get(order_id)?;
+        Some(&mut self.slot_mut(key).order)
+    }
+
+    /// Returns the price of the level the order is queued at.
+    pub fn price_of(&self, order_id: &OrderId) -> Option<P> {
+        self.index.get(order_id).map(|key| self.slot(*key).price)
+    }
+
+    /// Iterates over the orders of the level in time priority.
+    pub fn iter<'a>(&'a self, level: &PriceLevel) -> LevelIter<'a, P> {
+        LevelIter {
+            arena: self,
+            next: level.head,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.free.clear();
+        self.index.clear();
+    }
+
+    fn insert(&mut self, slot: Slot<P>) -> usize {
+        let order_id = slot.order.id;
+        debug_assert!(!self.index.contains_key(&order_id));
+        let key = match self.free.pop() {
+            Some(key) => {
+                self.slots[key] = Some(slot);
+                key
+            }
+            None => {
+                self.slots.push(Some(slot));
+                self.slots.len() - 1
+            }
+        };
+        self.index.insert(order_id, key);
+        key
+    }
+
+    fn unlink(&mut self, level: &mut PriceLevel, key: usize) -> TradeOrder<P::Qty> {
+        self.detach(level, key);
+        let slot = self.slots[key].take().expect("indexed slot is occupied");
+        self.index.remove(&slot.order.id);
+        self.free.push(key);
+        slot.order
+    }
+
+    // Links the slot in as the tail of the level
+    fn attach_back(&mut self, level: &mut PriceLevel, key: usize) {
+        let slot = self.slot_mut(key);
+        slot.prev = level.tail;
+        slot.next = None;
+        match level.tail {
+            Some(tail) => self.slot_mut(tail).next = Some(key),
+            None => level.head = Some(key),
+        }
+        level.tail = Some(key);
+        level.len += 1;
+    }
+
+    // Unlinks the slot from its neighbours, leaving it in the slab
+    fn detach(&mut self, level: &mut PriceLevel, key: usize) {
+        let (prev, next) = {
+            let slot = self.slot(key);
+            (slot.prev, slot.next)
+        };
+        match prev {
+            Some(prev) => self.slot_mut(prev).next = next,
+            None => level.head = next,
+        }
+        match next {
+            Some(next) => self.slot_mut(next).prev = prev,
+            None => level.tail = prev,
+        }
+        level.len -= 1;
+    }
+
+    fn slot(&self, key: usize) -> &Slot<P> {
+        self.slots[key].as_ref().expect("linked slot is occupied")
+    }
+
+    fn slot_mut(&mut self, key: usize) -> &mut Slot<P> {
+        self.slots[key].as_mut().expect("linked slot is occupied")
+    }
+}
+
+/// Iterator over the orders of a price level, front to back.
+pub struct LevelIter<'a, P: PriceType> {
+    arena: &'a OrderArena<P>,
+    next: Option<usize>,
+}
+
+impl<'a, P: PriceType> Iterator for LevelIter<'a, P> {
+    type Item = &'a TradeOrder<P::Qty>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let slot = self.arena.slot(self.next?);
+        self.next = slot.next;
+        Some(&slot.order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(arena: &OrderArena<Price>, level: &PriceLevel) -> Vec<OrderId> {
+        arena.iter(level).map(|o| o.id).collect()
+    }
+
+    #[test]
+    fn test_arena_fifo_and_remove() {
+        let mut arena = OrderArena::<Price>::default();
+        let mut level = PriceLevel::default();
+        let orders: Vec<_> = (1..=4).map(TradeOrder::new).collect();
+        let order_ids: Vec<_> = orders.iter().map(|o| o.id).collect();
+        for order in orders {
+            arena.push_back(&mut level, 10.into(), order);
+        }
+        assert_eq!(level.len(), 4);
+        assert_eq!(ids(&arena, &level), order_ids);
+
+        // Remove from the middle, the front and the back
+        assert!(arena.remove(&mut level, &order_ids[1]).is_some());
+        assert!(arena.remove(&mut level, &order_ids[0]).is_some());
+        assert!(arena.remove(&mut level, &order_ids[3]).is_some());
+        assert!(arena.remove(&mut level, &order_ids[3]).is_none());
+        assert_eq!(ids(&arena, &level), [order_ids[2]]);
+        assert_eq!(arena.front_mut(&level).unwrap().id, order_ids[2]);
+        assert_eq!(arena.len(), 1);
+    }
+
+    #[test]
+    fn test_arena_move_to_back_and_slot_reuse() {
+        let mut arena = OrderArena::<Price>::default();
+        let mut level = PriceLevel::default();
+        let first = TradeOrder::new(1);
+        let second = TradeOrder::new(2);
+        let (first_id, second_id) = (first.id, second.id);
+        arena.push_back(&mut level, 10.into(), first);
+        arena.push_back(&mut level, 10.into(), second);
+
+        arena.move_to_back(&mut level, &first_id);
+        assert_eq!(ids(&arena, &level), [second_id, first_id]);
+        assert_eq!(arena.price_of(&first_id), Some(10.into()));
+
+        arena.remove(&mut level, &second_id);
+        let third = TradeOrder::new(3);
+        let third_id = third.id;
+        arena.push_back(&mut level, 10.into(), third);
+        assert_eq!(arena.slots.len(), 2);
+        assert_eq!(ids(&arena, &level), [first_id, third_id]);
+        arena.get_mut(&third_id).unwrap().remaining_qty = 5.into();
+        assert_eq!(arena.get(&third_id).unwrap().remaining_qty, 5.into());
+    }
+}
@@ -0,0 +1,153 @@
+use std::cmp::Ordering;
+
+use rust_decimal::Decimal;
+
+use super::types::*;
+
+/// Equilibrium is the single price a call auction uncrosses at, with the volume it executes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Equilibrium {
+    pub price: Price,
+    // Quantity executed at the price
+    pub volume: Quantity,
+    // Quantity left unexecuted on the side with the surplus at the price
+    pub imbalance: Quantity,
+    // Side with more quantity than can execute, None if both sides execute in full
+    pub surplus: Option<Side>,
+}
+
+/// Finds the equilibrium price of a crossed book from the total open quantity of each level, in any order.
+///
+/// The price executing the most volume wins, ties go to the price leaving the smallest imbalance, then to the price
+/// closest to `reference`, then to the lowest price. Returns None if no volume can execute.
+pub fn find_equilibrium(
+    bids: &[(Price, Quantity)],
+    asks: &[(Price, Quantity)],
+    reference: Option<Price>,
+) -> Option<Equilibrium> {
+    let mut bids = bids.to_vec();
+    let mut asks = asks.to_vec();
+    bids.sort_by_key(|(price, _)| *price);
+    asks.sort_by_key(|(price, _)| *price);
+    // Quantity bid at or above each bid price, and offered at or below each ask price
+    let mut bid_volume = vec![Decimal::ZERO; bids.len() + 1];
+    for (i, (_, qty)) in bids.iter().enumerate().rev() {
+        bid_volume[i] = bid_volume[i + 1] + qty;
+    }
+    let mut ask_volume = vec![Decimal::ZERO; asks.len() + 1];
+    for (i, (_, qty)) in asks.iter().enumerate() {
+        ask_volume[i + 1] = ask_volume[i] + qty;
+    }
+
+    let distance = |price: Price| reference.map(|reference| (price - reference).abs());
+    let mut best: Option<Equilibrium> = None;
+    for price in bids.iter().chain(&asks).map(|(price, _)| *price) {
+        let demand = bid_volume[bids.partition_point(|(p, _)| *p < price)];
+        let supply = ask_volume[asks.partition_point(|(p, _)| *p <= price)];
+        let volume = demand.min(supply);
+        if volume == Decimal::ZERO {
+            continue;
+        }
+        let candidate = Equilibrium {
+            price,
+            volume,
+            imbalance: (demand - supply).abs(),
+            surplus: match demand.cmp(&supply) {
+                Ordering::Greater => Some(Side::Bid),
+                Ordering::Less => Some(Side::Ask),
+                Ordering::Equal => None,
+            },
+        };
+        let better = best.is_none_or(|best| {
+            candidate
+                .volume
+                .cmp(&best.volume)
+                .then(best.imbalance.cmp(&candidate.imbalance))
+                .then(distance(best.price).cmp(&distance(candidate.price)))
+                .then(best.price.cmp(&candidate.price))
+                == Ordering::Greater
+        });
+        if better {
+            best = Some(candidate);
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(levels: &[(i64, i64)]) -> Vec<(Price, Quantity)> {
+        levels
+            .iter()
+            .map(|(price, qty)| (Price::from(*price), Quantity::from(*qty)))
+            .collect()
+    }
+
+    #[test]
+    fn test_equilibrium_maximises_volume() {
+        let bids = levels(&[(102, 10), (101, 20), (100, 30)]);
+        let asks = levels(&[(99, 15), (100, 10), (101, 20)]);
+        // At 101 there are 30 bid and 45 offered, at 100 60 bid and 25 offered
+        let equilibrium = find_equilibrium(&bids, &asks, None).unwrap();
+        assert_eq!(
+            equilibrium,
+            Equilibrium {
+                price: 101.into(),
+                volume: 30.into(),
+                imbalance: 15.into(),
+                surplus: Some(Side::Ask),
+            }
+        );
+    }
+
+    #[test]
+    fn test_equilibrium_minimises_imbalance() {
+        // 99 and 101 both execute 10, 101 leaves nothing over
+        let bids = levels(&[(101, 10), (99, 5)]);
+        let asks = levels(&[(99, 10), (102, 5)]);
+        let equilibrium = find_equilibrium(&bids, &asks, None).unwrap();
+        assert_eq!(equilibrium.price, 101.into());
+        assert_eq!(equilibrium.volume, 10.into());
+        assert_eq!(equilibrium.imbalance, 0.into());
+
+        let bids = levels(&[(101, 10)]);
+        let asks = levels(&[(99, 10), (100, 3)]);
+        let equilibrium = find_equilibrium(&bids, &asks, None).unwrap();
+        assert_eq!(equilibrium.price, 99.into());
+        assert_eq!(equilibrium.surplus, None);
+    }
+
+    #[test]
+    fn test_equilibrium_reference_price_tie_break() {
+        // Every price from 99 to 101 executes 10 with no imbalance
+        let bids = levels(&[(101, 10)]);
+        let asks = levels(&[(99, 10)]);
+        assert_eq!(
+            find_equilibrium(&bids, &asks, Some(105.into()))
+                .unwrap()
+                .price,
+            101.into()
+        );
+        assert_eq!(
+            find_equilibrium(&bids, &asks, Some(90.into()))
+                .unwrap()
+                .price,
+            99.into()
+        );
+        assert_eq!(
+            find_equilibrium(&bids, &asks, None).unwrap().price,
+            99.into()
+        );
+    }
+
+    #[test]
+    fn test_equilibrium_of_uncrossed_book() {
+        let bids = levels(&[(99, 10)]);
+        let asks = levels(&[(100, 10)]);
+        assert_eq!(find_equilibrium(&bids, &asks, None), None);
+        assert_eq!(find_equilibrium(&[], &asks, None), None);
+    }
+}
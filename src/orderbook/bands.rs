@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+
+use super::types::*;
+
+/// What happens to the open quantity of an order that stopped matching at a price band.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RemainderPolicy {
+    // The open quantity is cancelled
+    #[default]
+    Cancel,
+    // Orders with a limit price rest on the book, joining the volatility auction if a circuit breaker tripped.
+    // Market orders are cancelled as they can not rest.
+    Rest,
+}
+
+/// Which band a trade would have printed beyond.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BandBreach {
+    Static,
+    Dynamic,
+}
+
+/// PriceBands protect an order book from trades printing far from where it has been trading.
+///
+/// The static band is fixed around a reference price, orders priced outside it are rejected and no trade prints beyond
+/// it. The dynamic band moves with the last trade price, a trade that would print beyond it trips a circuit breaker
+/// that halts matching and switches the book to a volatility auction. Bands are fractions of their reference price,
+/// 0.1 is 10%. `PriceBands::default()` has no bands.
+///
+/// PriceBands are the trade-time check of a market, applied by the book to every trade as it matches, with the static
+/// band also screening the prices of the orders the book receives. The pre-trade check of order prices against the
+/// last trade is the price band of the market's `InstrumentSpec`, applied by the `MatchingEngine` before the order
+/// reaches the book.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriceBands {
+    pub reference_price: Option<Price>,
+    pub static_band: Option<Decimal>,
+    pub dynamic_band: Option<Decimal>,
+    pub remainder: RemainderPolicy,
+    // How long a volatility auction runs before the `MatchingEngine` returns the market to continuous trading,
+    // None leaves it to the operator
+    pub volatility_auction: Option<Duration>,
+}
+
+impl PriceBands {
+    pub fn static_band(
+        mut self,
+        reference_price: impl Into<Price>,
+        band: impl Into<Decimal>,
+    ) -> Self {
+        self.reference_price = Some(reference_price.into());
+        self.static_band = Some(band.into());
+        self
+    }
+
+    pub fn dynamic_band(mut self, band: impl Into<Decimal>) -> Self {
+        self.dynamic_band = Some(band.into());
+        self
+    }
+
+    pub fn remainder(mut self, remainder: RemainderPolicy) -> Self {
+        self.remainder = remainder;
+        self
+    }
+
+    pub fn volatility_auction(mut self, duration: Duration) -> Self {
+        self.volatility_auction = Some(duration);
+        self
+    }
+
+    /// Returns whether an order can be priced at `price`, the static band's reference is not moved by trading.
+    pub fn accepts_price(&self, price: &Price) -> bool {
+        within(price, self.reference_price, self.static_band)
+    }
+
+    /// Checks a trade at `price` against the bands, the dynamic band is measured from `last_trade_price`.
+    /// A trade beyond both bands trips the circuit breaker.
+    pub fn check_trade(
+        &self,
+        price: &Price,
+        last_trade_price: Option<Price>,
+    ) -> Option<BandBreach> {
+        if !within(price, last_trade_price, self.dynamic_band) {
+            Some(BandBreach::Dynamic)
+        } else if !self.accepts_price(price) {
+            Some(BandBreach::Static)
+        } else {
+            None
+        }
+    }
+}
+
+// Without a reference or a band every price is inside
+fn within(price: &Price, reference: Option<Price>, band: Option<Decimal>) -> bool {
+    match (reference, band) {
+        (Some(reference), Some(band)) => (*price - reference).abs() <= reference * band,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_static_band() {
+        let bands = PriceBands::default().static_band(100, Decimal::new(1, 1));
+        assert!(bands.accepts_price(&90.into()));
+        assert!(bands.accepts_price(&110.into()));
+        assert!(!bands.accepts_price(&111.into()));
+        assert_eq!(
+            bands.check_trade(&89.into(), None),
+            Some(BandBreach::Static)
+        );
+        assert!(PriceBands::default().accepts_price(&1_000_000.into()));
+    }
+
+    #[test]
+    fn test_dynamic_band() {
+        let bands = PriceBands::default()
+            .static_band(100, Decimal::new(2, 1))
+            .dynamic_band(Decimal::new(5, 2));
+        assert_eq!(bands.check_trade(&110.into(), None), None);
+        assert_eq!(bands.check_trade(&105.into(), Some(100.into())), None);
+        assert_eq!(
+            bands.check_trade(&106.into(), Some(100.into())),
+            Some(BandBreach::Dynamic)
+        );
+        assert_eq!(
+            bands.check_trade(&130.into(), Some(100.into())),
+            Some(BandBreach::Dynamic)
+        );
+        assert_eq!(
+            bands.check_trade(&121.into(), Some(118.into())),
+            Some(BandBreach::Static)
+        );
+    }
+}
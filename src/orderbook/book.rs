@@ -1,246 +1,636 @@
-use rust_decimal::Decimal;
+use rust_decimal::{Decimal, RoundingStrategy};
 
 use tracing::{info, warn};
 
+use crate::errors::{OrderBookError, Result};
+use crate::notifications::{EventSink, Notification, NullSink};
+
+use super::arena::{OrderArena, PriceLevel};
+use super::auction::{find_equilibrium, Equilibrium};
+use super::bands::{BandBreach, PriceBands, RemainderPolicy};
+use super::matching::{Fifo, MatchingPolicy};
 use super::orders::*;
 use super::price_levels::SparseVec;
+use super::snapshot::{LevelSnapshot, OrderBookSnapshot, OrderSnapshot};
+use super::triggers::TriggerBook;
 use super::types::*;
 
-use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
 
+/// HalfBook holds the price levels of one side of an order book.
+///
+/// Levels are keyed by `P` and orders hold their quantities as `P::Qty`. The methods the `OrderBook` uses to change the
+/// book take the keys it converted on entry, the queries take and return `Price` and `Quantity`.
 #[derive(Debug)]
-pub struct HalfBook {
+pub struct HalfBook<P: PriceType = Price> {
     s: Side,
-    // Price & Index of price Level
-    price_set: BTreeSet<Price>,
-    price_levels: SparseVec<Price, PriceLevel>,
+    // Decimal places of the prices and quantities, used to convert them to the price type
+    scale: u32,
+    qty_scale: u32,
+    // Price levels, ordered by price with the extremes cached
+    price_levels: SparseVec<P, PriceLevel>,
+    // Every order on this side, linked into the queues of the price levels
+    orders: OrderArena<P>,
+    // How an incoming order is shared among the orders of a level
+    policy: Arc<dyn MatchingPolicy>,
+    // Orders of the current matching pass with their allocations, kept to reuse the allocation
+    pass: Vec<(OrderId, P::Qty)>,
 }
 
 impl HalfBook {
     pub fn new(s: Side) -> HalfBook {
+        Self::with_scale(s, 0, 0)
+    }
+}
+
+impl<P: PriceType> HalfBook<P> {
+    /// Creates an empty half book whose prices have at most `scale` decimal places and quantities `qty_scale`.
+    pub fn with_scale(s: Side, scale: u32, qty_scale: u32) -> Self {
         HalfBook {
             s,
-            price_set: BTreeSet::new(),
+            scale,
+            qty_scale,
             price_levels: SparseVec::with_capacity(10_000),
+            orders: OrderArena::with_capacity(10_000),
+            policy: Arc::new(Fifo),
+            pass: Vec::new(),
         }
     }
 
-    pub fn add_order(&mut self, price: impl Into<Price>, order: TradeOrder) {
-        let price = price.into();
-        if let Some(level) = self.price_levels.get_mut(&price) {
-            level.push_back(order);
-        } else {
-            self.price_set.insert(price);
-            self.price_levels.insert(price, VecDeque::from(vec![order]));
-        }
+    pub fn set_matching_policy(&mut self, policy: Arc<dyn MatchingPolicy>) {
+        self.policy = policy;
     }
 
-    pub fn remove_order(&mut self, price: &Price, order_id: OrderId) -> Option<TradeOrder> {
-        let level = self.price_levels.get_mut(price)?;
-        let removed_order = level
-            .iter()
-            .position(|o| o.id == order_id)
-            .map(|i| level.remove(i))?;
+    /// Converts the price to the key of its level, None if it has more decimal places than the book.
+    pub fn price_key(&self, price: &Price) -> Option<P> {
+        P::from_price(*price, self.scale)
+    }
+
+    pub fn price_of_key(&self, key: &P) -> Price {
+        key.to_price(self.scale)
+    }
+
+    /// Converts the quantity to the book's quantity type, None if it has more decimal places than the book.
+    pub fn qty_key(&self, qty: &Quantity) -> Option<P::Qty> {
+        P::Qty::from_qty(*qty, self.qty_scale)
+    }
+
+    pub fn qty_of_key(&self, qty: P::Qty) -> Quantity {
+        qty.to_qty(self.qty_scale)
+    }
+
+    pub fn add_order(&mut self, key: P, order: TradeOrder<P::Qty>) {
+        let level = self
+            .price_levels
+            .get_or_insert_with(key, PriceLevel::default);
+        self.orders.push_back(level, key, order);
+    }
+
+    pub fn remove_order(&mut self, key: &P, order_id: OrderId) -> Option<TradeOrder<P::Qty>> {
+        if self.orders.price_of(&order_id)? != *key {
+            return None;
+        }
+        let level = self.price_levels.get_mut(key)?;
+        let removed_order = self.orders.remove(level, &order_id);
         if level.is_empty() {
-            self.price_levels.remove(price);
-            self.price_set.remove(price);
+            self.price_levels.remove(key);
         }
         removed_order
     }
 
+    /// Matches the incoming order against the level at `price`, nothing trades if the price is not on the book's scale.
     pub fn match_order(
         &mut self,
-        incoming_order: &mut TradeOrder,
+        incoming_order: &mut TradeOrder<P::Qty>,
         price: impl Into<Price>,
+        ts: Timestamp,
     ) -> Vec<TradeExecution> {
         let price = price.into();
+        let Some(key) = self.price_key(&price) else {
+            return Vec::new();
+        };
+        self.match_order_at(
+            incoming_order,
+            key,
+            price,
+            ts,
+            &mut Vec::new(),
+            &mut NullSink,
+        )
+    }
+
+    /// Matches the incoming order against the price level `key`, trading at `price`, which call auctions set to the one
+    /// price they execute every crossing order at. Every order taken off the level (filled, or cancelled by self-trade
+    /// prevention) is pushed to `removed` and the events of both sides are sent to `sink`.
+    pub fn match_order_at(
+        &mut self,
+        incoming_order: &mut TradeOrder<P::Qty>,
+        key: P,
+        price: Price,
+        ts: Timestamp,
+        removed: &mut Vec<TradeOrder<P::Qty>>,
+        sink: &mut dyn EventSink,
+    ) -> Vec<TradeExecution> {
         let mut executions = Vec::new();
-        if let Some(price_level) = self.price_levels.get_mut(&price) {
-            while !price_level.is_empty() && incoming_order.remaining_qty > Decimal::ZERO {
-                if let Some(mut existing_order) = price_level.pop_front() {
-                    let fill_qty = existing_order.filled_by(incoming_order, price);
-                    executions.push(TradeExecution::new(
-                        fill_qty,
+        let level_price = self.price_of_key(&key);
+        let qty_scale = self.qty_scale;
+        if let Some(price_level) = self.price_levels.get_mut(&key) {
+            // Each pass shares the open quantity among the displayed orders of the level as the policy allocates it.
+            // A pass ends early on a self-trade, and iceberg peaks refreshed during a pass only trade in the next one.
+            let mut pass = std::mem::take(&mut self.pass);
+            while incoming_order.remaining_qty > P::Qty::ZERO {
+                pass.clear();
+                if self.policy.fifo() {
+                    // Only the front order trades in a pass, any order left behind it is at the front in the next one
+                    pass.extend(self.orders.front(price_level).map(|order| {
+                        (
+                            order.id,
+                            order.visible_qty().min(incoming_order.remaining_qty),
+                        )
+                    }));
+                } else {
+                    let (ids, displayed): (Vec<OrderId>, Vec<Quantity>) = self
+                        .orders
+                        .iter(price_level)
+                        .map(|order| (order.id, order.visible_qty().to_qty(qty_scale)))
+                        .unzip();
+                    let allocation = self
+                        .policy
+                        .allocate(&displayed, incoming_order.remaining_qty.to_qty(qty_scale));
+                    // Shares finer than the book's quantities are rounded down, what is left goes in the next pass
+                    pass.extend(ids.into_iter().zip(allocation).map(|(id, qty)| {
+                        let qty = qty.round_dp_with_strategy(qty_scale, RoundingStrategy::ToZero);
+                        (id, P::Qty::from_qty(qty, qty_scale).unwrap_or_default())
+                    }));
+                }
+                let mut traded = false;
+                for &(existing_id, max_qty) in &pass {
+                    if max_qty == P::Qty::ZERO || incoming_order.remaining_qty == P::Qty::ZERO {
+                        continue;
+                    }
+                    let Some(existing_order) = self.orders.get_mut(&existing_id) else {
+                        continue;
+                    };
+                    traded = true;
+                    if incoming_order.is_self_trade(existing_order) {
+                        let incoming_qty = incoming_order.remaining_qty;
+                        let existing_qty = existing_order.remaining_qty;
+                        incoming_order.prevent_self_trade(existing_order);
+                        emit_cancel(
+                            sink,
+                            existing_order,
+                            Some(level_price),
+                            existing_qty,
+                            qty_scale,
+                        );
+                        emit_cancel(sink, incoming_order, None, incoming_qty, qty_scale);
+                        if existing_order.remaining_qty == P::Qty::ZERO {
+                            removed.extend(self.orders.remove(price_level, &existing_id));
+                        }
+                        break;
+                    }
+                    let fill_qty =
+                        existing_order.filled_by_at_most(incoming_order, max_qty, price, ts);
+                    let execution = TradeExecution::new(
+                        fill_qty.to_qty(qty_scale),
                         price,
                         incoming_order,
-                        &existing_order,
+                        existing_order,
                         self.s.opposite(),
-                    ));
-
-                    if existing_order.remaining_qty > Decimal::ZERO {
-                        price_level.push_front(existing_order);
+                        ts,
+                    );
+                    sink.emit(Notification::TradeExecuted(execution.clone()));
+                    executions.push(execution);
+                    emit_fill(sink, existing_order, price, fill_qty, qty_scale);
+                    emit_fill(sink, incoming_order, price, fill_qty, qty_scale);
+
+                    // A consumed iceberg peak is refreshed from its reserve and loses time priority
+                    if existing_order.replenish(ts) {
+                        sink.emit(Notification::OrderReplenished {
+                            order_id: existing_id,
+                            side: existing_order.side,
+                            account: existing_order.account,
+                            price: level_price,
+                            qty: existing_order.remaining_qty.to_qty(qty_scale),
+                            displayed_qty: existing_order.visible_qty().to_qty(qty_scale),
+                        });
+                        self.orders.move_to_back(price_level, &existing_id);
+                    } else if existing_order.remaining_qty == P::Qty::ZERO {
+                        removed.extend(self.orders.remove(price_level, &existing_id));
                     }
                 }
+                if !traded {
+                    break;
+                }
             }
+            self.pass = pass;
             if price_level.is_empty() {
-                self.price_levels.remove(&price);
-                self.price_set.remove(&price);
+                self.price_levels.remove(&key);
             }
         }
         executions
     }
 
-    pub fn best_price(&self) -> Option<Price> {
+    /// Returns the order at the front of the best level, the next one to trade on this side.
+    pub fn best_order_mut(&mut self) -> Option<&mut TradeOrder<P::Qty>> {
+        let key = self.best_key()?;
+        let level = self.price_levels.get(&key)?;
+        self.orders.front_mut(level)
+    }
+
+    /// Returns the key of the best level.
+    pub fn best_key(&self) -> Option<P> {
         match self.s {
             Side::Ask => self.price_levels.min_index(),
             Side::Bid => self.price_levels.max_index(),
         }
+        .copied()
+    }
+
+    pub fn best_price(&self) -> Option<Price> {
+        self.best_key().map(|key| self.price_of_key(&key))
+    }
+
+    /// Returns the key of the best level worse than `after`, or of the best level without one.
+    pub fn next_key(&self, after: Option<P>) -> Option<P> {
+        let Some(after) = after else {
+            return self.best_key();
+        };
+        self.price_levels
+            .keys(self.s == Side::Bid)
+            .find(|key| match self.s {
+                Side::Ask => **key > after,
+                Side::Bid => **key < after,
+            })
+            .copied()
+    }
+
+    pub fn has_level(&self, key: &P) -> bool {
+        self.price_levels.get(key).is_some()
     }
 
     pub fn get_price_level(&self, price: &Price) -> Option<&PriceLevel> {
-        self.price_levels.get(price)
+        self.price_levels.get(&self.price_key(price)?)
     }
 
-    // TODO: Improve this
-    pub fn iter_prices(&self) -> impl Iterator<Item = Price> {
-        match self.s {
-            Side::Ask => self
-                .price_set
-                .iter()
-                .cloned()
-                .collect::<Vec<_>>()
-                .into_iter(),
-            Side::Bid => self
-                .price_set
-                .iter()
-                .rev()
-                .cloned()
-                .collect::<Vec<_>>()
-                .into_iter(),
-        }
+    // Prices from the best to the worst
+    pub fn iter_prices(&self) -> impl Iterator<Item = Price> + '_ {
+        self.price_levels
+            .keys(self.s == Side::Bid)
+            .map(|key| self.price_of_key(key))
     }
 
-    pub fn show_depth(&self) {
-        let prices: Vec<_> = match self.s {
-            Side::Ask => self.price_set.iter().rev().cloned().collect(),
-            Side::Bid => self.price_set.iter().rev().cloned().collect(),
-        };
-        self.print_price_levels(prices.iter());
+    // Displayed quantity of each level from the best price to the worst
+    pub fn iter_levels(&self) -> impl Iterator<Item = (Price, Quantity)> + '_ {
+        self.price_levels.keys(self.s == Side::Bid).map(|key| {
+            let qty = self.level_qty(key, |o| o.visible_qty());
+            (self.price_of_key(key), self.qty_of_key(qty))
+        })
+    }
+
+    // Open quantity of each level including iceberg reserves, from the best price to the worst
+    pub fn iter_open_levels(&self) -> impl Iterator<Item = (Price, Quantity)> + '_ {
+        self.price_levels.keys(self.s == Side::Bid).map(|key| {
+            let qty = self.level_qty(key, |o| o.remaining_qty);
+            (self.price_of_key(key), self.qty_of_key(qty))
+        })
+    }
+
+    /// Iterates over the levels from the best price to the worst, with their orders in time priority.
+    pub fn iter_orders(
+        &self,
+    ) -> impl Iterator<Item = (Price, impl Iterator<Item = &TradeOrder<P::Qty>>)> {
+        self.price_levels.keys(self.s == Side::Bid).map(|key| {
+            (
+                self.price_of_key(key),
+                self.iter_level(key).into_iter().flatten(),
+            )
+        })
     }
 
-    fn print_price_levels<'a, I>(&self, prices: I)
-    where
-        I: Iterator<Item = &'a Price>,
-    {
-        for price in prices {
-            let level = self.get_price_level(price).unwrap();
+    pub fn show_depth(&self) {
+        for key in self.price_levels.keys(true) {
             println!(
                 "Price: {} Qty: {}",
-                price,
-                level
-                    .iter()
-                    .fold(Decimal::ZERO, |acc, o| acc + o.remaining_qty)
+                self.price_of_key(key),
+                self.qty_of_key(self.level_qty(key, |o| o.visible_qty()))
             );
         }
     }
 
-    pub fn get_total_qty(&self, price: &Price) -> Option<Price> {
-        Some(
-            self.price_levels
-                .get(price)?
-                .iter()
-                .fold(Decimal::ZERO, |acc, o| acc + o.remaining_qty),
-        )
+    // Displayed quantity at the price, iceberg reserves are not included
+    pub fn get_total_qty(&self, price: &Price) -> Option<Quantity> {
+        let key = self.price_key(price)?;
+        self.price_levels.get(&key)?;
+        Some(self.qty_of_key(self.level_qty(&key, |o| o.visible_qty())))
+    }
+
+    fn level_qty(&self, key: &P, qty: impl Fn(&TradeOrder<P::Qty>) -> P::Qty) -> P::Qty {
+        self.iter_level(key)
+            .into_iter()
+            .flatten()
+            .fold(P::Qty::ZERO, |acc, o| acc + qty(o))
     }
 
     pub fn get_available_quantity(&self, target_price: impl Into<Price>) -> Quantity {
         let target_price = target_price.into();
-        self.iter_prices()
-            .take_while(|&p| match self.s {
-                Side::Ask => p <= target_price,
-                Side::Bid => p >= target_price,
+        let qty = self
+            .price_levels
+            .keys(self.s == Side::Bid)
+            .take_while(|key| {
+                let p = self.price_of_key(key);
+                match self.s {
+                    Side::Ask => p <= target_price,
+                    Side::Bid => p >= target_price,
+                }
             })
-            .map(|p| self.get_total_qty(&p).unwrap_or(Decimal::ZERO))
-            .sum()
+            // Iceberg reserves can be executed so they are included
+            .fold(P::Qty::ZERO, |acc, key| {
+                acc + self.level_qty(key, |o| o.remaining_qty)
+            });
+        self.qty_of_key(qty)
     }
 
     pub fn get_levels(&self) -> Vec<(Price, Quantity)> {
-        self.iter_prices()
-            .map(|price| (price, self.get_total_qty(&price).unwrap_or(Decimal::ZERO)))
-            .collect()
+        self.iter_levels().collect()
     }
 
     pub fn get_total_volume(&self) -> Quantity {
-        self.iter_prices()
-            .map(|price| self.get_total_qty(&price).unwrap_or(Decimal::ZERO))
-            .sum()
+        self.iter_levels().map(|(_, qty)| qty).sum()
     }
 
     pub fn get_depth(&self) -> usize {
-        self.price_set.len()
+        self.price_levels.len()
     }
 
     pub fn get_price_range(&self) -> Option<Price> {
-        if self.price_set.is_empty() {
-            return None;
-        }
-        let min = *self.price_set.iter().next()?;
-        let max = *self.price_set.iter().next_back()?;
+        let min = self.price_of_key(self.price_levels.min_index()?);
+        let max = self.price_of_key(self.price_levels.max_index()?);
         Some(max - min)
     }
 
-    pub fn get_orders_at_price(&self, price: impl Into<Price>) -> Option<Vec<&TradeOrder>> {
-        let price = price.into();
+    pub fn get_orders_at_price(&self, price: impl Into<Price>) -> Option<Vec<&TradeOrder<P::Qty>>> {
+        let key = self.price_key(&price.into())?;
+        self.iter_level(&key).map(|orders| orders.collect())
+    }
+
+    // Orders of the level in time priority
+    fn iter_level(&self, key: &P) -> Option<impl Iterator<Item = &TradeOrder<P::Qty>>> {
         self.price_levels
-            .get(&price)
-            .map(|level| level.iter().collect())
+            .get(key)
+            .map(|level| self.orders.iter(level))
     }
 
     pub fn is_empty(&self) -> bool {
-        self.price_set.is_empty()
+        self.price_levels.is_empty()
     }
 
-    pub fn get_order(&self, price: impl Into<Price>, order_id: OrderId) -> Option<&TradeOrder> {
-        let price = price.into();
-        self.price_levels
-            .get(&price)
-            .and_then(|level| level.iter().find(|o| o.id == order_id))
+    pub fn get_order(
+        &self,
+        price: impl Into<Price>,
+        order_id: OrderId,
+    ) -> Option<&TradeOrder<P::Qty>> {
+        let key = self.price_key(&price.into())?;
+        self.orders
+            .get(&order_id)
+            .filter(|_| self.orders.price_of(&order_id) == Some(key))
     }
 
-    pub fn get_order_mut(&mut self, price: &Price, order_id: &OrderId) -> Option<&mut TradeOrder> {
-        self.price_levels
-            .get_mut(price)
-            .and_then(|level| level.iter_mut().find(|o| o.id == *order_id))
+    pub fn get_order_mut(
+        &mut self,
+        key: &P,
+        order_id: &OrderId,
+    ) -> Option<&mut TradeOrder<P::Qty>> {
+        if self.orders.price_of(order_id)? != *key {
+            return None;
+        }
+        self.orders.get_mut(order_id)
     }
 
     pub fn get_order_count(&self) -> usize {
-        self.price_levels.iter().map(|(_, level)| level.len()).sum()
+        self.orders.len()
     }
 
     pub fn clear(&mut self) {
-        self.price_set.clear();
-        self.price_levels = SparseVec::with_capacity(10_000);
+        self.price_levels.clear();
+        self.orders.clear();
     }
 }
-#[derive(Debug)]
+
+// Reports the part of an order that traded, after the fill has been applied
+fn emit_fill<Q: QuantityType>(
+    sink: &mut dyn EventSink,
+    order: &TradeOrder<Q>,
+    price: Price,
+    qty: Q,
+    qty_scale: u32,
+) {
+    let notification = if order.remaining_qty == Q::ZERO {
+        Notification::OrderFilled {
+            order_id: order.id,
+            side: order.side,
+            account: order.account,
+            price,
+            qty: qty.to_qty(qty_scale),
+        }
+    } else {
+        Notification::OrderPartiallyFilled {
+            order_id: order.id,
+            side: order.side,
+            account: order.account,
+            price,
+            qty: qty.to_qty(qty_scale),
+            remaining_qty: order.remaining_qty.to_qty(qty_scale),
+            displayed_qty: order.visible_qty().to_qty(qty_scale),
+        }
+    };
+    sink.emit(notification);
+}
+
+// Reports the quantity cancelled since the order had `before` open, if any
+fn emit_cancel<Q: QuantityType>(
+    sink: &mut dyn EventSink,
+    order: &TradeOrder<Q>,
+    price: Option<Price>,
+    before: Q,
+    qty_scale: u32,
+) {
+    let qty = before - order.remaining_qty;
+    if qty > Q::ZERO {
+        sink.emit(Notification::OrderCancelled {
+            order_id: order.id,
+            side: order.side,
+            account: order.account,
+            price,
+            qty: qty.to_qty(qty_scale),
+            remaining_qty: order.remaining_qty.to_qty(qty_scale),
+            displayed_qty: order.visible_qty().to_qty(qty_scale),
+        });
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderBookState {
     pub asks: Vec<(Price, Quantity)>,
     pub bids: Vec<(Price, Quantity)>,
 }
 #[derive(Debug)]
-pub struct OrderBook {
-    pub asks: HalfBook,
-    pub bids: HalfBook,
-    // For fast order lookup / cancel OrderId -> (Side, PriceLevelIndex)
-    pub order_loc: HashMap<OrderId, (Side, Price)>,
+pub struct OrderBook<C: Clock = SystemClock, P: PriceType = Price> {
+    pub asks: HalfBook<P>,
+    pub bids: HalfBook<P>,
+    // For fast order lookup / cancel OrderId -> (Side, price level key)
+    pub order_loc: HashMap<OrderId, (Side, P)>,
+    // Stop orders waiting for their trigger price
+    pub stop_asks: TriggerBook<P>,
+    pub stop_bids: TriggerBook<P>,
+    // For stop order lookup / cancel OrderId -> (Side, trigger price key)
+    pub stop_loc: HashMap<OrderId, (Side, P)>,
+    // Orders with a time in force other than GTC, ordered by expiry
+    expiry_index: BTreeSet<(Timestamp, OrderId)>,
+    last_trade_price: Option<Price>,
+    // Orders accumulate without matching while a call auction is running
+    in_auction: bool,
+    // Indicative uncross last published during the auction
+    indicative: Option<Equilibrium>,
+    // Limits on the prices orders and trades can have
+    bands: PriceBands,
+    clock: C,
+    // Receives the events of every order processed by the book
+    sink: Box<dyn EventSink>,
+    // Orders that left the book or its stop book since they were last taken, None unless tracked
+    departed: Option<Vec<OrderId>>,
 }
 
 impl Default for OrderBook {
     fn default() -> Self {
+        Self::with_clock(SystemClock)
+    }
+}
+
+impl<C: Clock> OrderBook<C> {
+    /// Creates an empty order book that takes its timestamps from `clock`.
+    pub fn with_clock(clock: C) -> Self {
+        Self::with_scale(clock, 0, 0)
+    }
+}
+
+impl<C: Clock, P: PriceType> OrderBook<C, P> {
+    /// Creates an empty order book keying its price levels by `P`, whose prices have at most `scale` decimal places
+    /// and quantities `qty_scale`. The scales only matter for integer price types, e.g. a scale of 2 stores a price of
+    /// 1.25 as 125 ticks. Prices and quantities are converted once as they enter the book, and back as it reports them.
+    pub fn with_scale(clock: C, scale: u32, qty_scale: u32) -> Self {
         Self {
-            asks: HalfBook::new(Side::Ask),
-            bids: HalfBook::new(Side::Bid),
+            asks: HalfBook::with_scale(Side::Ask, scale, qty_scale),
+            bids: HalfBook::with_scale(Side::Bid, scale, qty_scale),
             order_loc: HashMap::with_capacity(10_000),
+            stop_asks: TriggerBook::with_scale(Side::Ask, scale),
+            stop_bids: TriggerBook::with_scale(Side::Bid, scale),
+            stop_loc: HashMap::new(),
+            expiry_index: BTreeSet::new(),
+            last_trade_price: None,
+            in_auction: false,
+            indicative: None,
+            bands: PriceBands::default(),
+            clock,
+            sink: Box::new(NullSink),
+            departed: None,
         }
     }
-}
 
-impl OrderBook {
-    fn get_mut_opposite_book(&mut self, side: &Side) -> &mut HalfBook {
-        match side {
-            Side::Ask => &mut self.bids,
-            Side::Bid => &mut self.asks,
+    /// Rebuilds a book from a snapshot, with the orders in the same queue positions.
+    /// The order locations and expiry index are derived from the orders, no events are emitted.
+    pub fn restore(clock: C, snapshot: OrderBookSnapshot) -> Result<Self> {
+        let mut book = Self::with_scale(clock, snapshot.scale, snapshot.qty_scale);
+        book.last_trade_price = snapshot.last_trade_price;
+        for (side, levels) in [(Side::Bid, snapshot.bids), (Side::Ask, snapshot.asks)] {
+            for level in levels {
+                for order in level.orders {
+                    let order = TradeOrder::from(order);
+                    let key = book.validate_resting_order(&level.price, &order)?;
+                    let order = book.book_order(order)?;
+                    book.rest_order(side, key, order);
+                }
+            }
+        }
+        for (side, levels) in [
+            (Side::Bid, snapshot.stop_bids),
+            (Side::Ask, snapshot.stop_asks),
+        ] {
+            for level in levels {
+                for order in level.orders {
+                    let order = TradeOrder::from(order);
+                    let key = book.validate_resting_order(&level.price, &order)?;
+                    let order = book.book_order(order)?;
+                    book.rest_stop_order(side, key, order);
+                }
+            }
+        }
+        if snapshot.in_auction {
+            book.in_auction = true;
+            book.indicative = book.indicative_uncross();
         }
+        Ok(book)
+    }
+
+    /// Sends the book's events to `sink` instead of discarding them.
+    pub fn with_event_sink(mut self, sink: impl EventSink + 'static) -> Self {
+        self.set_event_sink(sink);
+        self
+    }
+
+    pub fn set_event_sink(&mut self, sink: impl EventSink + 'static) {
+        self.sink = Box::new(sink);
+    }
+
+    /// Sends the notification to the book's event sink, the engine uses it for events of the book's market.
+    pub fn emit(&mut self, notification: Notification) {
+        self.sink.emit(notification);
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// Records the orders that leave the book, filled, cancelled or expired, and the stop orders that get triggered,
+    /// until they are taken with `take_departed`. The engine uses it to settle only the orders an operation touched.
+    pub fn track_departed(&mut self) {
+        self.departed.get_or_insert_with(Vec::new);
+    }
+
+    /// Takes the orders that left the book or its stop book since the last call, empty unless tracked.
+    pub fn take_departed(&mut self) -> Vec<OrderId> {
+        self.departed
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Protects the book with the price bands, see `PriceBands`.
+    pub fn with_price_bands(mut self, bands: PriceBands) -> Self {
+        self.bands = bands;
+        self
+    }
+
+    pub fn set_price_bands(&mut self, bands: PriceBands) {
+        self.bands = bands;
+    }
+
+    pub fn price_bands(&self) -> &PriceBands {
+        &self.bands
+    }
+
+    /// Matches incoming orders against each price level with the policy, see `MatchingPolicy`. Books match FIFO by
+    /// default.
+    pub fn with_matching_policy(mut self, policy: Arc<dyn MatchingPolicy>) -> Self {
+        self.set_matching_policy(policy);
+        self
+    }
+
+    pub fn set_matching_policy(&mut self, policy: Arc<dyn MatchingPolicy>) {
+        self.bids.set_matching_policy(policy.clone());
+        self.asks.set_matching_policy(policy);
     }
 
     pub fn show_depth(&self) {
@@ -280,194 +670,950 @@ impl OrderBook {
         (self.bids.best_price(), self.asks.best_price())
     }
 
-    pub fn delete_order(&mut self, order_id: OrderId) -> Option<OrderResult> {
-        let (side, price) = self.order_loc.remove(&order_id)?;
-        let book = self.get_mut_book(&side);
-        let order = book.remove_order(&price, order_id)?;
-        Some(OrderResult::cancelled(order))
+    pub fn last_trade_price(&self) -> Option<Price> {
+        self.last_trade_price
     }
 
-    pub fn cancel_order(
-        &mut self,
-        order_id: OrderId,
-        qty: impl Into<Quantity>,
-    ) -> Option<OrderResult> {
-        let trade_order = self.get_order_mut(&order_id)?;
-        trade_order.cancel(qty);
-        if trade_order.remaining_qty == Decimal::ZERO {
-            return self.delete_order(order_id);
-        }
-        Some(OrderResult::from(trade_order.clone()))
+    /// Starts a call auction. Until `uncross` is called, limit orders rest without matching even when they cross, stop
+    /// orders wait in the trigger book and orders that must execute on arrival (market, IOC and FOK) are rejected.
+    /// The indicative uncross is published with an `IndicativeUncross` notification whenever it changes.
+    pub fn start_auction(&mut self) {
+        self.in_auction = true;
+        self.indicative = None;
+        self.publish_indicative();
     }
 
-    pub fn add_order(&mut self, order: OrderRequest) -> (OrderResult, Vec<TradeExecution>) {
-        let opposite_book = self.get_mut_opposite_book(&order.side);
-        let mut executions = Vec::new();
-
-        if let OrderType::FOK(price) = order.order_type {
-            let available_qty = opposite_book.get_available_quantity(price);
-            info!("Available qty: {}", available_qty);
-            info!("Order qty: {}", order.qty);
-            if available_qty < order.qty {
-                warn!("FOK order failed");
-                return (OrderResult::from(order), executions);
-            }
-        };
-        let mut trade_order = TradeOrder::from(order);
-
-        let filtered_prices = opposite_book
-            .iter_prices()
-            .filter(|p| match &trade_order.order_type {
-                // Market order no filtering required
-                OrderType::Market => true,
-                OrderType::Limit(price)
-                | OrderType::IOC(price)
-                | OrderType::FOK(price)
-                | OrderType::SystemLevel(price) => match &trade_order.side {
-                    Side::Bid => price >= p,
-                    Side::Ask => price <= p,
-                },
-            })
-            .collect::<Vec<_>>();
+    pub fn in_auction(&self) -> bool {
+        self.in_auction
+    }
 
-        for p in filtered_prices {
-            let mut price_executions = opposite_book.match_order(&mut trade_order, p);
-            executions.append(&mut price_executions);
-            if trade_order.remaining_qty == Decimal::ZERO {
-                break;
-            }
-        }
+    /// Returns the price and volume the book would uncross at now, None if it does not cross.
+    /// Ties are broken towards the last trade price, see `find_equilibrium`.
+    pub fn indicative_uncross(&self) -> Option<Equilibrium> {
+        let bids: Vec<_> = self.bids.iter_open_levels().collect();
+        let asks: Vec<_> = self.asks.iter_open_levels().collect();
+        find_equilibrium(&bids, &asks, self.last_trade_price)
+    }
 
-        match &trade_order.order_type {
-            OrderType::Limit(price) => {
-                if price > &Decimal::ZERO && trade_order.remaining_qty > Decimal::ZERO {
-                    self.add_limit_order(trade_order.side, *price, trade_order.clone());
+    /// Ends the call auction, executing every crossing order at the equilibrium price and returning to continuous
+    /// trading. Orders match in price-time priority on both sides, with the bid reported as the taker of each trade.
+    /// Stop orders triggered by the uncross price are then matched as in continuous trading.
+    pub fn uncross(&mut self) -> Vec<TradeExecution> {
+        let equilibrium = self.indicative_uncross();
+        self.in_auction = false;
+        let mut executions = Vec::new();
+        // The equilibrium is the price of a level, so it is on the book's scale
+        let uncross_at = equilibrium
+            .and_then(|Equilibrium { price, .. }| Some((price, self.asks.price_key(&price)?)));
+        if let Some((price, key)) = uncross_at {
+            let now = self.clock.now();
+            let sink = self.sink.as_mut();
+            let mut removed = Vec::new();
+            // An ask level left with nothing displayed to trade is skipped, as in continuous matching
+            let mut skipped = None;
+            while let (Some(bid_key), Some(ask_key)) =
+                (self.bids.best_key(), self.asks.next_key(skipped))
+            {
+                if bid_key < key || ask_key > key {
+                    break;
                 }
-            }
-            OrderType::SystemLevel(price) => {
-                if price > &Decimal::ZERO && trade_order.remaining_qty > Decimal::ZERO {
-                    self.add_system_order(trade_order.side, *price, trade_order.clone());
+                let Some(bid) = self.bids.best_order_mut() else {
+                    break;
+                };
+                let bid_id = bid.id;
+                let mut level_executions =
+                    self.asks
+                        .match_order_at(bid, ask_key, price, now, &mut removed, sink);
+                executions.append(&mut level_executions);
+                if bid.remaining_qty == P::Qty::ZERO {
+                    removed.extend(self.bids.remove_order(&bid_key, bid_id));
+                } else if self.asks.has_level(&ask_key) {
+                    skipped = Some(ask_key);
                 }
             }
-            OrderType::Market | OrderType::IOC(_) | OrderType::FOK(_) => {}
+            for order in removed {
+                self.forget_order(&order);
+            }
+        }
+        if self.indicative.take().is_some() {
+            self.emit(Notification::IndicativeUncross(None));
         }
-        (OrderResult::from(trade_order), executions)
+        self.process_triggers(&mut executions);
+        executions
     }
 
-    pub fn add_limit_order(&mut self, side: Side, price: impl Into<Price>, order: TradeOrder) {
-        let price = price.into();
-        assert_eq!(self.order_loc.insert(order.id, (side, price)), None);
-        self.get_mut_book(&side).add_order(price, order);
+    // Publishes the indicative uncross of a running call auction if it has changed
+    fn publish_indicative(&mut self) {
+        if !self.in_auction {
+            return;
+        }
+        let indicative = self.indicative_uncross();
+        if indicative != self.indicative {
+            self.indicative = indicative;
+            self.emit(Notification::IndicativeUncross(indicative));
+        }
     }
 
-    // When a system order is added to the orderbook we need to merge it with the existing order if it exists
-    pub fn add_system_order(&mut self, side: Side, price: impl Into<Price>, order: TradeOrder) {
-        let price = price.into();
-        match self.get_order_mut(&order.id) {
-            Some(existing_order) => {
-                assert_eq!(existing_order.merge(order), None);
-            }
-            None => {
-                self.order_loc.insert(order.id, (side, price));
-                self.get_mut_book(&side).add_order(price, order);
-            }
+    pub fn delete_order(&mut self, order_id: OrderId) -> Result<OrderResult> {
+        let (order, price) = self
+            .remove_order(order_id)
+            .ok_or(OrderBookError::OrderNotFound(order_id))?;
+        self.emit(Notification::OrderCancelled {
+            order_id,
+            side: order.side,
+            account: order.account,
+            price,
+            qty: self.qty_of_key(order.remaining_qty),
+            remaining_qty: Decimal::ZERO,
+            displayed_qty: Decimal::ZERO,
+        });
+        self.publish_indicative();
+        Ok(OrderResult::cancelled(self.decimal_order(order)))
+    }
+
+    // Removes a resting or stop order from the book and the expiry index.
+    // Returns the order with the price of the level it rested at, None for a stop order.
+    fn remove_order(&mut self, order_id: OrderId) -> Option<(TradeOrder<P::Qty>, Option<Price>)> {
+        let (order, price) = if let Some((side, trigger)) = self.stop_loc.remove(&order_id) {
+            let order = self
+                .get_mut_trigger_book(&side)
+                .remove_order(&trigger, order_id)?;
+            (order, None)
+        } else {
+            let (side, key) = self.order_loc.remove(&order_id)?;
+            let order = self.get_mut_book(&side).remove_order(&key, order_id)?;
+            (order, Some(self.price_of_key(&key)))
         };
+        if let Some(expiry) = order.expires_at() {
+            self.expiry_index.remove(&(expiry, order_id));
+        }
+        self.record_departed(order_id);
+        Some((order, price))
     }
 
-    pub fn spread(&self) -> Option<Price> {
-        match (self.best_ask(), self.best_bid()) {
-            (Some(ask), Some(bid)) if ask > bid => Some(ask - bid),
-            _ => None,
+    /// Removes every order that has expired by `now`.
+    /// Only the expiry index is walked, so the cost depends on the number of expired orders rather than the book size.
+    pub fn expire_orders(&mut self, now: Timestamp) -> Vec<OrderResult> {
+        let mut expired = Vec::new();
+        while let Some(&(expiry, order_id)) = self.expiry_index.first() {
+            if expiry > now {
+                break;
+            }
+            self.expiry_index.pop_first();
+            // Orders that have since been filled are no longer on the book
+            if let Some((order, price)) = self.remove_order(order_id) {
+                self.emit(Notification::OrderExpired {
+                    order_id,
+                    side: order.side,
+                    account: order.account,
+                    price,
+                    qty: self.qty_of_key(order.remaining_qty),
+                });
+                expired.push(OrderResult::expired(self.decimal_order(order)));
+            }
         }
+        self.publish_indicative();
+        expired
     }
 
-    pub fn get_depth(&self) -> (usize, usize) {
-        (self.asks.get_depth(), self.bids.get_depth())
-    }
+    pub fn cancel_order(
+        &mut self,
+        order_id: OrderId,
+        qty: impl Into<Quantity>,
+    ) -> Result<OrderResult> {
+        let qty = self.check_qty(&qty.into())?;
+        let (side, key) = *self
+            .order_loc
+            .get(&order_id)
+            .ok_or(OrderBookError::OrderNotFound(order_id))?;
+        let price = self.price_of_key(&key);
+        let qty_scale = self.bids.qty_scale;
+        let trade_order = self
+            .get_order_mut(&order_id)
+            .ok_or(OrderBookError::OrderNotFound(order_id))?;
+        let qty = qty.min(trade_order.remaining_qty);
+        trade_order.cancel(qty);
+        let remaining_qty = trade_order.remaining_qty;
+        let displayed_qty = trade_order.visible_qty();
+        let account = trade_order.account;
+        let result = if remaining_qty == P::Qty::ZERO {
+            let (order, _) = self
+                .remove_order(order_id)
+                .ok_or(OrderBookError::OrderNotFound(order_id))?;
+            OrderResult::cancelled(order.into_decimal(qty_scale))
+        } else {
+            OrderResult::from(trade_order.clone().into_decimal(qty_scale))
+        };
+        self.emit(Notification::OrderCancelled {
+            order_id,
+            side,
+            account,
+            price: Some(price),
+            qty: self.qty_of_key(qty),
+            remaining_qty: self.qty_of_key(remaining_qty),
+            displayed_qty: self.qty_of_key(displayed_qty),
+        });
+        self.publish_indicative();
+        Ok(result)
+    }
+
+    /// Atomically modifies a resting order, keeping its id.
+    /// A pure quantity decrease keeps the order's place in the queue, a price change or quantity increase sends it to the
+    /// back of the queue at the new price, matching it first if the new price crosses. A zero quantity cancels the order.
+    /// A post only order is checked again at a new price, repriced as its mode allows or left unchanged with an
+    /// `AmendRejected` error. Stop orders can not be amended until they trigger.
+    pub fn amend_order(
+        &mut self,
+        order_id: OrderId,
+        new_qty: impl Into<Quantity>,
+        new_price: impl Into<Price>,
+    ) -> Result<(OrderResult, Vec<TradeExecution>)> {
+        let new_qty = new_qty.into();
+        let mut new_price = new_price.into();
+        if new_qty < Decimal::ZERO {
+            return Err(OrderBookError::InvalidQuantity(new_qty));
+        }
+        let mut new_key = self.check_price(&new_price)?;
+        let new_units = self
+            .bids
+            .qty_key(&new_qty)
+            .ok_or(OrderBookError::InvalidQuantity(new_qty))?;
+        let now = self.clock.now();
+        if self.stop_loc.contains_key(&order_id) {
+            return Err(OrderBookError::StopOrderNotAmendable(order_id));
+        }
+        let (side, key) = *self
+            .order_loc
+            .get(&order_id)
+            .ok_or(OrderBookError::OrderNotFound(order_id))?;
 
-    fn get_book(&self, side: &Side) -> &HalfBook {
-        match side {
-            Side::Ask => &self.asks,
-            Side::Bid => &self.bids,
+        if new_units == P::Qty::ZERO {
+            return Ok((self.delete_order(order_id)?, Vec::new()));
+        }
+        // A post only order must not match at its new price either
+        let post_only = self.get_order(order_id).and_then(|order| order.post_only());
+        if let Some(post_only) = post_only.filter(|_| new_key != key) {
+            new_price = self
+                .post_only_price(side, new_price, post_only)
+                .map_err(OrderBookError::AmendRejected)?;
+            new_key = self.check_price(&new_price)?;
         }
-    }
 
-    fn get_mut_book(&mut self, side: &Side) -> &mut HalfBook {
-        match side {
-            Side::Ask => &mut self.asks,
-            Side::Bid => &mut self.bids,
+        let price = self.price_of_key(&key);
+        let qty_scale = self.bids.qty_scale;
+        let order = self
+            .get_order_mut(&order_id)
+            .ok_or(OrderBookError::OrderNotFound(order_id))?;
+        let old_qty = order.remaining_qty;
+        let kept_priority = new_key == key && new_units <= old_qty;
+        // A decrease keeps what is left of an iceberg's peak, up to the new quantity
+        let displayed_qty = if kept_priority {
+            order.visible_qty().min(new_units)
+        } else {
+            P::Qty::ZERO
+        };
+        let amended = Notification::OrderAmended {
+            order_id,
+            side,
+            account: order.account,
+            old_price: price,
+            old_qty: old_qty.to_qty(qty_scale),
+            price: new_price,
+            qty: new_qty,
+            displayed_qty: displayed_qty.to_qty(qty_scale),
+            kept_priority,
+        };
+        if kept_priority {
+            order.amend(new_units, new_price, now);
+            let result = OrderResult::from(order.clone().into_decimal(qty_scale));
+            self.emit(amended);
+            self.publish_indicative();
+            return Ok((result, Vec::new()));
         }
+
+        let (mut order, _) = self
+            .remove_order(order_id)
+            .ok_or(OrderBookError::OrderNotFound(order_id))?;
+        order.amend(new_units, new_price, now);
+        // Back of the queue, an iceberg shows a fresh peak
+        order.refresh_peak();
+        self.emit(amended);
+        let (result, mut executions) = self.execute_order(order);
+        self.process_triggers(&mut executions);
+        self.publish_indicative();
+        Ok((result, executions))
     }
 
-    pub fn get_order_book_state(&self) -> OrderBookState {
-        let mut ask = self.asks.get_levels();
-        ask.reverse();
+    pub fn add_order(
+        &mut self,
+        mut order: OrderRequest,
+    ) -> Result<(OrderResult, Vec<TradeExecution>)> {
+        let (qty, peak_qty) = self.validate_order(&order)?;
+        order.peak_qty = order.peak_qty.map(|peak_qty| peak_qty.min(order.qty));
+        if self.in_auction
+            && matches!(
+                order.order_type,
+                OrderType::Market | OrderType::IOC(_) | OrderType::FOK(_)
+            )
+        {
+            warn!("Order rejected during call auction");
+            return Ok((
+                self.reject(order, RejectReason::AuctionInProgress),
+                Vec::new(),
+            ));
+        }
+        if order
+            .price()
+            .is_some_and(|price| !self.bands.accepts_price(&price))
+        {
+            warn!("Order priced outside the static band");
+            return Ok((
+                self.reject(order, RejectReason::PriceOutsideBand),
+                Vec::new(),
+            ));
+        }
+        if let Some(post_only) = order.post_only {
+            if let Err(reason) = self.apply_post_only(&mut order, post_only) {
+                warn!("Post only order rejected");
+                return Ok((self.reject(order, reason), Vec::new()));
+            }
+        }
+        self.emit(Notification::OrderAccepted {
+            order_id: order.id(),
+            side: order.side,
+            account: order.account,
+            order_type: order.order_type,
+            qty: order.qty,
+        });
+        let peak_qty = peak_qty.map(|peak_qty| peak_qty.min(qty));
+        let mut trade_order = TradeOrder::with_qty(order, qty, peak_qty, self.clock.now());
+
+        if let Some(trigger) = trade_order.order_type.trigger_price() {
+            let trigger_key = self.check_price(&trigger)?;
+            // Stop orders are only triggered by trades, so they wait for the uncross during an auction
+            if self.in_auction || !self.is_stop_triggered(&trade_order.side, &trigger_key) {
+                let result = OrderResult::from(self.decimal_order(trade_order.clone()));
+                self.rest_stop_order(trade_order.side, trigger_key, trade_order);
+                return Ok((result, Vec::new()));
+            }
+            self.trigger_stop_order(&mut trade_order, trigger);
+        }
 
-        OrderBookState {
-            asks: ask,
-            bids: self.bids.get_levels(),
+        let (result, mut executions) = self.execute_order(trade_order);
+        self.process_triggers(&mut executions);
+        self.publish_indicative();
+        Ok((result, executions))
+    }
+
+    /// Rejects the order without it reaching the book, reporting it to the event sink.
+    pub fn reject(&mut self, order: OrderRequest, reason: RejectReason) -> OrderResult {
+        self.emit(Notification::OrderRejected {
+            order_id: order.id(),
+            side: order.side,
+            account: order.account,
+            reason,
+        });
+        OrderResult::rejected(order, reason)
+    }
+
+    // Rejects orders the book cannot hold before anything is matched, returns the quantity and peak in book units
+    fn validate_order(&self, order: &OrderRequest) -> Result<(P::Qty, Option<P::Qty>)> {
+        let qty = self.check_qty(&order.qty)?;
+        // An iceberg displaying nothing could never trade
+        let peak_qty = order
+            .peak_qty
+            .map(|peak_qty| {
+                self.check_qty(&peak_qty)
+                    .map_err(|_| OrderBookError::InvalidPeakQuantity(peak_qty))
+            })
+            .transpose()?;
+        for price in [order.price(), order.order_type.trigger_price()]
+            .into_iter()
+            .flatten()
+        {
+            self.check_price(&price)?;
+        }
+        // System level orders share one id per price level and are merged instead
+        if !matches!(order.order_type, OrderType::SystemLevel(_))
+            && self.contains_order(&order.id())
+        {
+            return Err(OrderBookError::DuplicateOrderId(order.id()));
         }
+        Ok((qty, peak_qty))
     }
 
-    pub fn get_orders_at_price(
-        &self,
-        side: Side,
-        price: impl Into<Price>,
-    ) -> Option<Vec<&TradeOrder>> {
-        self.get_book(&side).get_orders_at_price(price)
+    // Prices must be positive and representable at the scale of the book, returns the price as the book keys it
+    fn check_price(&self, price: &Price) -> Result<P> {
+        match self.asks.price_key(price) {
+            Some(key) if *price > Decimal::ZERO => Ok(key),
+            _ => Err(OrderBookError::InvalidPrice(*price)),
+        }
     }
 
-    pub fn get_total_volume(&self) -> Quantity {
-        self.asks.get_total_volume() + self.bids.get_total_volume()
+    // Quantities must be positive and representable at the scale of the book, returns the quantity in book units
+    fn check_qty(&self, qty: &Quantity) -> Result<P::Qty> {
+        match self.bids.qty_key(qty) {
+            Some(units) if *qty > Decimal::ZERO => Ok(units),
+            _ => Err(OrderBookError::InvalidQuantity(*qty)),
+        }
     }
 
-    pub fn get_price_range(&self) -> Option<(Price, Price)> {
-        Some((self.asks.get_price_range()?, self.bids.get_price_range()?))
+    // Converts an order entering the book to the book's quantity type
+    fn book_order(&self, order: TradeOrder) -> Result<TradeOrder<P::Qty>> {
+        order
+            .into_qty_type(self.bids.qty_scale)
+            .map_err(OrderBookError::InvalidQuantity)
     }
 
-    pub fn get_order(&self, order_id: OrderId) -> Option<&TradeOrder> {
-        self.order_loc
-            .get(&order_id)
-            .and_then(|(side, price)| self.get_book(side).get_order(*price, order_id))
+    // Converts an order leaving the book back to decimal quantities
+    fn decimal_order(&self, order: TradeOrder<P::Qty>) -> TradeOrder {
+        order.into_decimal(self.bids.qty_scale)
     }
 
-    pub fn get_order_mut(&mut self, order_id: &OrderId) -> Option<&mut TradeOrder> {
-        self.order_loc
-            .get(order_id)
-            .and_then(|(side, price)| match side {
-                Side::Ask => self.asks.get_order_mut(price, order_id),
-                Side::Bid => self.bids.get_order_mut(price, order_id),
-            })
+    fn price_of_key(&self, key: &P) -> Price {
+        self.bids.price_of_key(key)
     }
 
-    pub fn get_volume_at_price(&self, side: &Side, price: &Price) -> Option<Quantity> {
-        self.get_book(side).get_total_qty(price)
+    fn qty_of_key(&self, qty: P::Qty) -> Quantity {
+        self.bids.qty_of_key(qty)
     }
 
-    pub fn get_order_count(&self) -> usize {
-        self.order_loc.len()
+    /// Returns true if an order with the id is resting on the book or waiting for its trigger.
+    pub fn contains_order(&self, order_id: &OrderId) -> bool {
+        self.order_loc.contains_key(order_id) || self.stop_loc.contains_key(order_id)
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.asks.is_empty() && self.bids.is_empty()
+    // Checks a post only order against the opposite best price, repricing it when the mode allows.
+    // Stop orders are not checked as they only reach the book once triggered.
+    fn apply_post_only(
+        &self,
+        order: &mut OrderRequest,
+        post_only: PostOnly,
+    ) -> Result<(), RejectReason> {
+        if order.order_type.trigger_price().is_some() {
+            return Ok(());
+        }
+        match order.price() {
+            Some(price) => {
+                let price = self.post_only_price(order.side, price, post_only)?;
+                order.order_type = order.order_type.with_price(price);
+                Ok(())
+            }
+            // Orders without a price take whatever is on the opposite side
+            None if self.get_book(&order.side.opposite()).best_price().is_some() => {
+                Err(RejectReason::PostOnlyWouldMatch)
+            }
+            None => Ok(()),
+        }
     }
 
-    pub fn clear(&mut self) {
-        self.asks.clear();
-        self.bids.clear();
-        self.order_loc.clear();
+    // Returns the price a post only order of the side can rest at without matching, `price` itself unless it would
+    // cross the opposite best price and the mode allows repricing it
+    fn post_only_price(
+        &self,
+        side: Side,
+        price: Price,
+        post_only: PostOnly,
+    ) -> Result<Price, RejectReason> {
+        let Some(best) = self.get_book(&side.opposite()).best_price() else {
+            return Ok(price);
+        };
+        let crosses = match side {
+            Side::Bid => price >= best,
+            Side::Ask => price <= best,
+        };
+        match post_only {
+            _ if !crosses => Ok(price),
+            PostOnly::Reprice(tick) => {
+                let price = match side {
+                    Side::Bid => best - tick,
+                    Side::Ask => best + tick,
+                };
+                if tick <= Decimal::ZERO || price <= Decimal::ZERO {
+                    return Err(RejectReason::PostOnlyWouldMatch);
+                }
+                // The repriced order must still be on the book's scale
+                if self.check_price(&price).is_err() {
+                    return Err(RejectReason::PriceNotOnTick);
+                }
+                Ok(price)
+            }
+            PostOnly::Reject => Err(RejectReason::PostOnlyWouldMatch),
+        }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
+
+    // Matches the order against the opposite book and rests whatever is left if the order type allows it
+    fn execute_order(
+        &mut self,
+        mut trade_order: TradeOrder<P::Qty>,
+    ) -> (OrderResult, Vec<TradeExecution>) {
+        let now = self.clock.now();
+        // Prices were checked as the order entered the book, so the limit converts
+        let limit = trade_order
+            .order_type
+            .price()
+            .and_then(|price| self.asks.price_key(&price));
+        // Borrowed field by field so the sink can be handed to the matching
+        let opposite_book = match trade_order.side {
+            Side::Ask => &mut self.bids,
+            Side::Bid => &mut self.asks,
+        };
+        let sink = self.sink.as_mut();
+        let mut executions = Vec::new();
+        let mut removed = Vec::new();
+        let mut breach = None;
+
+        if let OrderType::FOK(price) = trade_order.order_type {
+            // Only the levels the order can trade at without breaching a band are available
+            let available_qty: Quantity = opposite_book
+                .iter_open_levels()
+                .take_while(|(p, _)| {
+                    let crosses = match trade_order.side {
+                        Side::Bid => price >= *p,
+                        Side::Ask => price <= *p,
+                    };
+                    crosses && self.bands.check_trade(p, self.last_trade_price).is_none()
+                })
+                .map(|(_, qty)| qty)
+                .sum();
+            let order_qty = opposite_book.qty_of_key(trade_order.remaining_qty);
+            info!("Available qty: {}", available_qty);
+            info!("Order qty: {}", order_qty);
+            if available_qty < order_qty {
+                warn!("FOK order failed");
+                self.emit_unrested(&trade_order);
+                return (
+                    OrderResult::cancelled(self.decimal_order(trade_order)),
+                    executions,
+                );
+            }
+        };
+
+        // Each pass either empties the best level or fills the order, so the next best price is read in O(1). A level
+        // left with nothing displayed to trade is skipped rather than matched again.
+        // Nothing matches during a call auction, the order rests until the uncross.
+        let mut skipped = None;
+        while !self.in_auction && trade_order.remaining_qty > P::Qty::ZERO {
+            let Some(key) = opposite_book.next_key(skipped) else {
+                break;
+            };
+            let crosses = match &trade_order.order_type {
+                // Market order no filtering required
+                OrderType::Market | OrderType::Stop(_) => true,
+                _ => limit.is_some_and(|limit| match &trade_order.side {
+                    Side::Bid => limit >= key,
+                    Side::Ask => limit <= key,
+                }),
+            };
+            if !crosses {
+                break;
+            }
+            let p = opposite_book.price_of_key(&key);
+            // Matching stops at a band, the last trade price does not move until the order is done
+            if let Some(band_breach) = self.bands.check_trade(&p, self.last_trade_price) {
+                breach = Some((band_breach, p));
+                break;
+            }
+            let mut price_executions =
+                opposite_book.match_order_at(&mut trade_order, key, p, now, &mut removed, sink);
+            executions.append(&mut price_executions);
+            if trade_order.remaining_qty > P::Qty::ZERO && opposite_book.has_level(&key) {
+                skipped = Some(key);
+            }
+        }
+        for order in removed {
+            self.forget_order(&order);
+        }
+        if let Some((band_breach, price)) = breach {
+            if band_breach == BandBreach::Dynamic {
+                self.trip_circuit_breaker(price);
+            }
+            if self.bands.remainder == RemainderPolicy::Cancel {
+                self.emit_unrested(&trade_order);
+                return (
+                    OrderResult::cancelled(self.decimal_order(trade_order)),
+                    executions,
+                );
+            }
+        }
+
+        let open = trade_order.remaining_qty > P::Qty::ZERO;
+        match (trade_order.order_type, limit) {
+            (OrderType::Limit(_), Some(key)) => {
+                if open {
+                    // An iceberg that took liquidity on arrival shows a full peak once it rests
+                    trade_order.refresh_peak();
+                    self.rest_order(trade_order.side, key, trade_order.clone());
+                }
+            }
+            (OrderType::SystemLevel(_), Some(key)) => {
+                if open {
+                    if let Err(err) =
+                        self.merge_system_order(trade_order.side, key, trade_order.clone())
+                    {
+                        warn!("System order not added: {}", err);
+                    }
+                }
+            }
+            (OrderType::Limit(_) | OrderType::SystemLevel(_), None) => {
+                self.emit_unrested(&trade_order)
+            }
+            (
+                OrderType::Market
+                | OrderType::IOC(_)
+                | OrderType::FOK(_)
+                | OrderType::Stop(_)
+                | OrderType::StopLimit(_, _),
+                _,
+            ) => self.emit_unrested(&trade_order),
+        }
+        (
+            OrderResult::from(self.decimal_order(trade_order)),
+            executions,
+        )
+    }
+
+    // Halts matching by switching the book to a volatility auction, a trade at `price` would have breached the dynamic
+    // band. Stop orders triggered during the auction are handled as orders placed in it.
+    fn trip_circuit_breaker(&mut self, price: Price) {
+        warn!("Circuit breaker tripped at {}", price);
+        self.in_auction = true;
+        self.indicative = None;
+        self.emit(Notification::CircuitBreakerTripped {
+            price,
+            reference: self.last_trade_price,
+        });
+    }
+
+    // Reports the open quantity of an order that can not rest as cancelled
+    fn emit_unrested(&mut self, order: &TradeOrder<P::Qty>) {
+        if order.remaining_qty > P::Qty::ZERO {
+            self.emit(Notification::OrderCancelled {
+                order_id: order.id,
+                side: order.side,
+                account: order.account,
+                price: None,
+                qty: self.qty_of_key(order.remaining_qty),
+                remaining_qty: Decimal::ZERO,
+                displayed_qty: Decimal::ZERO,
+            });
+        }
+    }
+
+    // Turns a stop order into the order type it is matched as
+    fn trigger_stop_order(&mut self, order: &mut TradeOrder<P::Qty>, trigger: Price) {
+        self.emit(Notification::StopTriggered {
+            order_id: order.id,
+            side: order.side,
+            account: order.account,
+            trigger,
+        });
+        order.order_type = order.order_type.triggered();
+    }
+
+    // Walks the executions as they are produced, releasing any stop orders they trigger and matching them in turn.
+    // Executions from triggered orders are appended, so cascades are picked up by the same loop.
+    fn process_triggers(&mut self, executions: &mut Vec<TradeExecution>) {
+        let mut next = 0;
+        while next < executions.len() {
+            let price = executions[next].price;
+            next += 1;
+            self.last_trade_price = Some(price);
+            // Trades print at the prices of the book's levels, so they convert
+            let Some(key) = self.asks.price_key(&price) else {
+                continue;
+            };
+
+            let mut triggered = self.stop_bids.take_triggered(key);
+            triggered.append(&mut self.stop_asks.take_triggered(key));
+            for mut order in triggered {
+                self.stop_loc.remove(&order.id);
+                self.record_departed(order.id);
+                if let Some(trigger) = order.order_type.trigger_price() {
+                    self.trigger_stop_order(&mut order, trigger);
+                }
+                let (_, mut order_executions) = self.execute_order(order);
+                executions.append(&mut order_executions);
+            }
+        }
+    }
+
+    fn is_stop_triggered(&self, side: &Side, trigger: &P) -> bool {
+        self.last_trade_price
+            .and_then(|price| self.asks.price_key(&price))
+            .is_some_and(|price| self.get_trigger_book(side).is_triggered(trigger, &price))
+    }
+
+    pub fn add_stop_order(
+        &mut self,
+        side: Side,
+        trigger: impl Into<Price>,
+        order: TradeOrder,
+    ) -> Result<()> {
+        let trigger = self.validate_resting_order(&trigger.into(), &order)?;
+        let order = self.book_order(order)?;
+        self.rest_stop_order(side, trigger, order);
+        Ok(())
+    }
+
+    pub fn add_limit_order(
+        &mut self,
+        side: Side,
+        price: impl Into<Price>,
+        order: TradeOrder,
+    ) -> Result<()> {
+        let key = self.validate_resting_order(&price.into(), &order)?;
+        let order = self.book_order(order)?;
+        self.rest_order(side, key, order);
+        Ok(())
+    }
+
+    // When a system order is added to the orderbook we need to merge it with the existing order if it exists
+    pub fn add_system_order(
+        &mut self,
+        side: Side,
+        price: impl Into<Price>,
+        order: TradeOrder,
+    ) -> Result<()> {
+        let key = self.check_price(&price.into())?;
+        let order = self.book_order(order)?;
+        self.merge_system_order(side, key, order)
+    }
+
+    fn merge_system_order(&mut self, side: Side, key: P, order: TradeOrder<P::Qty>) -> Result<()> {
+        let price = self.price_of_key(&key);
+        let qty_scale = self.bids.qty_scale;
+        match self.get_order_mut(&order.id) {
+            Some(existing_order) => {
+                let order_id = order.id;
+                let old_qty = existing_order.remaining_qty;
+                if existing_order.merge(order).is_some() {
+                    return Err(OrderBookError::DuplicateOrderId(order_id));
+                }
+                let qty = existing_order.remaining_qty;
+                let displayed_qty = existing_order.visible_qty();
+                let account = existing_order.account;
+                self.emit(Notification::OrderAmended {
+                    order_id,
+                    side,
+                    account,
+                    old_price: price,
+                    old_qty: old_qty.to_qty(qty_scale),
+                    price,
+                    qty: qty.to_qty(qty_scale),
+                    displayed_qty: displayed_qty.to_qty(qty_scale),
+                    kept_priority: true,
+                });
+            }
+            None => self.rest_order(side, key, order),
+        };
+        Ok(())
+    }
+
+    // Checks an order placed straight onto the book, returns the price as the book keys it
+    fn validate_resting_order(&self, price: &Price, order: &TradeOrder) -> Result<P> {
+        let key = self.check_price(price)?;
+        self.check_qty(&order.remaining_qty)?;
+        if self.contains_order(&order.id) {
+            return Err(OrderBookError::DuplicateOrderId(order.id));
+        }
+        Ok(key)
+    }
+
+    fn rest_stop_order(&mut self, side: Side, trigger: P, order: TradeOrder<P::Qty>) {
+        self.stop_loc.insert(order.id, (side, trigger));
+        if let Some(expiry) = order.expires_at() {
+            self.expiry_index.insert((expiry, order.id));
+        }
+        self.get_mut_trigger_book(&side).add_order(trigger, order);
+    }
+
+    fn rest_order(&mut self, side: Side, key: P, order: TradeOrder<P::Qty>) {
+        self.order_loc.insert(order.id, (side, key));
+        if let Some(expiry) = order.expires_at() {
+            self.expiry_index.insert((expiry, order.id));
+        }
+        self.emit(Notification::OrderAdded {
+            order_id: order.id,
+            side,
+            account: order.account,
+            price: self.price_of_key(&key),
+            qty: self.qty_of_key(order.remaining_qty),
+            displayed_qty: self.qty_of_key(order.visible_qty()),
+        });
+        self.get_mut_book(&side).add_order(key, order);
+    }
+
+    // Drops the lookups of an order that has been taken off the book during matching
+    fn forget_order(&mut self, order: &TradeOrder<P::Qty>) {
+        self.record_departed(order.id);
+        self.order_loc.remove(&order.id);
+        if let Some(expiry) = order.expires_at() {
+            self.expiry_index.remove(&(expiry, order.id));
+        }
+    }
+
+    fn record_departed(&mut self, order_id: OrderId) {
+        if let Some(departed) = self.departed.as_mut() {
+            departed.push(order_id);
+        }
+    }
+
+    pub fn spread(&self) -> Option<Price> {
+        match (self.best_ask(), self.best_bid()) {
+            (Some(ask), Some(bid)) if ask > bid => Some(ask - bid),
+            _ => None,
+        }
+    }
+
+    pub fn get_depth(&self) -> (usize, usize) {
+        (self.asks.get_depth(), self.bids.get_depth())
+    }
+
+    fn get_book(&self, side: &Side) -> &HalfBook<P> {
+        match side {
+            Side::Ask => &self.asks,
+            Side::Bid => &self.bids,
+        }
+    }
+
+    fn get_mut_book(&mut self, side: &Side) -> &mut HalfBook<P> {
+        match side {
+            Side::Ask => &mut self.asks,
+            Side::Bid => &mut self.bids,
+        }
+    }
+
+    fn get_trigger_book(&self, side: &Side) -> &TriggerBook<P> {
+        match side {
+            Side::Ask => &self.stop_asks,
+            Side::Bid => &self.stop_bids,
+        }
+    }
+
+    fn get_mut_trigger_book(&mut self, side: &Side) -> &mut TriggerBook<P> {
+        match side {
+            Side::Ask => &mut self.stop_asks,
+            Side::Bid => &mut self.stop_bids,
+        }
+    }
+
+    /// Captures every order on the book, see `OrderBookSnapshot`.
+    pub fn snapshot(&self) -> OrderBookSnapshot {
+        fn levels<'a, Q: QuantityType + 'a>(
+            levels: impl Iterator<Item = (Price, impl Iterator<Item = &'a TradeOrder<Q>>)>,
+            qty_scale: u32,
+        ) -> Vec<LevelSnapshot> {
+            levels
+                .map(|(price, orders)| LevelSnapshot {
+                    price,
+                    orders: orders
+                        .map(|order| OrderSnapshot::from(&order.clone().into_decimal(qty_scale)))
+                        .collect(),
+                })
+                .collect()
+        }
+        let qty_scale = self.bids.qty_scale;
+        OrderBookSnapshot {
+            scale: self.bids.scale,
+            qty_scale,
+            last_trade_price: self.last_trade_price,
+            in_auction: self.in_auction,
+            bids: levels(self.bids.iter_orders(), qty_scale),
+            asks: levels(self.asks.iter_orders(), qty_scale),
+            stop_bids: levels(self.stop_bids.iter_orders(), qty_scale),
+            stop_asks: levels(self.stop_asks.iter_orders(), qty_scale),
+        }
+    }
+
+    pub fn get_order_book_state(&self) -> OrderBookState {
+        let mut ask = self.asks.get_levels();
+        ask.reverse();
+
+        OrderBookState {
+            asks: ask,
+            bids: self.bids.get_levels(),
+        }
+    }
+
+    pub fn get_orders_at_price(
+        &self,
+        side: Side,
+        price: impl Into<Price>,
+    ) -> Option<Vec<&TradeOrder<P::Qty>>> {
+        self.get_book(&side).get_orders_at_price(price)
+    }
+
+    pub fn get_total_volume(&self) -> Quantity {
+        self.asks.get_total_volume() + self.bids.get_total_volume()
+    }
+
+    pub fn get_price_range(&self) -> Option<(Price, Price)> {
+        Some((self.asks.get_price_range()?, self.bids.get_price_range()?))
+    }
+
+    /// Returns a resting order, its quantities held in the book's quantity type.
+    pub fn get_order(&self, order_id: OrderId) -> Option<&TradeOrder<P::Qty>> {
+        let (side, _) = self.order_loc.get(&order_id)?;
+        self.get_book(side).orders.get(&order_id)
+    }
+
+    pub fn get_order_mut(&mut self, order_id: &OrderId) -> Option<&mut TradeOrder<P::Qty>> {
+        self.order_loc
+            .get(order_id)
+            .and_then(|(side, key)| match side {
+                Side::Ask => self.asks.get_order_mut(key, order_id),
+                Side::Bid => self.bids.get_order_mut(key, order_id),
+            })
+    }
+
+    /// Returns the side, price and remaining quantity of a resting order.
+    pub fn open_order(&self, order_id: OrderId) -> Option<(Side, Price, Quantity)> {
+        let (side, key) = self.order_loc.get(&order_id)?;
+        let order = self.get_order(order_id)?;
+        Some((
+            *side,
+            self.price_of_key(key),
+            self.qty_of_key(order.remaining_qty),
+        ))
+    }
+
+    pub fn get_volume_at_price(&self, side: &Side, price: &Price) -> Option<Quantity> {
+        self.get_book(side).get_total_qty(price)
+    }
+
+    pub fn get_order_count(&self) -> usize {
+        self.order_loc.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.asks.is_empty() && self.bids.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.asks.clear();
+        self.bids.clear();
+        self.order_loc.clear();
+        self.stop_asks.clear();
+        self.stop_bids.clear();
+        self.stop_loc.clear();
+        self.expiry_index.clear();
+        self.last_trade_price = None;
+        self.indicative = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::NotificationKind;
+    use crate::MatchingAlgorithm;
 
     #[test]
     fn test_half_book_add_order() {
         let mut book = HalfBook::new(Side::Ask);
         let order = TradeOrder::new(100);
-        book.add_order(10, order);
+        book.add_order(10.into(), order);
         assert_eq!(book.best_price(), Some(10.into()));
     }
 
@@ -476,16 +1622,41 @@ mod tests {
         let mut book = HalfBook::new(Side::Ask);
         let order = TradeOrder::new(100);
         let order_id = order.id;
-        book.add_order(10, order);
+        book.add_order(10.into(), order);
         assert!(book.remove_order(&10.into(), order_id).is_some());
         assert!(book.best_price().is_none());
     }
 
+    #[test]
+    fn test_half_book_best_price_and_levels() {
+        let mut book = HalfBook::new(Side::Bid);
+        for (price, qty) in [(10, 5), (12, 7), (11, 3)] {
+            book.add_order(price.into(), TradeOrder::new(qty));
+        }
+        assert_eq!(book.best_price(), Some(12.into()));
+        assert_eq!(
+            book.get_levels(),
+            vec![
+                (12.into(), 7.into()),
+                (11.into(), 3.into()),
+                (10.into(), 5.into())
+            ]
+        );
+        assert_eq!(book.get_price_range(), Some(2.into()));
+
+        let mut incoming = TradeOrder::new(10);
+        book.match_order(&mut incoming, 12, timestamp());
+        assert_eq!(book.best_price(), Some(11.into()));
+        book.match_order(&mut incoming, 11, timestamp());
+        assert_eq!(book.best_price(), Some(10.into()));
+        assert_eq!(book.get_depth(), 1);
+    }
+
     #[test]
     fn test_order_book_add_order() {
         let mut book = OrderBook::default();
         let order = OrderRequest::new(Side::Ask, 100, OrderType::limit(10));
-        let (result, executions) = book.add_order(order);
+        let (result, executions) = book.add_order(order).unwrap();
         assert_eq!(result.status, OrderStatus::Open);
         assert!(executions.is_empty());
         assert_eq!(book.best_ask(), Some(10.into()));
@@ -496,7 +1667,7 @@ mod tests {
         let mut book = OrderBook::default();
         let order1 = OrderRequest::new(Side::Ask, 100, OrderType::system_level(10));
         let id1 = order1.id();
-        let (result, executions) = book.add_order(order1);
+        let (result, executions) = book.add_order(order1).unwrap();
         assert_eq!(result.status, OrderStatus::Open);
         assert!(executions.is_empty());
         assert_eq!(book.best_ask(), Some(10.into()));
@@ -504,7 +1675,7 @@ mod tests {
         let order2 = OrderRequest::new(Side::Ask, 50, OrderType::system_level(10));
         let id2 = order2.id();
         assert_eq!(id1, id2);
-        let (result, executions) = book.add_order(order2);
+        let (result, executions) = book.add_order(order2).unwrap();
         assert_eq!(result.status, OrderStatus::Open);
         assert!(executions.is_empty());
         assert_eq!(book.best_ask(), Some(10.into()));
@@ -512,7 +1683,7 @@ mod tests {
         let order1 = book.get_order(id1).unwrap();
         assert_eq!(order1.remaining_qty, 150.into());
         let order3 = OrderRequest::new(Side::Bid, 200, OrderType::system_level(10));
-        let (result, executions) = book.add_order(order3);
+        let (result, executions) = book.add_order(order3).unwrap();
         assert_eq!(result.status, OrderStatus::PartiallyFilled);
         assert!(!executions.is_empty());
         assert_eq!(book.best_ask(), None);
@@ -523,9 +1694,9 @@ mod tests {
     fn test_order_book_match_orders() {
         let mut book = OrderBook::default();
         let ask_order = OrderRequest::new(Side::Ask, 100, OrderType::limit(10));
-        book.add_order(ask_order);
+        book.add_order(ask_order).unwrap();
         let bid_order = OrderRequest::new(Side::Bid, 50, OrderType::limit(10));
-        let (result, executions) = book.add_order(bid_order);
+        let (result, executions) = book.add_order(bid_order).unwrap();
         assert_eq!(result.status, OrderStatus::Filled);
         assert_eq!(executions.len(), 1);
         assert_eq!(executions[0].qty, 50.into());
@@ -535,9 +1706,9 @@ mod tests {
     fn test_order_book_market_order() {
         let mut book = OrderBook::default();
         let ask_order = OrderRequest::new(Side::Ask, 100, OrderType::limit(10));
-        book.add_order(ask_order);
+        book.add_order(ask_order).unwrap();
         let market_order = OrderRequest::new(Side::Bid, 50, OrderType::Market);
-        let (result, executions) = book.add_order(market_order);
+        let (result, executions) = book.add_order(market_order).unwrap();
         assert_eq!(result.status, OrderStatus::Filled);
         assert_eq!(executions.len(), 1);
         assert_eq!(executions[0].qty, 50.into());
@@ -562,19 +1733,19 @@ mod tests {
         let mut book = OrderBook::default();
 
         // Add a bid order
-        let (bid_result, _) = book.add_order(limit_order(Side::Bid, 100, 10));
+        let (bid_result, _) = book.add_order(limit_order(Side::Bid, 100, 10)).unwrap();
         assert_eq!(book.best_bid(), Some(10.into()));
 
         // Add an ask order
-        let (ask_result, _) = book.add_order(limit_order(Side::Ask, 100, 11));
+        let (ask_result, _) = book.add_order(limit_order(Side::Ask, 100, 11)).unwrap();
         assert_eq!(book.best_ask(), Some(11.into()));
 
         // Remove the bid order
-        book.delete_order(bid_result.get_id());
+        book.delete_order(bid_result.get_id()).unwrap();
         assert_eq!(book.best_bid(), None);
 
         // Remove the ask order
-        book.delete_order(ask_result.get_id());
+        book.delete_order(ask_result.get_id()).unwrap();
         assert_eq!(book.best_ask(), None);
     }
 
@@ -583,12 +1754,12 @@ mod tests {
         let mut book = OrderBook::default();
 
         // Add some initial orders
-        book.add_order(limit_order(Side::Ask, 100, 10));
-        book.add_order(limit_order(Side::Ask, 100, 11));
-        book.add_order(limit_order(Side::Bid, 100, 9));
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        book.add_order(limit_order(Side::Ask, 100, 11)).unwrap();
+        book.add_order(limit_order(Side::Bid, 100, 9)).unwrap();
 
         // Add a matching bid order
-        let (result, executions) = book.add_order(limit_order(Side::Bid, 150, 10));
+        let (result, executions) = book.add_order(limit_order(Side::Bid, 150, 10)).unwrap();
 
         assert_eq!(result.status, OrderStatus::PartiallyFilled);
         assert_eq!(executions.len(), 1);
@@ -603,12 +1774,13 @@ mod tests {
         let mut book = OrderBook::default();
 
         // Add some limit orders
-        book.add_order(limit_order(Side::Ask, 50, 10));
-        book.add_order(limit_order(Side::Ask, 100, 11));
+        book.add_order(limit_order(Side::Ask, 50, 10)).unwrap();
+        book.add_order(limit_order(Side::Ask, 100, 11)).unwrap();
 
         // Add a market buy order
-        let (result, executions) =
-            book.add_order(OrderRequest::new(Side::Bid, 200, OrderType::Market));
+        let (result, executions) = book
+            .add_order(OrderRequest::new(Side::Bid, 200, OrderType::Market))
+            .unwrap();
 
         assert_eq!(result.status, OrderStatus::PartiallyFilled);
         assert_eq!(executions.len(), 2);
@@ -623,11 +1795,12 @@ mod tests {
         let mut book = OrderBook::default();
 
         // Add a limit sell order
-        book.add_order(limit_order(Side::Ask, 100, 10));
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
 
         // Add an IOC buy order that partially fills
-        let (result, executions) =
-            book.add_order(OrderRequest::new(Side::Bid, 150, OrderType::ioc(10)));
+        let (result, executions) = book
+            .add_order(OrderRequest::new(Side::Bid, 150, OrderType::ioc(10)))
+            .unwrap();
 
         assert_eq!(result.status, OrderStatus::PartiallyFilled);
         assert_eq!(executions.len(), 1);
@@ -641,12 +1814,13 @@ mod tests {
         let mut book = OrderBook::default();
 
         // Add some limit sell orders
-        book.add_order(limit_order(Side::Ask, 50, 10));
-        book.add_order(limit_order(Side::Ask, 50, 10));
+        book.add_order(limit_order(Side::Ask, 50, 10)).unwrap();
+        book.add_order(limit_order(Side::Ask, 50, 10)).unwrap();
 
         // Add a FOK buy order that fully fills
-        let (result, executions) =
-            book.add_order(OrderRequest::new(Side::Bid, 100, OrderType::fok(10)));
+        let (result, executions) = book
+            .add_order(OrderRequest::new(Side::Bid, 100, OrderType::fok(10)))
+            .unwrap();
 
         assert_eq!(result.status, OrderStatus::Filled);
         assert_eq!(executions.len(), 2);
@@ -655,8 +1829,9 @@ mod tests {
         assert_eq!(book.best_ask(), None);
 
         // Add a FOK buy order that doesn't fill
-        let (result, executions) =
-            book.add_order(OrderRequest::new(Side::Bid, 100, OrderType::fok(9)));
+        let (result, executions) = book
+            .add_order(OrderRequest::new(Side::Bid, 100, OrderType::fok(9)))
+            .unwrap();
 
         assert_eq!(result.status, OrderStatus::Cancelled);
         assert!(executions.is_empty());
@@ -667,14 +1842,14 @@ mod tests {
         let mut book = OrderBook::default();
 
         // Add multiple orders at the same price level
-        book.add_order(limit_order(Side::Ask, 100, 10));
-        book.add_order(limit_order(Side::Ask, 100, 10));
-        book.add_order(limit_order(Side::Ask, 100, 11));
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        book.add_order(limit_order(Side::Ask, 100, 11)).unwrap();
 
         assert_eq!(book.best_ask(), Some(10.into()));
 
         // Match against the first price level
-        let (_, executions) = book.add_order(limit_order(Side::Bid, 150, 10));
+        let (_, executions) = book.add_order(limit_order(Side::Bid, 150, 10)).unwrap();
 
         assert_eq!(executions.len(), 2);
         assert_eq!(executions[0].qty, 100.into());
@@ -682,7 +1857,7 @@ mod tests {
         assert_eq!(book.best_ask(), Some(10.into()));
 
         // Match the remaining order at the first price level
-        let (_, executions) = book.add_order(limit_order(Side::Bid, 100, 10));
+        let (_, executions) = book.add_order(limit_order(Side::Bid, 100, 10)).unwrap();
 
         assert_eq!(executions.len(), 1);
         assert_eq!(executions[0].qty, 50.into());
@@ -694,8 +1869,8 @@ mod tests {
         let mut book = OrderBook::default();
 
         // Add some orders
-        let (bid_result, _) = book.add_order(limit_order(Side::Bid, 100, 10));
-        let (ask_result, _) = book.add_order(limit_order(Side::Ask, 100, 11));
+        let (bid_result, _) = book.add_order(limit_order(Side::Bid, 100, 10)).unwrap();
+        let (ask_result, _) = book.add_order(limit_order(Side::Ask, 100, 11)).unwrap();
 
         // Cancel the bid order
         let cancelled_bid = book.delete_order(bid_result.get_id()).unwrap();
@@ -703,7 +1878,7 @@ mod tests {
         assert_eq!(book.best_bid(), None);
 
         // Try to cancel the same order again
-        assert!(book.delete_order(bid_result.get_id()).is_none());
+        assert!(book.delete_order(bid_result.get_id()).is_err());
 
         // Cancel the ask order
         let cancelled_ask = book.delete_order(ask_result.get_id()).unwrap();
@@ -716,16 +1891,17 @@ mod tests {
         let mut book = OrderBook::default();
 
         // Add initial orders
-        book.add_order(limit_order(Side::Ask, 100, 10));
-        book.add_order(limit_order(Side::Ask, 200, 11));
-        book.add_order(limit_order(Side::Ask, 300, 12));
-        book.add_order(limit_order(Side::Bid, 100, 8));
-        book.add_order(limit_order(Side::Bid, 200, 7));
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        book.add_order(limit_order(Side::Ask, 200, 11)).unwrap();
+        book.add_order(limit_order(Side::Ask, 300, 12)).unwrap();
+        book.add_order(limit_order(Side::Bid, 100, 8)).unwrap();
+        book.add_order(limit_order(Side::Bid, 200, 7)).unwrap();
 
         println!("{:#?}", book);
         // Add a large market buy order
-        let (result, executions) =
-            book.add_order(OrderRequest::new(Side::Bid, 650, OrderType::Market));
+        let (result, executions) = book
+            .add_order(OrderRequest::new(Side::Bid, 650, OrderType::Market))
+            .unwrap();
 
         assert_eq!(result.status, OrderStatus::PartiallyFilled);
         assert_eq!(executions.len(), 3);
@@ -736,8 +1912,9 @@ mod tests {
         assert_eq!(executions[2].qty, 300.into());
         assert_eq!(executions[2].price, 12.into());
 
-        let (result, executions) =
-            book.add_order(OrderRequest::new(Side::Ask, 110, OrderType::Market));
+        let (result, executions) = book
+            .add_order(OrderRequest::new(Side::Ask, 110, OrderType::Market))
+            .unwrap();
 
         assert_eq!(result.status, OrderStatus::Filled);
         assert_eq!(executions.len(), 2);
@@ -760,9 +1937,9 @@ mod tests {
     fn test_half_book_get_levels() {
         let mut book = HalfBook::new(Side::Ask);
         assert_eq!(book.get_levels(), vec![]);
-        book.add_order(10, TradeOrder::new(100));
-        book.add_order(10, TradeOrder::new(50));
-        book.add_order(11, TradeOrder::new(75));
+        book.add_order(10.into(), TradeOrder::new(100));
+        book.add_order(10.into(), TradeOrder::new(50));
+        book.add_order(11.into(), TradeOrder::new(75));
 
         let levels = book.get_levels();
         assert_eq!(
@@ -775,9 +1952,9 @@ mod tests {
     fn test_half_book_get_total_volume() {
         let mut book = HalfBook::new(Side::Bid);
         assert_eq!(book.get_total_volume(), 0.into());
-        book.add_order(10, TradeOrder::new(100));
-        book.add_order(11, TradeOrder::new(50));
-        book.add_order(9, TradeOrder::new(75));
+        book.add_order(10.into(), TradeOrder::new(100));
+        book.add_order(11.into(), TradeOrder::new(50));
+        book.add_order(9.into(), TradeOrder::new(75));
 
         assert_eq!(book.get_total_volume(), 225.into());
     }
@@ -787,8 +1964,8 @@ mod tests {
         let mut book = HalfBook::new(Side::Ask);
         let order1 = TradeOrder::new(100);
         let order2 = TradeOrder::new(50);
-        book.add_order(10, order1.clone());
-        book.add_order(10, order2.clone());
+        book.add_order(10.into(), order1.clone());
+        book.add_order(10.into(), order2.clone());
 
         let orders = book.get_orders_at_price(10).unwrap();
         assert_eq!(orders.len(), 2);
@@ -802,10 +1979,10 @@ mod tests {
         let state = book.get_order_book_state();
         assert_eq!(state.asks, vec![]);
         assert_eq!(state.bids, vec![]);
-        book.add_order(limit_order(Side::Ask, 100, 10));
-        book.add_order(limit_order(Side::Ask, 50, 11));
-        book.add_order(limit_order(Side::Bid, 75, 9));
-        book.add_order(limit_order(Side::Bid, 25, 8));
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        book.add_order(limit_order(Side::Ask, 50, 11)).unwrap();
+        book.add_order(limit_order(Side::Bid, 75, 9)).unwrap();
+        book.add_order(limit_order(Side::Bid, 25, 8)).unwrap();
 
         let state = book.get_order_book_state();
         assert_eq!(
@@ -822,8 +1999,8 @@ mod tests {
     fn test_order_book_get_orders_at_price() {
         let mut book = OrderBook::default();
         assert_eq!(book.get_orders_at_price(Side::Ask, 10), None);
-        book.add_order(limit_order(Side::Ask, 100, 10));
-        book.add_order(limit_order(Side::Ask, 50, 10));
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        book.add_order(limit_order(Side::Ask, 50, 10)).unwrap();
 
         let ask_orders = book.get_orders_at_price(Side::Ask, 10).unwrap();
         assert_eq!(ask_orders.len(), 2);
@@ -837,10 +2014,10 @@ mod tests {
     fn test_order_book_get_total_volume() {
         let mut book = OrderBook::default();
         assert_eq!(book.get_total_volume(), 0.into());
-        book.add_order(limit_order(Side::Ask, 100, 10));
-        book.add_order(limit_order(Side::Ask, 50, 11));
-        book.add_order(limit_order(Side::Bid, 75, 9));
-        book.add_order(limit_order(Side::Bid, 25, 8));
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        book.add_order(limit_order(Side::Ask, 50, 11)).unwrap();
+        book.add_order(limit_order(Side::Bid, 75, 9)).unwrap();
+        book.add_order(limit_order(Side::Bid, 25, 8)).unwrap();
 
         assert_eq!(book.get_total_volume(), 250.into());
     }
@@ -851,10 +2028,10 @@ mod tests {
         assert_eq!(book.get_depth(), (0, 0));
         assert_eq!(book.get_price_range(), None);
 
-        book.add_order(limit_order(Side::Ask, 100, 10));
-        book.add_order(limit_order(Side::Ask, 50, 15));
-        book.add_order(limit_order(Side::Bid, 75, 8));
-        book.add_order(limit_order(Side::Bid, 25, 5));
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        book.add_order(limit_order(Side::Ask, 50, 15)).unwrap();
+        book.add_order(limit_order(Side::Bid, 75, 8)).unwrap();
+        book.add_order(limit_order(Side::Bid, 25, 5)).unwrap();
 
         assert_eq!(book.get_depth(), (2, 2));
         assert_eq!(book.get_price_range(), Some((5.into(), 3.into())));
@@ -863,12 +2040,12 @@ mod tests {
     #[test]
     fn test_half_book_match_order() {
         let mut book = HalfBook::new(Side::Ask);
-        book.add_order(10, TradeOrder::new(100));
-        book.add_order(10, TradeOrder::new(50));
-        book.add_order(11, TradeOrder::new(75));
+        book.add_order(10.into(), TradeOrder::new(100));
+        book.add_order(10.into(), TradeOrder::new(50));
+        book.add_order(11.into(), TradeOrder::new(75));
 
         let mut incoming_order = TradeOrder::new(125);
-        let executions = book.match_order(&mut incoming_order, 10);
+        let executions = book.match_order(&mut incoming_order, 10, timestamp());
 
         assert_eq!(executions.len(), 2);
         assert_eq!(executions[0].qty, 100.into());
@@ -881,7 +2058,7 @@ mod tests {
     #[test]
     fn test_orderbook_get_order() {
         let mut book = OrderBook::default();
-        let (result, _) = book.add_order(limit_order(Side::Ask, 100, 10));
+        let (result, _) = book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
         let order_id = result.get_id();
 
         assert!(book.get_order(order_id).is_some());
@@ -892,7 +2069,7 @@ mod tests {
     #[test]
     fn test_orderbook_get_order_mut() {
         let mut book = OrderBook::default();
-        let (result, _) = book.add_order(limit_order(Side::Ask, 100, 10));
+        let (result, _) = book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
         let order_id = result.get_id();
 
         if let Some(order) = book.get_order_mut(&order_id) {
@@ -905,9 +2082,9 @@ mod tests {
     #[test]
     fn test_orderbook_get_volume_at_price() {
         let mut book = OrderBook::default();
-        book.add_order(limit_order(Side::Ask, 100, 10));
-        book.add_order(limit_order(Side::Ask, 50, 10));
-        book.add_order(limit_order(Side::Bid, 75, 9));
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        book.add_order(limit_order(Side::Ask, 50, 10)).unwrap();
+        book.add_order(limit_order(Side::Bid, 75, 9)).unwrap();
 
         assert_eq!(
             book.get_volume_at_price(&Side::Ask, &10.into()),
@@ -923,9 +2100,9 @@ mod tests {
     #[test]
     fn test_orderbook_get_order_count() {
         let mut book = OrderBook::default();
-        book.add_order(limit_order(Side::Ask, 100, 10));
-        book.add_order(limit_order(Side::Ask, 50, 11));
-        book.add_order(limit_order(Side::Bid, 75, 9));
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        book.add_order(limit_order(Side::Ask, 50, 11)).unwrap();
+        book.add_order(limit_order(Side::Bid, 75, 9)).unwrap();
 
         assert_eq!(book.get_order_count(), 3);
     }
@@ -935,15 +2112,15 @@ mod tests {
         let mut book = OrderBook::default();
         assert!(book.is_empty());
 
-        book.add_order(limit_order(Side::Ask, 100, 10));
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
         assert!(!book.is_empty());
     }
 
     #[test]
     fn test_orderbook_clear() {
         let mut book = OrderBook::default();
-        book.add_order(limit_order(Side::Ask, 100, 10));
-        book.add_order(limit_order(Side::Bid, 75, 9));
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        book.add_order(limit_order(Side::Bid, 75, 9)).unwrap();
 
         assert!(!book.is_empty());
         book.clear();
@@ -956,7 +2133,7 @@ mod tests {
         let mut book = HalfBook::new(Side::Ask);
         assert!(book.is_empty());
 
-        book.add_order(10, TradeOrder::new(100));
+        book.add_order(10.into(), TradeOrder::new(100));
         assert!(!book.is_empty());
     }
 
@@ -965,7 +2142,7 @@ mod tests {
         let mut book = HalfBook::new(Side::Ask);
         let order = TradeOrder::new(100);
         let order_id = order.id;
-        book.add_order(10, order);
+        book.add_order(10.into(), order);
 
         assert!(book.get_order(10, order_id).is_some());
         assert_eq!(
@@ -980,7 +2157,7 @@ mod tests {
         let mut book = HalfBook::new(Side::Ask);
         let order = TradeOrder::new(100);
         let order_id = order.id;
-        book.add_order(10, order);
+        book.add_order(10.into(), order);
 
         if let Some(order) = book.get_order_mut(&10.into(), &order_id) {
             order.remaining_qty = 50.into();
@@ -996,9 +2173,9 @@ mod tests {
     fn test_halfbook_get_order_count() {
         let mut book = HalfBook::new(Side::Ask);
         assert_eq!(book.get_order_count(), 0);
-        book.add_order(10, TradeOrder::new(100));
-        book.add_order(10, TradeOrder::new(50));
-        book.add_order(11, TradeOrder::new(75));
+        book.add_order(10.into(), TradeOrder::new(100));
+        book.add_order(10.into(), TradeOrder::new(50));
+        book.add_order(11.into(), TradeOrder::new(75));
 
         assert_eq!(book.get_order_count(), 3);
     }
@@ -1007,8 +2184,8 @@ mod tests {
     fn test_halfbook_clear() {
         let mut book = HalfBook::new(Side::Ask);
         assert!(book.is_empty());
-        book.add_order(10, TradeOrder::new(100));
-        book.add_order(11, TradeOrder::new(75));
+        book.add_order(10.into(), TradeOrder::new(100));
+        book.add_order(11.into(), TradeOrder::new(75));
 
         assert!(!book.is_empty());
         book.clear();
@@ -1021,8 +2198,8 @@ mod tests {
         let mut book = OrderBook::default();
 
         // Add some orders
-        let (bid_result, _) = book.add_order(limit_order(Side::Bid, 100, 10));
-        let (ask_result, _) = book.add_order(limit_order(Side::Ask, 100, 11));
+        let (bid_result, _) = book.add_order(limit_order(Side::Bid, 100, 10)).unwrap();
+        let (ask_result, _) = book.add_order(limit_order(Side::Ask, 100, 11)).unwrap();
 
         // Cancel the bid order
         let cancelled_bid = book.cancel_order(bid_result.get_id(), 50).unwrap();
@@ -1040,7 +2217,1208 @@ mod tests {
         assert_eq!(cancelled_ask.remaining_qty, 0.into());
 
         // Try to cancel the same order again
-        book.cancel_order(ask_result.get_id(), 50);
+        assert_eq!(
+            book.cancel_order(ask_result.get_id(), 50).unwrap_err(),
+            OrderBookError::OrderNotFound(ask_result.get_id())
+        );
         assert!(book.get_order(ask_result.get_id()).is_none());
     }
+
+    #[test]
+    fn test_stop_order_triggers_after_trade() {
+        let mut book = OrderBook::default();
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        book.add_order(limit_order(Side::Ask, 100, 11)).unwrap();
+
+        // Buy stop rests in the trigger book until a trade prints at or above 10
+        let (result, executions) = book
+            .add_order(OrderRequest::new(Side::Bid, 50, OrderType::stop(10)))
+            .unwrap();
+        assert_eq!(result.status, OrderStatus::Open);
+        assert!(executions.is_empty());
+        assert_eq!(book.stop_bids.get_order_count(), 1);
+        assert_eq!(
+            book.get_volume_at_price(&Side::Ask, &10.into()),
+            Some(100.into())
+        );
+
+        // The trade at 10 releases the stop as a market order in the same call
+        let (result, executions) = book.add_order(limit_order(Side::Bid, 80, 10)).unwrap();
+        assert_eq!(result.status, OrderStatus::Filled);
+        assert_eq!(executions.len(), 3);
+        assert_eq!(executions[1].qty, 20.into());
+        assert_eq!(executions[1].price, 10.into());
+        assert_eq!(executions[2].qty, 30.into());
+        assert_eq!(executions[2].price, 11.into());
+        assert!(book.stop_bids.is_empty());
+        assert_eq!(book.last_trade_price(), Some(11.into()));
+    }
+
+    #[test]
+    fn test_departed_orders_are_tracked() {
+        let mut book = OrderBook::default();
+        book.track_departed();
+        let (filled, _) = book.add_order(limit_order(Side::Ask, 50, 10)).unwrap();
+        let (resting, _) = book.add_order(limit_order(Side::Ask, 100, 11)).unwrap();
+        let (stop, _) = book
+            .add_order(OrderRequest::new(Side::Bid, 10, OrderType::stop(10)))
+            .unwrap();
+        assert!(book.take_departed().is_empty());
+
+        // The fill at 10 takes the first ask off the book and triggers the stop, which trades part of the second ask
+        book.add_order(limit_order(Side::Bid, 50, 10)).unwrap();
+        assert_eq!(book.take_departed(), vec![filled.get_id(), stop.get_id()]);
+
+        book.delete_order(resting.get_id()).unwrap();
+        assert_eq!(book.take_departed(), vec![resting.get_id()]);
+
+        // Books don't track departures unless asked to
+        let mut book = OrderBook::default();
+        let (order, _) = book.add_order(limit_order(Side::Ask, 50, 10)).unwrap();
+        book.delete_order(order.get_id()).unwrap();
+        assert!(book.take_departed().is_empty());
+    }
+
+    #[test]
+    fn test_stop_limit_order_rests_after_trigger() {
+        let mut book = OrderBook::default();
+        book.add_order(limit_order(Side::Bid, 100, 10)).unwrap();
+        book.add_order(OrderRequest::new(
+            Side::Ask,
+            50,
+            OrderType::stop_limit(10, 12),
+        ))
+        .unwrap();
+
+        let (_, executions) = book.add_order(limit_order(Side::Ask, 10, 10)).unwrap();
+        assert_eq!(executions.len(), 1);
+        assert!(book.stop_asks.is_empty());
+        assert_eq!(book.best_ask(), Some(12.into()));
+        assert_eq!(
+            book.get_volume_at_price(&Side::Ask, &12.into()),
+            Some(50.into())
+        );
+    }
+
+    #[test]
+    fn test_stop_order_cascade() {
+        let mut book = OrderBook::default();
+        book.add_order(limit_order(Side::Bid, 10, 10)).unwrap();
+        book.add_order(limit_order(Side::Bid, 10, 9)).unwrap();
+        book.add_order(limit_order(Side::Bid, 10, 8)).unwrap();
+        book.add_order(OrderRequest::new(Side::Ask, 10, OrderType::stop(10)))
+            .unwrap();
+        book.add_order(OrderRequest::new(Side::Ask, 10, OrderType::stop(9)))
+            .unwrap();
+
+        // Trade at 10 triggers the first stop, which trades at 9 and triggers the second
+        let (_, executions) = book
+            .add_order(OrderRequest::new(Side::Ask, 10, OrderType::Market))
+            .unwrap();
+        assert_eq!(executions.len(), 3);
+        assert_eq!(executions[0].price, 10.into());
+        assert_eq!(executions[1].price, 9.into());
+        assert_eq!(executions[2].price, 8.into());
+        assert!(book.stop_asks.is_empty());
+        assert!(book.bids.is_empty());
+    }
+
+    #[test]
+    fn test_stop_order_already_triggered() {
+        let mut book = OrderBook::default();
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        book.add_order(limit_order(Side::Bid, 10, 10)).unwrap();
+
+        let (result, executions) = book
+            .add_order(OrderRequest::new(Side::Bid, 10, OrderType::stop(9)))
+            .unwrap();
+        assert_eq!(result.status, OrderStatus::Filled);
+        assert_eq!(executions.len(), 1);
+        assert!(book.stop_bids.is_empty());
+    }
+
+    #[test]
+    fn test_delete_stop_order() {
+        let mut book = OrderBook::default();
+        let (result, _) = book
+            .add_order(OrderRequest::new(Side::Bid, 10, OrderType::stop(10)))
+            .unwrap();
+        let cancelled = book.delete_order(result.get_id()).unwrap();
+        assert_eq!(cancelled.status, OrderStatus::Cancelled);
+        assert!(book.stop_bids.is_empty());
+        assert!(book.delete_order(result.get_id()).is_err());
+    }
+
+    #[test]
+    fn test_iceberg_order_displays_peak() {
+        let mut book = OrderBook::default();
+        book.add_order(limit_order(Side::Ask, 100, 10).iceberg(20))
+            .unwrap();
+        book.add_order(limit_order(Side::Ask, 50, 10)).unwrap();
+
+        assert_eq!(
+            book.get_volume_at_price(&Side::Ask, &10.into()),
+            Some(70.into())
+        );
+        assert_eq!(
+            book.get_order_book_state().asks,
+            vec![(10.into(), 70.into())]
+        );
+        assert_eq!(book.get_total_volume(), 70.into());
+        // Hidden quantity is still available to fill or kill orders
+        assert_eq!(book.asks.get_available_quantity(10), 150.into());
+    }
+
+    #[test]
+    fn test_iceberg_peak_is_validated() {
+        let mut book = OrderBook::default();
+        assert_eq!(
+            book.add_order(limit_order(Side::Ask, 10, 100).iceberg(0))
+                .unwrap_err(),
+            OrderBookError::InvalidPeakQuantity(0.into())
+        );
+        assert_eq!(
+            book.add_order(limit_order(Side::Ask, 10, 100).iceberg(-1))
+                .unwrap_err(),
+            OrderBookError::InvalidPeakQuantity((-1).into())
+        );
+        // A peak larger than the order displays all of it
+        let (result, _) = book
+            .add_order(limit_order(Side::Ask, 10, 100).iceberg(50))
+            .unwrap();
+        let order = book.get_order(result.get_id()).unwrap();
+        assert_eq!(order.visible_qty(), 10.into());
+        assert_eq!(order.reserve_qty(), 0.into());
+    }
+
+    #[test]
+    fn test_level_without_displayed_quantity_is_skipped() {
+        let mut book = OrderBook::default();
+        // Slipped past validation, the order displays nothing
+        let hidden = TradeOrder::from(limit_order(Side::Ask, 10, 100).iceberg(0));
+        book.asks.add_order(100.into(), hidden);
+        book.add_order(limit_order(Side::Ask, 5, 101)).unwrap();
+
+        let (result, executions) = book.add_order(limit_order(Side::Bid, 5, 101)).unwrap();
+        assert_eq!(result.status, OrderStatus::Filled);
+        assert_eq!(executions.len(), 1);
+        assert_eq!(executions[0].price, 101.into());
+        assert_eq!(book.asks.best_price(), Some(100.into()));
+    }
+
+    #[test]
+    fn test_iceberg_order_loses_priority_on_refresh() {
+        let mut book = OrderBook::default();
+        let (iceberg, _) = book
+            .add_order(limit_order(Side::Ask, 100, 10).iceberg(20))
+            .unwrap();
+        let (other, _) = book.add_order(limit_order(Side::Ask, 50, 10)).unwrap();
+
+        // Consume the peak, the refreshed iceberg goes behind the other order
+        let (_, executions) = book.add_order(limit_order(Side::Bid, 20, 10)).unwrap();
+        assert_eq!(executions.len(), 1);
+        assert_eq!(executions[0].maker_order_id, iceberg.get_id());
+        let orders = book.get_orders_at_price(Side::Ask, 10).unwrap();
+        assert_eq!(orders[0].id, other.get_id());
+        assert_eq!(orders[1].id, iceberg.get_id());
+        assert_eq!(orders[1].visible_qty(), 20.into());
+        assert_eq!(orders[1].remaining_qty, 80.into());
+
+        // A large order walks through the other order and repeatedly through the iceberg peaks
+        let (result, executions) = book.add_order(limit_order(Side::Bid, 100, 10)).unwrap();
+        assert_eq!(result.status, OrderStatus::Filled);
+        assert_eq!(executions[0].maker_order_id, other.get_id());
+        assert_eq!(executions[0].qty, 50.into());
+        assert!(executions[1..]
+            .iter()
+            .all(|e| e.maker_order_id == iceberg.get_id() && e.qty <= 20.into()));
+        assert_eq!(
+            book.get_order(iceberg.get_id()).unwrap().remaining_qty,
+            30.into()
+        );
+        assert_eq!(
+            book.get_volume_at_price(&Side::Ask, &10.into()),
+            Some(10.into())
+        );
+    }
+
+    #[test]
+    fn test_post_only_order_rejected() {
+        let mut book = OrderBook::default();
+        book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+
+        let (result, executions) = book
+            .add_order(limit_order(Side::Bid, 50, 10).post_only(PostOnly::Reject))
+            .unwrap();
+        assert_eq!(
+            result.status,
+            OrderStatus::Rejected(RejectReason::PostOnlyWouldMatch)
+        );
+        assert!(executions.is_empty());
+        assert_eq!(book.best_bid(), None);
+        assert_eq!(
+            book.get_volume_at_price(&Side::Ask, &10.into()),
+            Some(100.into())
+        );
+
+        // Orders that do not cross rest as usual
+        let (result, _) = book
+            .add_order(limit_order(Side::Bid, 50, 9).post_only(PostOnly::Reject))
+            .unwrap();
+        assert_eq!(result.status, OrderStatus::Open);
+        assert_eq!(book.best_bid(), Some(9.into()));
+
+        // Market orders always take liquidity
+        let (result, _) = book
+            .add_order(
+                OrderRequest::new(Side::Ask, 10, OrderType::Market).post_only(PostOnly::Reject),
+            )
+            .unwrap();
+        assert_eq!(
+            result.status,
+            OrderStatus::Rejected(RejectReason::PostOnlyWouldMatch)
+        );
+    }
+
+    #[test]
+    fn test_post_only_order_repriced() {
+        let mut book = OrderBook::default();
+        book.add_order(limit_order(Side::Bid, 100, 10)).unwrap();
+
+        let tick = Decimal::new(1, 1);
+        let (result, executions) = book
+            .add_order(limit_order(Side::Ask, 50, 9).post_only(PostOnly::Reprice(tick)))
+            .unwrap();
+        assert_eq!(result.status, OrderStatus::Open);
+        assert!(executions.is_empty());
+        assert_eq!(book.best_ask(), Some(Decimal::new(101, 1)));
+        assert_eq!(book.best_bid(), Some(10.into()));
+
+        // A reprice off the book's scale is rejected rather than failing the call
+        let mut book = OrderBook::<SystemClock, Ticks>::with_scale(SystemClock, 0, 0);
+        book.add_order(limit_order(Side::Bid, 100, 10)).unwrap();
+        let (result, executions) = book
+            .add_order(limit_order(Side::Ask, 50, 9).post_only(PostOnly::Reprice(tick)))
+            .unwrap();
+        assert_eq!(
+            result.status,
+            OrderStatus::Rejected(RejectReason::PriceNotOnTick)
+        );
+        assert!(executions.is_empty());
+        assert_eq!(book.best_ask(), None);
+    }
+
+    #[test]
+    fn test_expire_orders() {
+        let mut book = OrderBook::default();
+        let now = timestamp();
+        let soon = now + std::time::Duration::from_secs(60);
+        let later = now + std::time::Duration::from_secs(120);
+
+        let (gtd_soon, _) = book
+            .add_order(limit_order(Side::Bid, 100, 10).time_in_force(TimeInForce::GTD(soon)))
+            .unwrap();
+        let (gtd_later, _) = book
+            .add_order(limit_order(Side::Ask, 100, 12).time_in_force(TimeInForce::GTD(later)))
+            .unwrap();
+        let (stop, _) = book
+            .add_order(
+                OrderRequest::new(Side::Bid, 10, OrderType::stop(15))
+                    .time_in_force(TimeInForce::GTD(soon)),
+            )
+            .unwrap();
+        let (gtc, _) = book.add_order(limit_order(Side::Bid, 100, 9)).unwrap();
+
+        assert!(book.expire_orders(now).is_empty());
+
+        let expired = book.expire_orders(soon);
+        assert_eq!(expired.len(), 2);
+        assert!(expired.iter().all(|r| r.status == OrderStatus::Expired));
+        assert!(expired.iter().any(|r| r.get_id() == gtd_soon.get_id()));
+        assert!(expired.iter().any(|r| r.get_id() == stop.get_id()));
+        assert!(book.stop_bids.is_empty());
+        assert_eq!(book.best_bid(), Some(9.into()));
+
+        let expired = book.expire_orders(later);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].get_id(), gtd_later.get_id());
+        assert!(book.get_order(gtc.get_id()).is_some());
+    }
+
+    #[test]
+    fn test_expire_orders_skips_removed_orders() {
+        let mut book = OrderBook::default();
+        let now = timestamp();
+        let (filled, _) = book
+            .add_order(limit_order(Side::Ask, 100, 10).time_in_force(TimeInForce::DAY))
+            .unwrap();
+        let (deleted, _) = book
+            .add_order(limit_order(Side::Ask, 100, 11).time_in_force(TimeInForce::DAY))
+            .unwrap();
+
+        book.add_order(limit_order(Side::Bid, 100, 10)).unwrap();
+        book.delete_order(deleted.get_id()).unwrap();
+        assert!(book.get_order(filled.get_id()).is_none());
+
+        let end_of_day = TimeInForce::DAY.expiry(now).unwrap();
+        assert!(book.expire_orders(end_of_day).is_empty());
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn test_order_book_with_manual_clock() {
+        let clock =
+            ManualClock::new(std::time::UNIX_EPOCH + std::time::Duration::from_secs(34_200));
+        let mut book = OrderBook::with_clock(clock.clone());
+
+        let (ask, _) = book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        clock.advance(std::time::Duration::from_millis(250));
+        let (_, executions) = book.add_order(limit_order(Side::Bid, 40, 10)).unwrap();
+
+        let expected = std::time::UNIX_EPOCH + std::time::Duration::from_millis(34_200_250);
+        assert_eq!(executions[0].timestamp, expected);
+        let resting = book.get_order(ask.get_id()).unwrap();
+        assert_eq!(
+            resting.creation_timestamp(),
+            clock.now() - std::time::Duration::from_millis(250)
+        );
+        assert_eq!(resting.last_modified_timestamp(), expected);
+    }
+
+    fn account_order(
+        account: AccountId,
+        side: Side,
+        qty: impl Into<Quantity>,
+        price: impl Into<Price>,
+        mode: SelfTradePrevention,
+    ) -> OrderRequest {
+        limit_order(side, qty, price)
+            .account(account)
+            .self_trade_prevention(mode)
+    }
+
+    #[test]
+    fn test_self_trade_prevention_cancel_newest() {
+        let mut book = OrderBook::default();
+        let (resting, _) = book
+            .add_order(limit_order(Side::Ask, 50, 10).account(1))
+            .unwrap();
+        let (result, executions) = book
+            .add_order(account_order(
+                1,
+                Side::Bid,
+                80,
+                10,
+                SelfTradePrevention::CancelNewest,
+            ))
+            .unwrap();
+        assert!(executions.is_empty());
+        assert_eq!(result.status, OrderStatus::Cancelled);
+        assert_eq!(result.prevented_qty, 80.into());
+        assert_eq!(book.best_bid(), None);
+        assert_eq!(
+            book.get_order(resting.get_id()).unwrap().remaining_qty,
+            50.into()
+        );
+    }
+
+    #[test]
+    fn test_self_trade_prevention_cancel_oldest() {
+        let mut book = OrderBook::default();
+        let (own, _) = book
+            .add_order(limit_order(Side::Ask, 50, 10).account(1))
+            .unwrap();
+        let (other, _) = book
+            .add_order(limit_order(Side::Ask, 50, 10).account(2))
+            .unwrap();
+        let (result, executions) = book
+            .add_order(account_order(
+                1,
+                Side::Bid,
+                80,
+                10,
+                SelfTradePrevention::CancelOldest,
+            ))
+            .unwrap();
+        assert_eq!(executions.len(), 1);
+        assert_eq!(executions[0].maker_order_id, other.get_id());
+        assert_eq!(result.status, OrderStatus::PartiallyFilled);
+        assert_eq!(result.prevented_qty, 50.into());
+        assert!(book.get_order(own.get_id()).is_none());
+        assert_eq!(book.best_bid(), Some(10.into()));
+        assert_eq!(
+            book.get_volume_at_price(&Side::Bid, &10.into()),
+            Some(30.into())
+        );
+    }
+
+    #[test]
+    fn test_self_trade_prevention_cancel_both() {
+        let mut book = OrderBook::default();
+        let (own, _) = book
+            .add_order(limit_order(Side::Ask, 50, 10).account(1))
+            .unwrap();
+        let (result, executions) = book
+            .add_order(account_order(
+                1,
+                Side::Bid,
+                80,
+                10,
+                SelfTradePrevention::CancelBoth,
+            ))
+            .unwrap();
+        assert!(executions.is_empty());
+        assert_eq!(result.status, OrderStatus::Cancelled);
+        assert_eq!(result.prevented_qty, 130.into());
+        assert!(book.get_order(own.get_id()).is_none());
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn test_self_trade_prevention_decrement_and_cancel() {
+        let mut book = OrderBook::default();
+        let (own, _) = book
+            .add_order(limit_order(Side::Ask, 50, 10).account(1))
+            .unwrap();
+        book.add_order(limit_order(Side::Ask, 50, 10).account(2))
+            .unwrap();
+        let (result, executions) = book
+            .add_order(account_order(
+                1,
+                Side::Bid,
+                80,
+                10,
+                SelfTradePrevention::DecrementAndCancel,
+            ))
+            .unwrap();
+        // Both orders are reduced by 50, the rest of the incoming order trades with the other account
+        assert_eq!(executions.len(), 1);
+        assert_eq!(executions[0].qty, 30.into());
+        assert_eq!(result.status, OrderStatus::PartiallyFilled);
+        assert_eq!(result.remaining_qty, 0.into());
+        assert_eq!(result.prevented_qty, 100.into());
+        assert!(book.get_order(own.get_id()).is_none());
+        assert_eq!(
+            book.get_volume_at_price(&Side::Ask, &10.into()),
+            Some(20.into())
+        );
+    }
+
+    #[test]
+    fn test_different_accounts_trade() {
+        let mut book = OrderBook::default();
+        book.add_order(limit_order(Side::Ask, 50, 10).account(1))
+            .unwrap();
+        let (result, executions) = book
+            .add_order(limit_order(Side::Bid, 50, 10).account(2))
+            .unwrap();
+        assert_eq!(executions.len(), 1);
+        assert_eq!(result.status, OrderStatus::Filled);
+        assert_eq!(result.prevented_qty, 0.into());
+    }
+
+    #[test]
+    fn test_amend_order_decrease_keeps_priority() {
+        let mut book = OrderBook::default();
+        let (first, _) = book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        let (second, _) = book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+
+        let (result, executions) = book.amend_order(first.get_id(), 60, 10).unwrap();
+        assert!(executions.is_empty());
+        assert_eq!(result.status, OrderStatus::Open);
+        assert_eq!(result.remaining_qty, 60.into());
+
+        let orders = book.get_orders_at_price(Side::Ask, 10).unwrap();
+        assert_eq!(orders[0].id, first.get_id());
+        assert_eq!(orders[1].id, second.get_id());
+        assert_eq!(
+            book.get_volume_at_price(&Side::Ask, &10.into()),
+            Some(160.into())
+        );
+    }
+
+    #[test]
+    fn test_amend_order_increase_loses_priority() {
+        let mut book = OrderBook::default();
+        let (first, _) = book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+        let (second, _) = book.add_order(limit_order(Side::Ask, 100, 10)).unwrap();
+
+        book.amend_order(first.get_id(), 150, 10).unwrap();
+        let orders = book.get_orders_at_price(Side::Ask, 10).unwrap();
+        assert_eq!(orders[0].id, second.get_id());
+        assert_eq!(orders[1].id, first.get_id());
+        assert_eq!(orders[1].remaining_qty, 150.into());
+    }
+
+    #[test]
+    fn test_amend_order_price_change() {
+        let mut book = OrderBook::default();
+        let (ask, _) = book.add_order(limit_order(Side::Ask, 100, 11)).unwrap();
+        book.add_order(limit_order(Side::Bid, 40, 10)).unwrap();
+
+        book.amend_order(ask.get_id(), 100, 12).unwrap();
+        assert_eq!(
+            book.order_loc.get(&ask.get_id()),
+            Some(&(Side::Ask, 12.into()))
+        );
+        assert_eq!(book.best_ask(), Some(12.into()));
+
+        // Amending through the best bid matches straight away and rests the rest
+        let (result, executions) = book.amend_order(ask.get_id(), 100, 10).unwrap();
+        assert_eq!(executions.len(), 1);
+        assert_eq!(executions[0].qty, 40.into());
+        assert_eq!(executions[0].taker_order_id, result.get_id());
+        assert_eq!(result.status, OrderStatus::PartiallyFilled);
+        assert_eq!(book.best_bid(), None);
+        assert_eq!(book.best_ask(), Some(10.into()));
+        assert_eq!(
+            book.get_order(ask.get_id()).unwrap().remaining_qty,
+            60.into()
+        );
+    }
+
+    #[test]
+    fn test_amend_order_to_zero_cancels() {
+        let mut book = OrderBook::default();
+        let (ask, _) = book.add_order(limit_order(Side::Ask, 100, 11)).unwrap();
+        let (result, _) = book.amend_order(ask.get_id(), 0, 11).unwrap();
+        assert_eq!(result.status, OrderStatus::Cancelled);
+        assert!(book.is_empty());
+        assert!(book.amend_order(ask.get_id(), 10, 11).is_err());
+    }
+
+    #[test]
+    fn test_amend_iceberg_order_refreshes_peak() {
+        let mut book = OrderBook::default();
+        let (iceberg, _) = book
+            .add_order(limit_order(Side::Ask, 100, 10).iceberg(20))
+            .unwrap();
+        book.add_order(limit_order(Side::Bid, 15, 10)).unwrap();
+        assert_eq!(book.asks.get_total_qty(&10.into()), Some(5.into()));
+
+        // A decrease keeps the leftover of the peak along with the order's priority
+        book.amend_order(iceberg.get_id(), 80, 10).unwrap();
+        assert_eq!(book.asks.get_total_qty(&10.into()), Some(5.into()));
+
+        // A new price sends the order to the back of the queue with a full peak
+        book.amend_order(iceberg.get_id(), 80, 11).unwrap();
+        assert_eq!(book.asks.get_total_qty(&11.into()), Some(20.into()));
+        book.add_order(limit_order(Side::Bid, 5, 11)).unwrap();
+        assert_eq!(book.asks.get_total_qty(&11.into()), Some(15.into()));
+
+        // So does an increase
+        book.amend_order(iceberg.get_id(), 90, 11).unwrap();
+        assert_eq!(book.asks.get_total_qty(&11.into()), Some(20.into()));
+    }
+
+    #[test]
+    fn test_amend_post_only_order() {
+        let mut book = OrderBook::default();
+        book.add_order(limit_order(Side::Bid, 40, 10)).unwrap();
+        let (ask, _) = book
+            .add_order(limit_order(Side::Ask, 100, 12).post_only(PostOnly::Reject))
+            .unwrap();
+
+        // Crossing the best bid would match, the order is left as it was
+        assert_eq!(
+            book.amend_order(ask.get_id(), 100, 10).unwrap_err(),
+            OrderBookError::AmendRejected(RejectReason::PostOnlyWouldMatch)
+        );
+        assert_eq!(book.best_ask(), Some(12.into()));
+        assert_eq!(book.best_bid(), Some(10.into()));
+
+        let tick = Decimal::new(1, 1);
+        let (ask, _) = book
+            .add_order(limit_order(Side::Ask, 50, 13).post_only(PostOnly::Reprice(tick)))
+            .unwrap();
+        let (_, executions) = book.amend_order(ask.get_id(), 50, 9).unwrap();
+        assert!(executions.is_empty());
+        assert_eq!(book.best_ask(), Some(Decimal::new(101, 1)));
+        assert_eq!(book.best_bid(), Some(10.into()));
+    }
+
+    #[test]
+    fn test_amend_stop_order() {
+        let mut book = OrderBook::default();
+        let (stop, _) = book
+            .add_order(OrderRequest::new(
+                Side::Bid,
+                10,
+                OrderType::stop_limit(10, 11),
+            ))
+            .unwrap();
+        assert_eq!(
+            book.amend_order(stop.get_id(), 5, 11).unwrap_err(),
+            OrderBookError::StopOrderNotAmendable(stop.get_id())
+        );
+        assert_eq!(book.stop_bids.get_order_count(), 1);
+    }
+
+    #[test]
+    fn test_add_order_rejects_invalid_requests() {
+        let mut book = OrderBook::default();
+        assert_eq!(
+            book.add_order(limit_order(Side::Bid, 0, 10)).unwrap_err(),
+            OrderBookError::InvalidQuantity(0.into())
+        );
+        assert_eq!(
+            book.add_order(limit_order(Side::Bid, 10, 0)).unwrap_err(),
+            OrderBookError::InvalidPrice(0.into())
+        );
+        assert_eq!(
+            book.add_order(OrderRequest::new(Side::Bid, 10, OrderType::stop(-1)))
+                .unwrap_err(),
+            OrderBookError::InvalidPrice((-1).into())
+        );
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn test_add_order_rejects_duplicate_id() {
+        let mut book = OrderBook::default();
+        let order = OrderRequest::new_with_other_id("1", Side::Ask, 100, OrderType::limit(10));
+        let (result, _) = book.add_order(order).unwrap();
+        assert_eq!(
+            book.add_order(order).unwrap_err(),
+            OrderBookError::DuplicateOrderId(result.get_id())
+        );
+
+        // Once the order has been filled the id is free again
+        book.add_order(OrderRequest::new(Side::Bid, 100, OrderType::Market))
+            .unwrap();
+        assert!(book.order_loc.is_empty());
+        assert!(book.add_order(order).is_ok());
+    }
+
+    #[test]
+    fn test_order_book_with_ticks() {
+        let mut book = OrderBook::<SystemClock, Ticks>::with_scale(SystemClock, 2, 0);
+        assert_eq!(
+            book.add_order(limit_order(Side::Bid, 10, Decimal::new(10005, 3)))
+                .unwrap_err(),
+            OrderBookError::InvalidPrice(Decimal::new(10005, 3))
+        );
+
+        book.add_order(limit_order(Side::Ask, 10, Decimal::new(1001, 2)))
+            .unwrap();
+        book.add_order(limit_order(Side::Ask, 10, Decimal::new(1002, 2)))
+            .unwrap();
+        assert_eq!(book.best_ask(), Some(Decimal::new(1001, 2)));
+
+        let (result, executions) = book
+            .add_order(limit_order(Side::Bid, 15, Decimal::new(1002, 2)))
+            .unwrap();
+        assert_eq!(result.status, OrderStatus::Filled);
+        assert_eq!(executions.len(), 2);
+        assert_eq!(executions[1].price, Decimal::new(1002, 2));
+        assert_eq!(book.best_ask(), Some(Decimal::new(1002, 2)));
+    }
+
+    #[test]
+    fn test_order_book_with_lots() {
+        let mut book = OrderBook::<SystemClock, Ticks>::with_scale(SystemClock, 2, 2);
+        assert_eq!(
+            book.add_order(limit_order(Side::Bid, Decimal::new(1005, 3), 10))
+                .unwrap_err(),
+            OrderBookError::InvalidQuantity(Decimal::new(1005, 3))
+        );
+
+        let (ask, _) = book
+            .add_order(limit_order(Side::Ask, Decimal::new(125, 2), 10))
+            .unwrap();
+        assert_eq!(
+            book.get_order(ask.get_id()).unwrap().remaining_qty,
+            Lots(125)
+        );
+        assert_eq!(
+            book.get_volume_at_price(&Side::Ask, &10.into()),
+            Some(Decimal::new(125, 2))
+        );
+
+        let (result, executions) = book
+            .add_order(limit_order(Side::Bid, Decimal::new(5, 1), 10))
+            .unwrap();
+        assert_eq!(result.status, OrderStatus::Filled);
+        assert_eq!(executions[0].qty, Decimal::new(5, 1));
+        let result = book
+            .cancel_order(ask.get_id(), Decimal::new(25, 2))
+            .unwrap();
+        assert_eq!(result.remaining_qty, Decimal::new(5, 1));
+
+        let restored =
+            OrderBook::<SystemClock, Ticks>::restore(SystemClock, book.snapshot()).unwrap();
+        assert_eq!(restored.order_loc, book.order_loc);
+        assert_eq!(restored.snapshot(), book.snapshot());
+    }
+
+    fn record_events(book: &mut OrderBook) -> crossbeam_channel::Receiver<Notification> {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        book.set_event_sink(sender);
+        receiver
+    }
+
+    #[test]
+    fn test_events_of_matched_orders() {
+        let mut book = OrderBook::default();
+        let events = record_events(&mut book);
+        let (ask, _) = book.add_order(limit_order(Side::Ask, 10, 100)).unwrap();
+        let ask_id = ask.get_id();
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [
+                Notification::OrderAccepted {
+                    order_id: ask_id,
+                    side: Side::Ask,
+                    account: None,
+                    order_type: OrderType::limit(100),
+                    qty: 10.into(),
+                },
+                Notification::OrderAdded {
+                    order_id: ask_id,
+                    side: Side::Ask,
+                    account: None,
+                    price: 100.into(),
+                    qty: 10.into(),
+                    displayed_qty: 10.into(),
+                },
+            ]
+        );
+
+        let (bid, executions) = book.add_order(limit_order(Side::Bid, 4, 100)).unwrap();
+        let bid_id = bid.get_id();
+        let events: Vec<_> = events.try_iter().collect();
+        assert_eq!(events.len(), 4);
+        assert_eq!(
+            events[1],
+            Notification::TradeExecuted(executions[0].clone())
+        );
+        assert_eq!(
+            events[2],
+            Notification::OrderPartiallyFilled {
+                order_id: ask_id,
+                side: Side::Ask,
+                account: None,
+                price: 100.into(),
+                qty: 4.into(),
+                remaining_qty: 6.into(),
+                displayed_qty: 6.into(),
+            }
+        );
+        assert_eq!(
+            events[3],
+            Notification::OrderFilled {
+                order_id: bid_id,
+                side: Side::Bid,
+                account: None,
+                price: 100.into(),
+                qty: 4.into(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_events_of_unrested_orders() {
+        let mut book = OrderBook::default();
+        book.add_order(limit_order(Side::Ask, 5, 100)).unwrap();
+        book.add_order(limit_order(Side::Ask, 5, 101)).unwrap();
+        let events = record_events(&mut book);
+
+        let (ioc, _) = book
+            .add_order(OrderRequest::new(Side::Bid, 8, OrderType::ioc(100)))
+            .unwrap();
+        assert_eq!(
+            events.try_iter().last(),
+            Some(Notification::OrderCancelled {
+                order_id: ioc.get_id(),
+                side: Side::Bid,
+                account: None,
+                price: None,
+                qty: 3.into(),
+                remaining_qty: 0.into(),
+                displayed_qty: 0.into(),
+            })
+        );
+
+        let (rejected, _) = book
+            .add_order(limit_order(Side::Bid, 1, 101).post_only(PostOnly::Reject))
+            .unwrap();
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [Notification::OrderRejected {
+                order_id: rejected.get_id(),
+                side: Side::Bid,
+                account: None,
+                reason: RejectReason::PostOnlyWouldMatch,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_events_of_cancelled_and_amended_orders() {
+        let mut book = OrderBook::default();
+        let (bid, _) = book.add_order(limit_order(Side::Bid, 10, 100)).unwrap();
+        let bid_id = bid.get_id();
+        let events = record_events(&mut book);
+
+        book.cancel_order(bid_id, 3).unwrap();
+        book.amend_order(bid_id, 5, 100).unwrap();
+        book.amend_order(bid_id, 5, 101).unwrap();
+        book.delete_order(bid_id).unwrap();
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [
+                Notification::OrderCancelled {
+                    order_id: bid_id,
+                    side: Side::Bid,
+                    account: None,
+                    price: Some(100.into()),
+                    qty: 3.into(),
+                    remaining_qty: 7.into(),
+                    displayed_qty: 7.into(),
+                },
+                Notification::OrderAmended {
+                    order_id: bid_id,
+                    side: Side::Bid,
+                    account: None,
+                    old_price: 100.into(),
+                    old_qty: 7.into(),
+                    price: 100.into(),
+                    qty: 5.into(),
+                    displayed_qty: 5.into(),
+                    kept_priority: true,
+                },
+                Notification::OrderAmended {
+                    order_id: bid_id,
+                    side: Side::Bid,
+                    account: None,
+                    old_price: 100.into(),
+                    old_qty: 5.into(),
+                    price: 101.into(),
+                    qty: 5.into(),
+                    displayed_qty: 0.into(),
+                    kept_priority: false,
+                },
+                Notification::OrderAdded {
+                    order_id: bid_id,
+                    side: Side::Bid,
+                    account: None,
+                    price: 101.into(),
+                    qty: 5.into(),
+                    displayed_qty: 5.into(),
+                },
+                Notification::OrderCancelled {
+                    order_id: bid_id,
+                    side: Side::Bid,
+                    account: None,
+                    price: Some(101.into()),
+                    qty: 5.into(),
+                    remaining_qty: 0.into(),
+                    displayed_qty: 0.into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_events_of_stop_and_expired_orders() {
+        let mut book = OrderBook::default();
+        let now = timestamp();
+        let (stop, _) = book
+            .add_order(OrderRequest::new(Side::Bid, 2, OrderType::stop(100)))
+            .unwrap();
+        let (bid, _) = book
+            .add_order(limit_order(Side::Bid, 5, 90).time_in_force(TimeInForce::GTD(now)))
+            .unwrap();
+        book.add_order(limit_order(Side::Ask, 10, 100)).unwrap();
+        let events = record_events(&mut book);
+
+        book.add_order(limit_order(Side::Bid, 1, 100)).unwrap();
+        assert!(events
+            .try_iter()
+            .collect::<Vec<_>>()
+            .contains(&Notification::StopTriggered {
+                order_id: stop.get_id(),
+                side: Side::Bid,
+                account: None,
+                trigger: 100.into(),
+            }));
+
+        book.expire_orders(now);
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [Notification::OrderExpired {
+                order_id: bid.get_id(),
+                side: Side::Bid,
+                account: None,
+                price: Some(90.into()),
+                qty: 5.into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_call_auction_accumulates_orders() {
+        let mut book = OrderBook::default();
+        book.start_auction();
+        assert!(book.in_auction());
+        let (bid, executions) = book.add_order(limit_order(Side::Bid, 10, 102)).unwrap();
+        assert!(executions.is_empty());
+        let (_, executions) = book.add_order(limit_order(Side::Ask, 15, 99)).unwrap();
+        assert!(executions.is_empty());
+        assert_eq!(book.best_prices(), (Some(102.into()), Some(99.into())));
+        assert_eq!(
+            book.indicative_uncross(),
+            Some(Equilibrium {
+                price: 99.into(),
+                volume: 10.into(),
+                imbalance: 5.into(),
+                surplus: Some(Side::Ask),
+            })
+        );
+
+        let (market, _) = book
+            .add_order(OrderRequest::new(Side::Bid, 5, OrderType::Market))
+            .unwrap();
+        assert_eq!(
+            market.status,
+            OrderStatus::Rejected(RejectReason::AuctionInProgress)
+        );
+        let (stop, _) = book
+            .add_order(OrderRequest::new(Side::Ask, 5, OrderType::stop(99)))
+            .unwrap();
+        assert!(book.stop_loc.contains_key(&stop.get_id()));
+
+        // Amending through the opposite side rests the order again without matching
+        let (_, executions) = book.amend_order(bid.get_id(), 20, 101).unwrap();
+        assert!(executions.is_empty());
+        assert_eq!(book.best_bid(), Some(101.into()));
+    }
+
+    #[test]
+    fn test_call_auction_uncross() {
+        let mut book = OrderBook::default();
+        book.start_auction();
+        let (bid_1, _) = book.add_order(limit_order(Side::Bid, 10, 102)).unwrap();
+        let (bid_2, _) = book.add_order(limit_order(Side::Bid, 20, 101)).unwrap();
+        book.add_order(limit_order(Side::Bid, 30, 100)).unwrap();
+        let (ask_1, _) = book.add_order(limit_order(Side::Ask, 15, 99)).unwrap();
+        book.add_order(limit_order(Side::Ask, 10, 100).iceberg(4))
+            .unwrap();
+        let (ask_3, _) = book.add_order(limit_order(Side::Ask, 20, 101)).unwrap();
+
+        let executions = book.uncross();
+        assert!(!book.in_auction());
+        assert!(executions.iter().all(|e| e.price == 101.into()));
+        assert_eq!(
+            executions.iter().map(|e| e.qty).sum::<Quantity>(),
+            30.into()
+        );
+        assert_eq!(executions[0].taker_order_id, bid_1.get_id());
+        assert_eq!(executions[0].maker_order_id, ask_1.get_id());
+        assert_eq!(book.last_trade_price(), Some(101.into()));
+
+        // Both crossing bids are filled, the surplus is left on the ask at 101
+        assert!(book.get_order(bid_1.get_id()).is_none());
+        assert!(book.get_order(bid_2.get_id()).is_none());
+        assert_eq!(book.best_prices(), (Some(100.into()), Some(101.into())));
+        assert_eq!(
+            book.get_order(ask_3.get_id()).unwrap().remaining_qty,
+            15.into()
+        );
+        assert_eq!(book.get_order_count(), 2);
+        assert_eq!(book.uncross(), []);
+
+        // Continuous trading resumes
+        let (_, executions) = book.add_order(limit_order(Side::Bid, 5, 101)).unwrap();
+        assert_eq!(executions.len(), 1);
+    }
+
+    #[test]
+    fn test_call_auction_uncross_skips_level_without_displayed_quantity() {
+        let mut book = OrderBook::default();
+        book.start_auction();
+        book.add_order(limit_order(Side::Bid, 20, 101)).unwrap();
+        book.add_order(limit_order(Side::Ask, 5, 100)).unwrap();
+        // Slipped past validation, the order displays nothing
+        let hidden = TradeOrder::from(limit_order(Side::Ask, 10, 99).iceberg(0));
+        book.asks.add_order(99.into(), hidden);
+
+        let executions = book.uncross();
+        assert_eq!(executions.len(), 1);
+        assert_eq!(executions[0].qty, 5.into());
+        assert_eq!(executions[0].price, 100.into());
+        assert!(!book.in_auction());
+    }
+
+    #[test]
+    fn test_call_auction_publishes_indicative_uncross() {
+        let mut book = OrderBook::default();
+        let events = record_events(&mut book);
+        book.start_auction();
+        book.add_order(limit_order(Side::Bid, 10, 100)).unwrap();
+        book.add_order(limit_order(Side::Ask, 10, 101)).unwrap();
+        assert!(!events
+            .try_iter()
+            .any(|event| event.kind() == NotificationKind::IndicativeUncross));
+
+        let (ask, _) = book.add_order(limit_order(Side::Ask, 4, 100)).unwrap();
+        let indicative = Equilibrium {
+            price: 100.into(),
+            volume: 4.into(),
+            imbalance: 6.into(),
+            surplus: Some(Side::Bid),
+        };
+        assert_eq!(
+            events.try_iter().last(),
+            Some(Notification::IndicativeUncross(Some(indicative)))
+        );
+        // Orders that do not change the indicative uncross publish nothing
+        book.add_order(limit_order(Side::Ask, 5, 105)).unwrap();
+        assert!(!events
+            .try_iter()
+            .any(|event| event.kind() == NotificationKind::IndicativeUncross));
+
+        book.delete_order(ask.get_id()).unwrap();
+        assert_eq!(
+            events.try_iter().last(),
+            Some(Notification::IndicativeUncross(None))
+        );
+        assert_eq!(book.uncross(), []);
+    }
+
+    #[test]
+    fn test_static_price_band() {
+        let bands = PriceBands::default().static_band(100, Decimal::new(1, 1));
+        let mut book = OrderBook::default().with_price_bands(bands);
+        let (result, _) = book.add_order(limit_order(Side::Bid, 10, 89)).unwrap();
+        assert_eq!(
+            result.status,
+            OrderStatus::Rejected(RejectReason::PriceOutsideBand)
+        );
+        for price in [100, 105, 110] {
+            book.add_order(limit_order(Side::Ask, 10, price)).unwrap();
+        }
+        // Asks resting from before the band moved are not traded
+        book.set_price_bands(PriceBands::default().static_band(100, Decimal::new(5, 2)));
+
+        let (result, executions) = book
+            .add_order(OrderRequest::new(Side::Bid, 30, OrderType::Market))
+            .unwrap();
+        assert_eq!(executions.len(), 2);
+        assert!(executions.iter().all(|e| e.price <= 105.into()));
+        assert_eq!(result.status, OrderStatus::Cancelled);
+        assert_eq!(result.remaining_qty, 10.into());
+        assert_eq!(book.best_ask(), Some(110.into()));
+        assert!(!book.in_auction());
+    }
+
+    #[test]
+    fn test_circuit_breaker_starts_volatility_auction() {
+        let bands = PriceBands::default()
+            .dynamic_band(Decimal::new(5, 2))
+            .remainder(RemainderPolicy::Rest);
+        let mut book = OrderBook::default().with_price_bands(bands);
+        let events = record_events(&mut book);
+        book.add_order(limit_order(Side::Ask, 10, 100)).unwrap();
+        book.add_order(limit_order(Side::Bid, 5, 100)).unwrap();
+        book.add_order(limit_order(Side::Ask, 10, 110)).unwrap();
+        assert_eq!(book.last_trade_price(), Some(100.into()));
+
+        // A FOK order only counts the liquidity inside the band
+        let (result, executions) = book
+            .add_order(OrderRequest::new(Side::Bid, 10, OrderType::fok(110)))
+            .unwrap();
+        assert_eq!(result.status, OrderStatus::Cancelled);
+        assert!(executions.is_empty());
+        assert!(!book.in_auction());
+
+        let (result, executions) = book.add_order(limit_order(Side::Bid, 20, 110)).unwrap();
+        assert_eq!(executions.len(), 1);
+        assert_eq!(executions[0].price, 100.into());
+        // The rest of the order joins the auction instead of trading at 110
+        assert!(book.in_auction());
+        assert_eq!(result.status, OrderStatus::PartiallyFilled);
+        assert_eq!(book.best_prices(), (Some(110.into()), Some(110.into())));
+        let events: Vec<_> = events.try_iter().collect();
+        assert!(events.contains(&Notification::CircuitBreakerTripped {
+            price: 110.into(),
+            reference: Some(100.into()),
+        }));
+        assert_eq!(
+            events.last(),
+            Some(&Notification::IndicativeUncross(book.indicative_uncross()))
+        );
+
+        let executions = book.uncross();
+        assert_eq!(executions.len(), 1);
+        assert_eq!(executions[0].qty, 10.into());
+        assert_eq!(book.last_trade_price(), Some(110.into()));
+    }
+
+    #[test]
+    fn test_matching_policies() {
+        let fills = |algorithm: MatchingAlgorithm| {
+            let mut book = OrderBook::default().with_matching_policy(algorithm.policy(None));
+            for qty in [10, 30, 60] {
+                book.add_order(limit_order(Side::Ask, qty, 100)).unwrap();
+            }
+            let (result, executions) = book.add_order(limit_order(Side::Bid, 25, 100)).unwrap();
+            assert_eq!(result.status, OrderStatus::Filled);
+            let resting: Vec<Quantity> = book
+                .get_orders_at_price(Side::Ask, 100)
+                .unwrap()
+                .iter()
+                .map(|order| order.remaining_qty)
+                .collect();
+            let fills: Vec<Quantity> = executions.iter().map(|e| e.qty).collect();
+            (fills, resting)
+        };
+        let quantities = |quantities: &[i64]| -> Vec<Quantity> {
+            quantities.iter().map(|q| Quantity::from(*q)).collect()
+        };
+
+        assert_eq!(
+            fills(MatchingAlgorithm::Fifo),
+            (quantities(&[10, 15]), quantities(&[15, 60]))
+        );
+        assert_eq!(
+            fills(MatchingAlgorithm::ProRata),
+            (quantities(&[3, 7, 15]), quantities(&[7, 23, 45]))
+        );
+        assert_eq!(
+            fills(MatchingAlgorithm::TopOrderProRata),
+            (quantities(&[10, 5, 10]), quantities(&[25, 50]))
+        );
+    }
+
+    #[test]
+    fn test_pro_rata_matching_with_iceberg() {
+        let mut book =
+            OrderBook::default().with_matching_policy(MatchingAlgorithm::ProRata.policy(None));
+        let (iceberg, _) = book
+            .add_order(limit_order(Side::Ask, 100, 10).iceberg(10))
+            .unwrap();
+        let (other, _) = book.add_order(limit_order(Side::Ask, 10, 10)).unwrap();
+
+        // Only the peak counts towards the share, the refreshed peak trades in the next pass
+        let (result, executions) = book.add_order(limit_order(Side::Bid, 30, 10)).unwrap();
+        assert_eq!(result.status, OrderStatus::Filled);
+        let fills: Vec<_> = executions
+            .iter()
+            .map(|e| (e.maker_order_id, e.qty))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                (iceberg.get_id(), 10.into()),
+                (other.get_id(), 10.into()),
+                (iceberg.get_id(), 10.into()),
+            ]
+        );
+        assert_eq!(
+            book.get_order(iceberg.get_id()).unwrap().remaining_qty,
+            80.into()
+        );
+        assert!(book.get_order(other.get_id()).is_none());
+    }
 }
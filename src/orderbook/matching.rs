@@ -0,0 +1,206 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use rust_decimal::Decimal;
+
+use super::types::*;
+
+/// MatchingPolicy decides how an incoming order's quantity is shared among the orders resting at one price level.
+pub trait MatchingPolicy: Debug + Send + Sync {
+    /// Allocates `qty` among the resting orders, given their displayed quantities in time priority.
+    /// Returns one allocation per order, none larger than its displayed quantity and together no more than `qty`.
+    fn allocate(&self, resting: &[Quantity], qty: Quantity) -> Vec<Quantity>;
+
+    /// Whether each order is filled in full before the next one in the queue, letting the book match the front of
+    /// the level in place without calling `allocate`.
+    fn fifo(&self) -> bool {
+        false
+    }
+}
+
+/// Price-time priority, each order is filled in full before the next one in the queue.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fifo;
+
+impl MatchingPolicy for Fifo {
+    fn allocate(&self, resting: &[Quantity], qty: Quantity) -> Vec<Quantity> {
+        let mut allocation = vec![Decimal::ZERO; resting.len()];
+        allocate_fifo(resting, &mut allocation, qty);
+        allocation
+    }
+
+    fn fifo(&self) -> bool {
+        true
+    }
+}
+
+/// Shares the quantity in proportion to the size of each order.
+///
+/// Shares are rounded down to a multiple of `lot`, or of the smallest decimal place of the quantities without one, and
+/// whatever is left after rounding is allocated in time priority. Orders whose share is below `min_allocation` get
+/// nothing from the pro-rata pass. With `top_order` the order at the front of the queue is filled first and the rest
+/// shared among the others.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProRata {
+    pub lot: Option<Quantity>,
+    pub min_allocation: Option<Quantity>,
+    pub top_order: bool,
+}
+
+impl MatchingPolicy for ProRata {
+    fn allocate(&self, resting: &[Quantity], qty: Quantity) -> Vec<Quantity> {
+        let Some((top, others)) = resting.split_first() else {
+            return Vec::new();
+        };
+        if !self.top_order {
+            return self.pro_rata(resting, qty);
+        }
+        let top_qty = qty.min(*top);
+        let mut allocation = vec![top_qty];
+        allocation.extend(self.pro_rata(others, qty - top_qty));
+        allocation
+    }
+}
+
+impl ProRata {
+    fn pro_rata(&self, resting: &[Quantity], qty: Quantity) -> Vec<Quantity> {
+        let total: Quantity = resting.iter().sum();
+        if total <= qty {
+            return resting.to_vec();
+        }
+        let unit = self
+            .lot
+            .filter(|lot| *lot > Decimal::ZERO)
+            .unwrap_or_else(|| {
+                let scale = resting.iter().chain([&qty]).map(|q| q.scale()).max();
+                Decimal::new(1, scale.unwrap_or(0))
+            });
+        let min_allocation = self.min_allocation.unwrap_or(Decimal::ZERO);
+        let mut allocation: Vec<_> = resting
+            .iter()
+            .map(|resting_qty| {
+                let share = (qty * resting_qty / total / unit).floor() * unit;
+                if share < min_allocation {
+                    Decimal::ZERO
+                } else {
+                    share.min(*resting_qty)
+                }
+            })
+            .collect();
+        let left = qty - allocation.iter().sum::<Quantity>();
+        allocate_fifo(resting, &mut allocation, left);
+        allocation
+    }
+}
+
+// Tops up the allocations in time priority until `qty` is used up
+fn allocate_fifo(resting: &[Quantity], allocation: &mut [Quantity], mut qty: Quantity) {
+    for (resting_qty, allocated) in resting.iter().zip(allocation) {
+        let extra = (resting_qty - *allocated).min(qty);
+        *allocated += extra;
+        qty -= extra;
+    }
+}
+
+/// The matching algorithm of a market, selected through its `InstrumentSpec`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MatchingAlgorithm {
+    // Price-time priority
+    #[default]
+    Fifo,
+    // Pro-rata allocation by order size
+    ProRata,
+    // The front of the queue is filled first, the rest is shared pro-rata
+    TopOrderProRata,
+    // Pro-rata allocation, shares below the minimum are given out in time priority instead
+    ProRataMinimum(Quantity),
+}
+
+impl MatchingAlgorithm {
+    /// Creates the policy of the algorithm, pro-rata shares are rounded to `lot`.
+    pub fn policy(&self, lot: Option<Quantity>) -> Arc<dyn MatchingPolicy> {
+        let pro_rata = ProRata {
+            lot,
+            ..ProRata::default()
+        };
+        match *self {
+            MatchingAlgorithm::Fifo => Arc::new(Fifo),
+            MatchingAlgorithm::ProRata => Arc::new(pro_rata),
+            MatchingAlgorithm::TopOrderProRata => Arc::new(ProRata {
+                top_order: true,
+                ..pro_rata
+            }),
+            MatchingAlgorithm::ProRataMinimum(min_allocation) => Arc::new(ProRata {
+                min_allocation: Some(min_allocation),
+                ..pro_rata
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quantities(quantities: &[i64]) -> Vec<Quantity> {
+        quantities.iter().map(|q| Quantity::from(*q)).collect()
+    }
+
+    #[test]
+    fn test_allocations_across_policies() {
+        let resting = quantities(&[10, 30, 60]);
+        let qty = Quantity::from(25);
+        let allocate =
+            |algorithm: MatchingAlgorithm| algorithm.policy(None).allocate(&resting, qty);
+
+        assert_eq!(allocate(MatchingAlgorithm::Fifo), quantities(&[10, 15, 0]));
+        // 2.5, 7.5 and 15 round down to 2, 7 and 15, the lot left over goes to the oldest order
+        assert_eq!(
+            allocate(MatchingAlgorithm::ProRata),
+            quantities(&[3, 7, 15])
+        );
+        // The top order takes 10, the other 15 is shared 1:2
+        assert_eq!(
+            allocate(MatchingAlgorithm::TopOrderProRata),
+            quantities(&[10, 5, 10])
+        );
+
+        // The share of 2 of the second order is below the minimum and goes to the oldest order instead
+        let resting = quantities(&[40, 20, 40]);
+        let qty = Quantity::from(10);
+        assert_eq!(
+            MatchingAlgorithm::ProRata
+                .policy(None)
+                .allocate(&resting, qty),
+            quantities(&[4, 2, 4])
+        );
+        assert_eq!(
+            MatchingAlgorithm::ProRataMinimum(3.into())
+                .policy(None)
+                .allocate(&resting, qty),
+            quantities(&[6, 0, 4])
+        );
+    }
+
+    #[test]
+    fn test_pro_rata_rounding() {
+        let resting = quantities(&[1, 1, 1]);
+        let policy = MatchingAlgorithm::ProRata.policy(None);
+        // Thirds of a lot round down to nothing, the whole quantity goes out in time priority
+        assert_eq!(policy.allocate(&resting, 2.into()), quantities(&[1, 1, 0]));
+        // Without a lot size shares are rounded to the decimal places of the quantities
+        let resting = vec![Decimal::new(15, 1), Decimal::new(15, 1)];
+        assert_eq!(
+            policy.allocate(&resting, 1.into()),
+            vec![Decimal::new(5, 1), Decimal::new(5, 1)]
+        );
+        // With a lot size of 0.5 the shares of 0.67 and 1.33 round down to 0.5 and 1
+        let policy = MatchingAlgorithm::ProRata.policy(Some(Decimal::new(5, 1)));
+        let resting = quantities(&[1, 2]);
+        assert_eq!(policy.allocate(&resting, 2.into()), quantities(&[1, 1]));
+        // Everything is filled when the level is smaller than the quantity
+        assert_eq!(policy.allocate(&resting, 5.into()), quantities(&[1, 2]));
+        assert!(policy.allocate(&[], 5.into()).is_empty());
+    }
+}
@@ -1,8 +1,20 @@
+mod arena;
+mod auction;
+mod bands;
 mod book;
+mod matching;
 mod orders;
 mod price_levels;
+mod snapshot;
+mod triggers;
 mod types;
 
+pub use arena::PriceLevel;
+pub use auction::*;
+pub use bands::*;
 pub use book::*;
+pub use matching::*;
 pub use orders::*;
+pub use snapshot::*;
+pub use triggers::*;
 pub use types::*;
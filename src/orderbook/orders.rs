@@ -1,12 +1,15 @@
+use std::convert::Infallible;
 use std::fmt::Display;
 
 use log::warn;
 use rust_decimal::Decimal;
 
+use super::snapshot::OrderSnapshot;
 use super::types::*;
 
 /// Type of an order that can be placed.
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OrderType {
     Market,
     Limit(Price),
@@ -15,6 +18,10 @@ pub enum OrderType {
     // Fill or Kill
     FOK(Price),
     SystemLevel(Price), // New variant for price level tracking
+    // Market order released once a trade prints through the trigger price
+    Stop(Price),
+    // Limit order released once a trade prints through the trigger price (trigger, limit)
+    StopLimit(Price, Price),
 }
 impl OrderType {
     pub fn limit(price: impl Into<Price>) -> Self {
@@ -32,6 +39,14 @@ impl OrderType {
     pub fn system_level(price: impl Into<Price>) -> Self {
         OrderType::SystemLevel(price.into())
     }
+    /// Helper function to create a stop order type.
+    pub fn stop(trigger: impl Into<Price>) -> Self {
+        OrderType::Stop(trigger.into())
+    }
+    /// Helper function to create a stop limit order type.
+    pub fn stop_limit(trigger: impl Into<Price>, limit: impl Into<Price>) -> Self {
+        OrderType::StopLimit(trigger.into(), limit.into())
+    }
     /// Generates a unique order id for the order type.
     pub fn generate_id(&self) -> OrderId {
         match self {
@@ -39,6 +54,8 @@ impl OrderType {
             OrderType::Limit(_) => create_order_id(),
             OrderType::IOC(_) => create_order_id(),
             OrderType::FOK(_) => create_order_id(),
+            OrderType::Stop(_) => create_order_id(),
+            OrderType::StopLimit(_, _) => create_order_id(),
             // When using the system level order type, we want to use the price as the id. This is so we can create a system level order for a specific price level.
             OrderType::SystemLevel(p) => create_id_from_bytes(p.to_string().as_bytes()),
         }
@@ -50,7 +67,35 @@ impl OrderType {
             OrderType::IOC(price) => Some(*price),
             OrderType::FOK(price) => Some(*price),
             OrderType::SystemLevel(price) => Some(*price),
-            OrderType::Market => None,
+            OrderType::StopLimit(_, price) => Some(*price),
+            OrderType::Market | OrderType::Stop(_) => None,
+        }
+    }
+    /// Returns the trigger price of the order if it is a stop order.
+    pub fn trigger_price(&self) -> Option<Price> {
+        match self {
+            OrderType::Stop(trigger) | OrderType::StopLimit(trigger, _) => Some(*trigger),
+            _ => None,
+        }
+    }
+    /// Returns the order type a stop order becomes once it has been triggered.
+    pub fn triggered(&self) -> OrderType {
+        match self {
+            OrderType::Stop(_) => OrderType::Market,
+            OrderType::StopLimit(_, limit) => OrderType::Limit(*limit),
+            order_type => *order_type,
+        }
+    }
+    /// Returns the same order type with its limit price replaced, order types without a price are returned unchanged.
+    pub fn with_price(&self, price: impl Into<Price>) -> OrderType {
+        let price = price.into();
+        match self {
+            OrderType::Limit(_) => OrderType::Limit(price),
+            OrderType::IOC(_) => OrderType::IOC(price),
+            OrderType::FOK(_) => OrderType::FOK(price),
+            OrderType::SystemLevel(_) => OrderType::SystemLevel(price),
+            OrderType::StopLimit(trigger, _) => OrderType::StopLimit(*trigger, price),
+            OrderType::Market | OrderType::Stop(_) => *self,
         }
     }
 }
@@ -63,33 +108,130 @@ impl Display for OrderType {
             OrderType::IOC(_) => write!(f, "IOC"),
             OrderType::FOK(_) => write!(f, "FOK"),
             OrderType::SystemLevel(_) => write!(f, "SystemLevel"),
+            OrderType::Stop(_) => write!(f, "Stop"),
+            OrderType::StopLimit(_, _) => write!(f, "StopLimit"),
+        }
+    }
+}
+
+/// What to do with a post only order that would match on entry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PostOnly {
+    // Reject the order
+    Reject,
+    // Reprice the order one tick (the given tick size) away from the opposite best price
+    Reprice(Price),
+}
+
+/// What happens when an incoming order would trade against a resting order of the same account.
+/// The mode of the incoming order is applied.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SelfTradePrevention {
+    // Cancel the remaining quantity of the incoming order
+    #[default]
+    CancelNewest,
+    // Cancel the resting order and keep matching
+    CancelOldest,
+    // Cancel both orders
+    CancelBoth,
+    // Reduce both orders by the smaller quantity, cancelling the smaller order
+    DecrementAndCancel,
+}
+
+/// Reason an order was rejected without reaching the book.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RejectReason {
+    // A post only order would have taken liquidity
+    PostOnlyWouldMatch,
+    // A price is not a multiple of the market's tick size
+    PriceNotOnTick,
+    // A quantity is not a multiple of the market's lot size
+    QuantityNotOnLot,
+    QuantityBelowMinimum,
+    QuantityAboveMaximum,
+    // The peak of an iceberg order is not positive or below the market's minimum quantity
+    PeakBelowMinimum,
+    NotionalBelowMinimum,
+    // The price is too far from the market's reference price
+    PriceOutsideBand,
+    // Orders that must execute on arrival can not be placed while a call auction is running
+    AuctionInProgress,
+    // Trading in the market is halted
+    MarketHalted,
+    // The market is not open yet or has closed
+    MarketNotOpen,
+    // The market only accepts orders from accounts
+    AccountRequired,
+    // The account can not fund the order
+    InsufficientFunds,
+    // An order with the same id already holds a reservation
+    DuplicateOrderId,
+    // The funds of a stop order buying at market can not be known in advance
+    LimitPriceRequired,
+}
+
+/// How long an order stays on the book before it expires.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimeInForce {
+    // Good till cancelled
+    #[default]
+    GTC,
+    // Good till date, expires once the given time is reached
+    GTD(#[cfg_attr(feature = "serde", serde(with = "crate::codec::nanos"))] Timestamp),
+    // Good for the (UTC) day the order was placed on
+    DAY,
+}
+
+impl TimeInForce {
+    /// Returns when an order created at `created` expires, GTC orders never expire.
+    pub fn expiry(&self, created: Timestamp) -> Option<Timestamp> {
+        match self {
+            TimeInForce::GTC => None,
+            TimeInForce::GTD(expiry) => Some(*expiry),
+            TimeInForce::DAY => {
+                const SECS_PER_DAY: u64 = 24 * 60 * 60;
+                let secs = created
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs());
+                let end_of_day = (secs / SECS_PER_DAY + 1) * SECS_PER_DAY;
+                Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(end_of_day))
+            }
         }
     }
 }
 
 /// Status of an order.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OrderStatus {
     Open,
     Filled,
     PartiallyFilled,
     Cancelled,
+    Rejected(RejectReason),
+    Expired,
 }
 /// Fill is a record of a trade that has been executed.
 #[derive(Debug, PartialEq, Clone, Copy)]
-pub struct Fill {
-    pub qty: Quantity,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fill<Q = Quantity> {
+    pub qty: Q,
     pub price: Price,
+    #[cfg_attr(feature = "serde", serde(with = "crate::codec::nanos"))]
     pub timestamp: Timestamp,
     pub order_id: OrderId,
 }
 
-impl Fill {
-    pub fn new(qty: Quantity, price: Price, order_id: OrderId) -> Self {
+impl<Q> Fill<Q> {
+    pub fn new(qty: Q, price: Price, order_id: OrderId, timestamp: Timestamp) -> Self {
         Self {
             qty,
             price,
-            timestamp: timestamp(),
+            timestamp,
             order_id,
         }
     }
@@ -97,11 +239,19 @@ impl Fill {
 
 /// OrderRequest is a request to place an order.
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderRequest {
     id: OrderId,
     pub side: Side,
     pub qty: Quantity,
     pub order_type: OrderType,
+    // Displayed quantity of an iceberg order, the rest of qty is held in reserve
+    pub peak_qty: Option<Quantity>,
+    // Post only orders must never take liquidity on entry
+    pub post_only: Option<PostOnly>,
+    pub time_in_force: TimeInForce,
+    pub account: Option<AccountId>,
+    pub self_trade_prevention: SelfTradePrevention,
 }
 
 impl OrderRequest {
@@ -112,6 +262,11 @@ impl OrderRequest {
             side,
             qty: qty.into(),
             order_type,
+            peak_qty: None,
+            post_only: None,
+            time_in_force: TimeInForce::GTC,
+            account: None,
+            self_trade_prevention: SelfTradePrevention::CancelNewest,
         }
     }
 
@@ -126,6 +281,11 @@ impl OrderRequest {
             side,
             qty: qty.into(),
             order_type,
+            peak_qty: None,
+            post_only: None,
+            time_in_force: TimeInForce::GTC,
+            account: None,
+            self_trade_prevention: SelfTradePrevention::CancelNewest,
         }
     }
 
@@ -140,9 +300,43 @@ impl OrderRequest {
             side,
             qty: qty.into(),
             order_type,
+            peak_qty: None,
+            post_only: None,
+            time_in_force: TimeInForce::GTC,
+            account: None,
+            self_trade_prevention: SelfTradePrevention::CancelNewest,
         }
     }
 
+    /// Turns the request into an iceberg order that only displays `peak_qty` at a time.
+    /// The book rejects a peak that is not positive and displays the whole order if the peak is larger than it.
+    pub fn iceberg(mut self, peak_qty: impl Into<Quantity>) -> Self {
+        self.peak_qty = Some(peak_qty.into());
+        self
+    }
+
+    /// Marks the request as post only, `mode` decides what happens if it would match on entry.
+    pub fn post_only(mut self, mode: PostOnly) -> Self {
+        self.post_only = Some(mode);
+        self
+    }
+
+    pub fn time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.time_in_force = time_in_force;
+        self
+    }
+
+    /// Ties the request to an account, orders of the same account never trade with each other.
+    pub fn account(mut self, account: AccountId) -> Self {
+        self.account = Some(account);
+        self
+    }
+
+    pub fn self_trade_prevention(mut self, mode: SelfTradePrevention) -> Self {
+        self.self_trade_prevention = mode;
+        self
+    }
+
     pub fn price(&self) -> Option<Price> {
         self.order_type.price()
     }
@@ -153,35 +347,91 @@ impl OrderRequest {
 }
 
 /// TradeOrder is an order that has been placed and is being tracked by the order book.
+///
+/// Its quantities are held as `Q`, the quantity type of the book it rests on (see `PriceType`), which converts them
+/// when the order enters and leaves the book.
 #[derive(Debug, Clone, PartialEq)]
-pub struct TradeOrder {
+pub struct TradeOrder<Q = Quantity> {
     pub id: OrderId,
     pub side: Side,
-    pub remaining_qty: Quantity,
-    initial_qty: Quantity,
-    fills: Vec<Fill>,
+    pub remaining_qty: Q,
+    initial_qty: Q,
+    // Iceberg peak size and the part of the current peak still displayed
+    peak_qty: Option<Q>,
+    displayed_qty: Q,
+    fills: Vec<Fill<Q>>,
     pub order_type: OrderType,
+    pub account: Option<AccountId>,
+    // Kept so that an amended price is checked again
+    post_only: Option<PostOnly>,
+    self_trade_prevention: SelfTradePrevention,
+    // Quantity cancelled by self-trade prevention while this was the incoming order, on either side
+    prevented_qty: Q,
+    // Whether any of this order's own quantity was cancelled by self-trade prevention
+    self_trade_cancelled: bool,
+    expires_at: Option<Timestamp>,
     creation_timestamp: Timestamp,
     last_modified_timestamp: Timestamp,
 }
 
 impl From<OrderRequest> for TradeOrder {
     fn from(order_request: OrderRequest) -> Self {
-        let ts = timestamp();
+        Self::from_request(order_request, timestamp())
+    }
+}
+
+impl From<&TradeOrder> for OrderSnapshot {
+    fn from(order: &TradeOrder) -> Self {
         Self {
-            id: order_request.id,
-            side: order_request.side,
-            remaining_qty: order_request.qty,
-            initial_qty: order_request.qty,
-            fills: Vec::new(),
-            order_type: order_request.order_type,
-            creation_timestamp: ts,
-            last_modified_timestamp: ts,
+            id: order.id,
+            side: order.side,
+            order_type: order.order_type,
+            account: order.account,
+            post_only: order.post_only,
+            initial_qty: order.initial_qty,
+            remaining_qty: order.remaining_qty,
+            peak_qty: order.peak_qty,
+            displayed_qty: order.displayed_qty,
+            fills: order.fills.clone(),
+            self_trade_prevention: order.self_trade_prevention,
+            prevented_qty: order.prevented_qty,
+            self_trade_cancelled: order.self_trade_cancelled,
+            expires_at: order.expires_at,
+            creation_timestamp: order.creation_timestamp,
+            last_modified_timestamp: order.last_modified_timestamp,
+        }
+    }
+}
+
+impl From<OrderSnapshot> for TradeOrder {
+    fn from(order: OrderSnapshot) -> Self {
+        Self {
+            id: order.id,
+            side: order.side,
+            remaining_qty: order.remaining_qty,
+            initial_qty: order.initial_qty,
+            peak_qty: order.peak_qty,
+            displayed_qty: order.displayed_qty,
+            fills: order.fills,
+            order_type: order.order_type,
+            account: order.account,
+            post_only: order.post_only,
+            self_trade_prevention: order.self_trade_prevention,
+            prevented_qty: order.prevented_qty,
+            self_trade_cancelled: order.self_trade_cancelled,
+            expires_at: order.expires_at,
+            creation_timestamp: order.creation_timestamp,
+            last_modified_timestamp: order.last_modified_timestamp,
         }
     }
 }
 
 impl TradeOrder {
+    /// Creates the order tracked by the book for a request accepted at `ts`.
+    pub fn from_request(order_request: OrderRequest, ts: Timestamp) -> Self {
+        Self::with_qty(order_request, order_request.qty, order_request.peak_qty, ts)
+    }
+
     pub fn new(qty: impl Into<Quantity>) -> Self {
         let qty = qty.into();
         let ts = timestamp();
@@ -190,48 +440,244 @@ impl TradeOrder {
             side: Side::Ask,
             remaining_qty: qty,
             initial_qty: qty,
+            peak_qty: None,
+            displayed_qty: qty,
             fills: Vec::new(),
             order_type: OrderType::Market,
+            account: None,
+            post_only: None,
+            self_trade_prevention: SelfTradePrevention::CancelNewest,
+            prevented_qty: Decimal::ZERO,
+            self_trade_cancelled: false,
+            expires_at: None,
+            creation_timestamp: ts,
+            last_modified_timestamp: ts,
+        }
+    }
+
+    /// Converts the quantities to `Q` at `scale`, returning the first quantity that is not representable as the error.
+    pub fn into_qty_type<Q: QuantityType>(self, scale: u32) -> Result<TradeOrder<Q>, Quantity> {
+        self.try_map_qty(|qty| Q::from_qty(qty, scale).ok_or(qty))
+    }
+}
+
+impl<Q: QuantityType> TradeOrder<Q> {
+    /// Creates the order for a request accepted at `ts`, with its quantity and peak already converted to `Q`.
+    pub fn with_qty(
+        order_request: OrderRequest,
+        qty: Q,
+        peak_qty: Option<Q>,
+        ts: Timestamp,
+    ) -> Self {
+        Self {
+            id: order_request.id,
+            side: order_request.side,
+            remaining_qty: qty,
+            initial_qty: qty,
+            peak_qty,
+            displayed_qty: peak_qty.map_or(qty, |peak| peak.min(qty)),
+            fills: Vec::new(),
+            order_type: order_request.order_type,
+            account: order_request.account,
+            post_only: order_request.post_only,
+            self_trade_prevention: order_request.self_trade_prevention,
+            prevented_qty: Q::ZERO,
+            self_trade_cancelled: false,
+            expires_at: order_request.time_in_force.expiry(ts),
             creation_timestamp: ts,
             last_modified_timestamp: ts,
         }
     }
+
+    /// Converts every quantity of the order with `f`, failing with the first error it returns.
+    pub fn try_map_qty<R, E>(self, f: impl Fn(Q) -> Result<R, E>) -> Result<TradeOrder<R>, E> {
+        Ok(TradeOrder {
+            id: self.id,
+            side: self.side,
+            remaining_qty: f(self.remaining_qty)?,
+            initial_qty: f(self.initial_qty)?,
+            peak_qty: self.peak_qty.map(&f).transpose()?,
+            displayed_qty: f(self.displayed_qty)?,
+            fills: self
+                .fills
+                .into_iter()
+                .map(|fill| {
+                    Ok(Fill::new(
+                        f(fill.qty)?,
+                        fill.price,
+                        fill.order_id,
+                        fill.timestamp,
+                    ))
+                })
+                .collect::<Result<_, E>>()?,
+            order_type: self.order_type,
+            account: self.account,
+            post_only: self.post_only,
+            self_trade_prevention: self.self_trade_prevention,
+            prevented_qty: f(self.prevented_qty)?,
+            self_trade_cancelled: self.self_trade_cancelled,
+            expires_at: self.expires_at,
+            creation_timestamp: self.creation_timestamp,
+            last_modified_timestamp: self.last_modified_timestamp,
+        })
+    }
+
+    /// Converts the quantities, held at `scale`, back to decimals.
+    pub fn into_decimal(self, scale: u32) -> TradeOrder {
+        let Ok(order) = self.try_map_qty(|qty| Ok::<_, Infallible>(qty.to_qty(scale)));
+        order
+    }
+
     /// Fills the order with the given quantity and price.
-    pub fn fill(&mut self, qty: &mut Quantity, price: impl Into<Price>, order_id: OrderId) {
+    pub fn fill(&mut self, qty: &mut Q, price: impl Into<Price>, order_id: OrderId, ts: Timestamp) {
         let price = price.into();
         let fill_qty = (*qty).min(self.remaining_qty);
         self.remaining_qty -= fill_qty;
-        self.fills.push(Fill::new(fill_qty, price, order_id));
+        self.consume_displayed(fill_qty);
+        self.fills.push(Fill::new(fill_qty, price, order_id, ts));
         *qty -= fill_qty;
-        self.last_modified_timestamp = timestamp();
+        self.last_modified_timestamp = ts;
     }
     /// Fills the order with the given quantity and price and returns the remaining quantity if the order was fully filled.
-    pub fn filled_by(&mut self, other: &mut TradeOrder, price: impl Into<Price>) -> Quantity {
+    /// Iceberg orders can only be filled up to their displayed quantity.
+    pub fn filled_by(
+        &mut self,
+        other: &mut TradeOrder<Q>,
+        price: impl Into<Price>,
+        ts: Timestamp,
+    ) -> Q {
+        let qty = other.remaining_qty;
+        self.filled_by_at_most(other, qty, price, ts)
+    }
+
+    /// Fills the order like `filled_by`, trading no more than `max_qty`.
+    pub fn filled_by_at_most(
+        &mut self,
+        other: &mut TradeOrder<Q>,
+        max_qty: Q,
+        price: impl Into<Price>,
+        ts: Timestamp,
+    ) -> Q {
         let price = price.into();
-        let fill_qty = other.remaining_qty.min(self.remaining_qty);
+        let fill_qty = other.remaining_qty.min(self.visible_qty()).min(max_qty);
         self.remaining_qty -= fill_qty;
+        self.consume_displayed(fill_qty);
         other.remaining_qty -= fill_qty;
-        self.fills.push(Fill::new(fill_qty, price, other.id));
-        other.fills.push(Fill::new(fill_qty, price, self.id));
-        self.last_modified_timestamp = timestamp();
+        self.fills.push(Fill::new(fill_qty, price, other.id, ts));
+        other.fills.push(Fill::new(fill_qty, price, self.id, ts));
+        self.last_modified_timestamp = ts;
         fill_qty
     }
+    /// Returns the quantity shown on the book, for iceberg orders this is what is left of the current peak.
+    pub fn visible_qty(&self) -> Q {
+        match self.peak_qty {
+            Some(_) => self.displayed_qty.min(self.remaining_qty),
+            None => self.remaining_qty,
+        }
+    }
+    /// Returns the quantity held in reserve behind the displayed peak.
+    pub fn reserve_qty(&self) -> Q {
+        self.remaining_qty - self.visible_qty()
+    }
+
+    pub fn post_only(&self) -> Option<PostOnly> {
+        self.post_only
+    }
+
+    pub fn expires_at(&self) -> Option<Timestamp> {
+        self.expires_at
+    }
+
+    pub fn creation_timestamp(&self) -> Timestamp {
+        self.creation_timestamp
+    }
+
+    pub fn last_modified_timestamp(&self) -> Timestamp {
+        self.last_modified_timestamp
+    }
+
+    pub fn is_iceberg(&self) -> bool {
+        self.peak_qty.is_some()
+    }
+    /// Refreshes the displayed peak of an iceberg order once it has been consumed.
+    /// Returns true if the order was replenished, in which case it should lose its time priority.
+    pub fn replenish(&mut self, ts: Timestamp) -> bool {
+        match self.peak_qty {
+            Some(_) if self.visible_qty() == Q::ZERO && self.remaining_qty > Q::ZERO => {
+                self.refresh_peak();
+                self.last_modified_timestamp = ts;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Displays a full peak of an iceberg order, or what is left of it, as when it joins the back of a queue.
+    pub fn refresh_peak(&mut self) {
+        if let Some(peak) = self.peak_qty {
+            self.displayed_qty = peak.min(self.remaining_qty);
+        }
+    }
+
+    fn consume_displayed(&mut self, qty: Q) {
+        if self.peak_qty.is_some() {
+            self.displayed_qty = (self.displayed_qty - qty).max(Q::ZERO);
+        }
+    }
     /// Returns the quantity that has been filled.
-    pub fn filled_quantity(&self) -> Quantity {
+    pub fn filled_quantity(&self) -> Q {
         self.initial_qty - self.remaining_qty
     }
     /// Cancels the order with the given quantity and returns the remaining quantity if the order was fully cancelled.
-    pub fn cancel(&mut self, qty: impl Into<Quantity>) {
+    pub fn cancel(&mut self, qty: impl Into<Q>) {
         let qty = qty.into();
         let qty = qty.min(self.remaining_qty);
         self.remaining_qty -= qty;
     }
 
-    pub fn mergable(&self, other: &TradeOrder) -> bool {
+    /// Changes the open quantity and limit price of the order, the filled quantity is kept.
+    pub fn amend(&mut self, qty: impl Into<Q>, price: impl Into<Price>, ts: Timestamp) {
+        let qty = qty.into();
+        self.initial_qty += qty - self.remaining_qty;
+        self.remaining_qty = qty;
+        self.order_type = self.order_type.with_price(price);
+        self.last_modified_timestamp = ts;
+    }
+
+    /// Returns true if both orders belong to the same account.
+    pub fn is_self_trade(&self, other: &TradeOrder<Q>) -> bool {
+        matches!((self.account, other.account), (Some(a), Some(b)) if a == b)
+    }
+
+    /// Applies this (incoming) order's self-trade prevention mode against a resting order of the same account.
+    /// Returns the quantity cancelled across both orders.
+    pub fn prevent_self_trade(&mut self, resting: &mut TradeOrder<Q>) -> Q {
+        let (incoming_qty, resting_qty) = match self.self_trade_prevention {
+            SelfTradePrevention::CancelNewest => (self.remaining_qty, Q::ZERO),
+            SelfTradePrevention::CancelOldest => (Q::ZERO, resting.remaining_qty),
+            SelfTradePrevention::CancelBoth => (self.remaining_qty, resting.remaining_qty),
+            SelfTradePrevention::DecrementAndCancel => {
+                let qty = self.remaining_qty.min(resting.remaining_qty);
+                (qty, qty)
+            }
+        };
+        if incoming_qty > Q::ZERO {
+            self.cancel(incoming_qty);
+            self.self_trade_cancelled = true;
+        }
+        if resting_qty > Q::ZERO {
+            resting.cancel(resting_qty);
+            resting.self_trade_cancelled = true;
+        }
+        self.prevented_qty += incoming_qty + resting_qty;
+        incoming_qty + resting_qty
+    }
+
+    pub fn mergable(&self, other: &TradeOrder<Q>) -> bool {
         self.side == other.side && self.order_type == other.order_type
     }
 
-    pub fn merge(&mut self, mut other: TradeOrder) -> Option<Self> {
+    pub fn merge(&mut self, mut other: TradeOrder<Q>) -> Option<Self> {
         if !self.mergable(&other) {
             warn!("Cannot merge orders with different side or order type");
             return Some(other);
@@ -239,13 +685,14 @@ impl TradeOrder {
         self.remaining_qty += other.remaining_qty;
         self.initial_qty += other.initial_qty;
         self.fills.append(&mut other.fills);
-        self.last_modified_timestamp = timestamp();
+        self.last_modified_timestamp = other.last_modified_timestamp;
         None
     }
 }
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderResult {
     trade_id: OrderId,
     side: Side,
@@ -254,12 +701,21 @@ pub struct OrderResult {
     pub remaining_qty: Quantity,
     fills: Vec<Fill>,
     pub status: OrderStatus,
+    // Quantity cancelled by self-trade prevention, on this order and on the resting orders it met
+    pub prevented_qty: Quantity,
 }
 
 impl From<TradeOrder> for OrderResult {
     fn from(trade_order: TradeOrder) -> Self {
         let status = if trade_order.remaining_qty == Decimal::ZERO {
-            OrderStatus::Filled
+            match (
+                trade_order.self_trade_cancelled,
+                trade_order.fills.is_empty(),
+            ) {
+                (false, _) => OrderStatus::Filled,
+                (true, true) => OrderStatus::Cancelled,
+                (true, false) => OrderStatus::PartiallyFilled,
+            }
         } else if trade_order.fills.is_empty() {
             match trade_order.order_type {
                 OrderType::Market => OrderStatus::Cancelled,
@@ -267,6 +723,8 @@ impl From<TradeOrder> for OrderResult {
                 OrderType::IOC(_) => OrderStatus::Cancelled,
                 OrderType::FOK(_) => OrderStatus::Cancelled,
                 OrderType::SystemLevel(_) => OrderStatus::Open,
+                OrderType::Stop(_) => OrderStatus::Open,
+                OrderType::StopLimit(_, _) => OrderStatus::Open,
             }
         } else {
             match trade_order.order_type {
@@ -275,6 +733,8 @@ impl From<TradeOrder> for OrderResult {
                 OrderType::IOC(_) => OrderStatus::PartiallyFilled,
                 OrderType::FOK(_) => OrderStatus::Cancelled,
                 OrderType::SystemLevel(_) => OrderStatus::PartiallyFilled,
+                OrderType::Stop(_) => OrderStatus::PartiallyFilled,
+                OrderType::StopLimit(_, _) => OrderStatus::PartiallyFilled,
             }
         };
         Self {
@@ -285,6 +745,7 @@ impl From<TradeOrder> for OrderResult {
             remaining_qty: trade_order.remaining_qty,
             fills: trade_order.fills,
             status,
+            prevented_qty: trade_order.prevented_qty,
         }
     }
 }
@@ -299,11 +760,19 @@ impl From<OrderRequest> for OrderResult {
             remaining_qty: order_request.qty,
             fills: Vec::new(),
             status: OrderStatus::Cancelled,
+            prevented_qty: Decimal::ZERO,
         }
     }
 }
 
 impl OrderResult {
+    pub fn rejected(order_request: OrderRequest, reason: RejectReason) -> Self {
+        Self {
+            status: OrderStatus::Rejected(reason),
+            ..Self::from(order_request)
+        }
+    }
+
     pub fn cancelled(trade_order: TradeOrder) -> Self {
         Self {
             trade_id: trade_order.id,
@@ -313,6 +782,14 @@ impl OrderResult {
             remaining_qty: trade_order.remaining_qty,
             fills: trade_order.fills,
             status: OrderStatus::Cancelled,
+            prevented_qty: trade_order.prevented_qty,
+        }
+    }
+
+    pub fn expired(trade_order: TradeOrder) -> Self {
+        Self {
+            status: OrderStatus::Expired,
+            ..Self::cancelled(trade_order)
         }
     }
 
@@ -331,23 +808,28 @@ impl OrderResult {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TradeExecution {
     pub qty: Quantity,
     pub price: Price,
     pub taker_order_id: OrderId,
     pub maker_order_id: OrderId,
     pub take_side: Side,
+    #[cfg_attr(feature = "serde", serde(with = "crate::codec::nanos"))]
     pub timestamp: Timestamp,
+    pub taker_account: Option<AccountId>,
+    pub maker_account: Option<AccountId>,
 }
 
 impl TradeExecution {
-    pub fn new(
+    pub fn new<Q>(
         qty: Quantity,
         price: Price,
-        taker_order: &TradeOrder,
-        maker_order: &TradeOrder,
+        taker_order: &TradeOrder<Q>,
+        maker_order: &TradeOrder<Q>,
         taker_side: Side,
+        timestamp: Timestamp,
     ) -> Self {
         Self {
             price,
@@ -355,7 +837,9 @@ impl TradeExecution {
             taker_order_id: taker_order.id,
             maker_order_id: maker_order.id,
             take_side: taker_side,
-            timestamp: timestamp(),
+            timestamp,
+            taker_account: taker_order.account,
+            maker_account: maker_order.account,
         }
     }
 }
@@ -376,7 +860,7 @@ mod tests {
     fn test_trade_order_fill() {
         let mut order = TradeOrder::new(100);
         let mut fill_qty = Decimal::from(60);
-        order.fill(&mut fill_qty, 10, create_order_id());
+        order.fill(&mut fill_qty, 10, create_order_id(), timestamp());
         assert_eq!(order.remaining_qty, 40.into());
         assert_eq!(order.fills.len(), 1);
         assert_eq!(fill_qty, Decimal::ZERO);
@@ -386,7 +870,7 @@ mod tests {
     fn test_trade_order_fill_by_same_quantity() {
         let mut order1 = TradeOrder::new(100);
         let mut order2 = TradeOrder::new(100);
-        let fill_qty = order1.filled_by(&mut order2, 10);
+        let fill_qty = order1.filled_by(&mut order2, 10, timestamp());
         assert_eq!(fill_qty, Decimal::ONE_HUNDRED);
         assert_eq!(order1.remaining_qty, Decimal::ZERO);
         assert_eq!(order2.remaining_qty, Decimal::ZERO);
@@ -398,7 +882,7 @@ mod tests {
     fn test_trade_order_fill_by_larger_quantity() {
         let mut order1 = TradeOrder::new(50);
         let mut order2 = TradeOrder::new(100);
-        let fill_qty = order1.filled_by(&mut order2, 10);
+        let fill_qty = order1.filled_by(&mut order2, 10, timestamp());
         assert_eq!(fill_qty, 50.into());
         assert_eq!(order1.remaining_qty, Decimal::ZERO);
         assert_eq!(order2.remaining_qty, 50.into());
@@ -412,11 +896,11 @@ mod tests {
         let mut order2 = TradeOrder::new(10);
         let mut order3 = TradeOrder::new(10);
         let mut order4 = TradeOrder::new(10);
-        let fill_qty = order1.filled_by(&mut order2, 10);
+        let fill_qty = order1.filled_by(&mut order2, 10, timestamp());
         assert_eq!(fill_qty, 10.into());
-        let fill_qty = order1.filled_by(&mut order3, 10);
+        let fill_qty = order1.filled_by(&mut order3, 10, timestamp());
         assert_eq!(fill_qty, 10.into());
-        let fill_qty = order1.filled_by(&mut order4, 10);
+        let fill_qty = order1.filled_by(&mut order4, 10, timestamp());
         assert_eq!(fill_qty, 10.into());
         assert_eq!(order1.remaining_qty, 70.into());
         assert_eq!(order2.remaining_qty, 0.into());
@@ -428,12 +912,70 @@ mod tests {
         assert_eq!(order4.fills.len(), 1);
     }
 
+    #[test]
+    fn test_iceberg_order_fill_and_replenish() {
+        let request = OrderRequest::new(Side::Ask, 100, OrderType::limit(10)).iceberg(30);
+        let mut iceberg = TradeOrder::from(request);
+        assert_eq!(iceberg.visible_qty(), 30.into());
+        assert_eq!(iceberg.reserve_qty(), 70.into());
+
+        let mut taker = TradeOrder::new(50);
+        let fill_qty = iceberg.filled_by(&mut taker, 10, timestamp());
+        assert_eq!(fill_qty, 30.into());
+        assert_eq!(taker.remaining_qty, 20.into());
+        assert_eq!(iceberg.visible_qty(), Decimal::ZERO);
+        assert_eq!(iceberg.remaining_qty, 70.into());
+
+        assert!(iceberg.replenish(timestamp()));
+        assert_eq!(iceberg.visible_qty(), 30.into());
+        assert_eq!(iceberg.reserve_qty(), 40.into());
+        assert!(!iceberg.replenish(timestamp()));
+    }
+
+    #[test]
+    fn test_time_in_force_expiry() {
+        let created = std::time::UNIX_EPOCH + std::time::Duration::from_secs(90_000);
+        assert_eq!(TimeInForce::GTC.expiry(created), None);
+        let gtd = created + std::time::Duration::from_secs(60);
+        assert_eq!(TimeInForce::GTD(gtd).expiry(created), Some(gtd));
+        assert_eq!(
+            TimeInForce::DAY.expiry(created),
+            Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(172_800))
+        );
+    }
+
+    #[test]
+    fn test_trade_order_amend() {
+        let mut order = TradeOrder::from(OrderRequest::new(Side::Bid, 100, OrderType::limit(10)));
+        let mut taker = TradeOrder::new(30);
+        order.filled_by(&mut taker, 10, timestamp());
+
+        order.amend(50, 11, timestamp());
+        assert_eq!(order.remaining_qty, 50.into());
+        assert_eq!(order.filled_quantity(), 30.into());
+        assert_eq!(order.order_type, OrderType::limit(11));
+    }
+
     #[test]
     fn test_order_request() {
         let request = OrderRequest::new(Side::Ask, 100, OrderType::limit(10));
         assert_eq!(request.price(), Some(10.into()));
         let request = OrderRequest::new(Side::Bid, 100, OrderType::Market);
         assert_eq!(request.price(), None);
+        let request = OrderRequest::new(Side::Bid, 100, OrderType::stop_limit(10, 11));
+        assert_eq!(request.price(), Some(11.into()));
+    }
+
+    #[test]
+    fn test_order_type_triggered() {
+        assert_eq!(OrderType::stop(10).trigger_price(), Some(10.into()));
+        assert_eq!(OrderType::stop(10).triggered(), OrderType::Market);
+        assert_eq!(
+            OrderType::stop_limit(10, 11).triggered(),
+            OrderType::limit(11)
+        );
+        assert_eq!(OrderType::limit(10).trigger_price(), None);
+        assert_eq!(OrderType::limit(10).triggered(), OrderType::limit(10));
     }
 
     #[test]
@@ -448,4 +990,47 @@ mod tests {
         assert_eq!(result.fills.len(), 0);
         assert_eq!(result.get_id(), id);
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_encoding() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let order_id = create_id_from_bytes("maker");
+        let fill = Fill::new(
+            Decimal::new(5, 0),
+            Decimal::new(1005, 1),
+            order_id,
+            UNIX_EPOCH + Duration::from_nanos(1_700_000_000_000_000_001),
+        );
+        let json = serde_json::to_string(&fill).unwrap();
+        assert_eq!(
+            json,
+            format!(
+                r#"{{"qty":"5","price":"100.5","timestamp":1700000000000000001,"order_id":"{}"}}"#,
+                order_id
+            )
+        );
+        assert_eq!(serde_json::from_str::<Fill>(&json).unwrap(), fill);
+
+        let request = OrderRequest::new(Side::Bid, 10, OrderType::stop_limit(105, 106))
+            .iceberg(2)
+            .post_only(PostOnly::Reprice(Decimal::new(1, 1)))
+            .time_in_force(TimeInForce::GTD(UNIX_EPOCH + Duration::from_secs(60)))
+            .account(7);
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.contains(r#""order_type":{"StopLimit":["105","106"]}"#));
+        assert!(json.contains(r#""time_in_force":{"GTD":60000000000}"#));
+        assert_eq!(
+            serde_json::from_str::<OrderRequest>(&json).unwrap(),
+            request
+        );
+
+        let mut order = TradeOrder::from_request(request, UNIX_EPOCH);
+        order.cancel(4);
+        let result = OrderResult::from(order);
+        let json = serde_json::to_string(&result).unwrap();
+        let decoded: OrderResult = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&decoded).unwrap(), json);
+    }
 }
@@ -1,6 +1,7 @@
 use ahash::AHashMap as HashMap;
 //use dashmap::DashMap as HashMap;
 // use std::collections::HashMap;
+use std::collections::{btree_set, BTreeSet};
 use std::hash::Hash;
 
 #[derive(Debug)]
@@ -10,11 +11,16 @@ use std::hash::Hash;
 /// leaving redundant data being stored.
 ///
 /// SparseVec allows us to store the data in a HashMap, and we can still iterate over the data in the order of the keys.
+/// The keys are also kept in a BTreeSet with the smallest and largest cached, so the extremes are read in O(1) and
+/// only need a O(log n) lookup when an extreme is removed.
 pub struct SparseVec<K, V>
 where
     K: Eq + Hash + Ord + Clone,
 {
     data: HashMap<K, V>,
+    keys: BTreeSet<K>,
+    min_index: Option<K>,
+    max_index: Option<K>,
 }
 
 impl<K, V> Default for SparseVec<K, V>
@@ -24,6 +30,9 @@ where
     fn default() -> Self {
         SparseVec {
             data: HashMap::new(),
+            keys: BTreeSet::new(),
+            min_index: None,
+            max_index: None,
         }
     }
 }
@@ -35,39 +44,42 @@ where
     pub fn with_capacity(capacity: usize) -> Self {
         SparseVec {
             data: HashMap::with_capacity(capacity),
+            ..Self::default()
         }
     }
 
     pub fn insert(&mut self, index: K, value: V) -> Option<V> {
-        self.data.insert(index, value)
-
-        // if self.data.insert(index.clone(), value).is_none() {
-        //     self.max_index = Some(
-        //         self.max_index
-        //             .take()
-        //             .map_or(index.clone(), |m| max(m, index.clone())),
-        //     );
-        //     self.min_index = Some(
-        //         self.min_index
-        //             .take()
-        //             .map_or(index.clone(), |m| min(m, index)),
-        //     );
-        // }
+        let previous = self.data.insert(index.clone(), value);
+        if previous.is_none() {
+            if self.min_index.as_ref().is_none_or(|m| index < *m) {
+                self.min_index = Some(index.clone());
+            }
+            if self.max_index.as_ref().is_none_or(|m| index > *m) {
+                self.max_index = Some(index.clone());
+            }
+            self.keys.insert(index);
+        }
+        previous
     }
 
     pub fn remove(&mut self, index: &K) -> Option<V> {
-        self.data.remove(index)
+        let result = self.data.remove(index)?;
+        self.keys.remove(index);
+        if Some(index) == self.min_index.as_ref() {
+            self.min_index = self.keys.first().cloned();
+        }
+        if Some(index) == self.max_index.as_ref() {
+            self.max_index = self.keys.last().cloned();
+        }
+        Some(result)
+    }
 
-        // let result = self.data.remove(index);
-        // if result.is_some() {
-        //     if Some(index) == self.max_index.as_ref() {
-        //         self.max_index = self.data.keys().max().cloned();
-        //     }
-        //     if Some(index) == self.min_index.as_ref() {
-        //         self.min_index = self.data.keys().min().cloned();
-        //     }
-        // }
-        // result
+    /// Returns the value at the index, inserting `f()` first if there is none.
+    pub fn get_or_insert_with(&mut self, index: K, f: impl FnOnce() -> V) -> &mut V {
+        if !self.data.contains_key(&index) {
+            self.insert(index.clone(), f());
+        }
+        self.data.get_mut(&index).expect("value was just inserted")
     }
 
     pub fn get(&self, index: &K) -> Option<&V> {
@@ -78,16 +90,57 @@ where
         self.data.get_mut(index)
     }
 
-    pub fn max_index(&self) -> Option<K> {
-        self.data.keys().max().cloned()
+    pub fn max_index(&self) -> Option<&K> {
+        self.max_index.as_ref()
+    }
+
+    pub fn min_index(&self) -> Option<&K> {
+        self.min_index.as_ref()
+    }
+
+    /// Iterates over the keys in ascending order, or descending order if `descending` is set.
+    pub fn keys(&self, descending: bool) -> Keys<'_, K> {
+        Keys {
+            inner: self.keys.iter(),
+            descending,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
     }
 
-    pub fn min_index(&self) -> Option<K> {
-        self.data.keys().min().cloned()
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.keys.clear();
+        self.min_index = None;
+        self.max_index = None;
     }
+}
+
+/// Ordered iterator over the keys of a SparseVec.
+pub struct Keys<'a, K> {
+    inner: btree_set::Iter<'a, K>,
+    descending: bool,
+}
 
-    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
-        self.data.iter()
+impl<'a, K> Iterator for Keys<'a, K> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.descending {
+            self.inner.next_back()
+        } else {
+            self.inner.next()
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
     }
 }
 
@@ -119,7 +172,28 @@ mod tests {
         sv.insert(5, 50);
         sv.insert(10, 100);
         sv.insert(3, 30);
-        assert_eq!(sv.max_index(), Some(10));
-        assert_eq!(sv.min_index(), Some(3));
+        assert_eq!(sv.max_index(), Some(&10));
+        assert_eq!(sv.min_index(), Some(&3));
+
+        sv.remove(&10);
+        sv.remove(&3);
+        assert_eq!(sv.max_index(), Some(&5));
+        assert_eq!(sv.min_index(), Some(&5));
+        sv.remove(&5);
+        assert_eq!(sv.max_index(), None);
+        assert_eq!(sv.min_index(), None);
+    }
+
+    #[test]
+    fn test_sparse_vec_keys_ordered() {
+        let mut sv = SparseVec::<u64, u64>::default();
+        for k in [7, 2, 9, 4] {
+            sv.insert(k, k * 10);
+        }
+        // Re-inserting an existing key does not duplicate it
+        sv.insert(4, 41);
+        assert_eq!(sv.keys(false).copied().collect::<Vec<_>>(), [2, 4, 7, 9]);
+        assert_eq!(sv.keys(true).copied().collect::<Vec<_>>(), [9, 7, 4, 2]);
+        assert_eq!(sv.len(), 4);
     }
 }
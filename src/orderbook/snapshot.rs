@@ -0,0 +1,572 @@
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+
+use crate::codec::*;
+use crate::errors::{OrderBookError, Result};
+
+use super::orders::{Fill, OrderType, PostOnly, SelfTradePrevention};
+use super::types::*;
+
+// Start of the binary format, followed by the version as a little endian u16
+const MAGIC: &[u8; 6] = b"OBSNAP";
+/// Version of the binary and JSON snapshot formats written by this build.
+pub const SNAPSHOT_VERSION: u16 = 4;
+
+/// OrderBookSnapshot is the full state of an `OrderBook`, every resting and stop order with its fills and timestamps.
+///
+/// Levels are listed best price first and the orders of a level in time priority, so `OrderBook::restore` rebuilds
+/// the same queues. Lookups such as the order locations and the expiry index are derived from the orders on restore, as
+/// is the indicative uncross of a book snapshotted during a call auction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBookSnapshot {
+    // Decimal places of the book's prices
+    pub scale: u32,
+    // Decimal places of the book's quantities
+    pub qty_scale: u32,
+    pub last_trade_price: Option<Price>,
+    // A call or volatility auction is running, the book may be crossed
+    pub in_auction: bool,
+    pub bids: Vec<LevelSnapshot>,
+    pub asks: Vec<LevelSnapshot>,
+    // Stop orders by trigger price, lowest trigger first
+    pub stop_bids: Vec<LevelSnapshot>,
+    pub stop_asks: Vec<LevelSnapshot>,
+}
+
+/// The orders queued at one price, or waiting on one trigger price.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelSnapshot {
+    pub price: Price,
+    pub orders: Vec<OrderSnapshot>,
+}
+
+/// Every field of a `TradeOrder`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderSnapshot {
+    pub id: OrderId,
+    pub side: Side,
+    pub order_type: OrderType,
+    pub account: Option<AccountId>,
+    pub post_only: Option<PostOnly>,
+    pub initial_qty: Quantity,
+    pub remaining_qty: Quantity,
+    pub peak_qty: Option<Quantity>,
+    pub displayed_qty: Quantity,
+    pub fills: Vec<Fill>,
+    pub self_trade_prevention: SelfTradePrevention,
+    pub prevented_qty: Quantity,
+    pub self_trade_cancelled: bool,
+    pub expires_at: Option<Timestamp>,
+    pub creation_timestamp: Timestamp,
+    pub last_modified_timestamp: Timestamp,
+}
+
+impl OrderBookSnapshot {
+    fn sides(&self) -> [&Vec<LevelSnapshot>; 4] {
+        [&self.bids, &self.asks, &self.stop_bids, &self.stop_asks]
+    }
+
+    /// Encodes the snapshot in the binary format, a versioned header, the levels and a CRC-32 of what precedes it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        put_u32(&mut buf, self.scale);
+        put_u32(&mut buf, self.qty_scale);
+        put_opt_decimal(&mut buf, self.last_trade_price);
+        put_bool(&mut buf, self.in_auction);
+        for levels in self.sides() {
+            put_u32(&mut buf, levels.len() as u32);
+            for level in levels {
+                put_decimal(&mut buf, level.price);
+                put_u32(&mut buf, level.orders.len() as u32);
+                for order in &level.orders {
+                    put_order(&mut buf, order);
+                }
+            }
+        }
+        let checksum = crc32(&buf);
+        put_u32(&mut buf, checksum);
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let header_len = MAGIC.len() + 2;
+        if bytes.len() < header_len + 4 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(invalid("not a binary order book snapshot"));
+        }
+        let version = u16::from_le_bytes([bytes[MAGIC.len()], bytes[MAGIC.len() + 1]]);
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid(format!("unsupported version {}", version)));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 4);
+        if crc32(body).to_le_bytes() != checksum {
+            return Err(invalid("checksum mismatch"));
+        }
+        let mut reader = Reader::new(&body[header_len..]);
+        read_snapshot(&mut reader)
+            .filter(|_| reader.is_empty())
+            .ok_or_else(|| invalid("malformed snapshot"))
+    }
+
+    /// Encodes the snapshot as JSON. Prices and quantities are decimal strings, timestamps nanoseconds since the unix
+    /// epoch and ids hyphenated uuids.
+    pub fn to_json(&self) -> String {
+        let levels = |levels: &Vec<LevelSnapshot>| -> Value {
+            levels
+                .iter()
+                .map(|level| {
+                    json!({
+                        "price": level.price.to_string(),
+                        "orders": level.orders.iter().map(order_to_json).collect::<Vec<_>>(),
+                    })
+                })
+                .collect()
+        };
+        json!({
+            "version": SNAPSHOT_VERSION,
+            "scale": self.scale,
+            "qty_scale": self.qty_scale,
+            "last_trade_price": self.last_trade_price.map(|p| p.to_string()),
+            "in_auction": self.in_auction,
+            "bids": levels(&self.bids),
+            "asks": levels(&self.asks),
+            "stop_bids": levels(&self.stop_bids),
+            "stop_asks": levels(&self.stop_asks),
+        })
+        .to_string()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json).map_err(|err| invalid(err.to_string()))?;
+        let version = json_u64(field(&value, "version")?)?;
+        if version != SNAPSHOT_VERSION as u64 {
+            return Err(invalid(format!("unsupported version {}", version)));
+        }
+        let levels = |name: &str| -> Result<Vec<LevelSnapshot>> {
+            json_array(field(&value, name)?)?
+                .iter()
+                .map(|level| {
+                    Ok(LevelSnapshot {
+                        price: json_decimal(field(level, "price")?)?,
+                        orders: json_array(field(level, "orders")?)?
+                            .iter()
+                            .map(order_from_json)
+                            .collect::<Result<_>>()?,
+                    })
+                })
+                .collect()
+        };
+        Ok(Self {
+            scale: json_u64(field(&value, "scale")?)? as u32,
+            qty_scale: json_u64(field(&value, "qty_scale")?)? as u32,
+            last_trade_price: json_opt(field(&value, "last_trade_price")?, json_decimal)?,
+            in_auction: json_bool(field(&value, "in_auction")?)?,
+            bids: levels("bids")?,
+            asks: levels("asks")?,
+            stop_bids: levels("stop_bids")?,
+            stop_asks: levels("stop_asks")?,
+        })
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> OrderBookError {
+    OrderBookError::InvalidSnapshot(reason.into())
+}
+
+fn put_order(buf: &mut Vec<u8>, order: &OrderSnapshot) {
+    put_uuid(buf, order.id);
+    put_side(buf, order.side);
+    put_order_type(buf, order.order_type);
+    put_opt_u64(buf, order.account);
+    put_post_only(buf, order.post_only);
+    put_decimal(buf, order.initial_qty);
+    put_decimal(buf, order.remaining_qty);
+    put_opt_decimal(buf, order.peak_qty);
+    put_decimal(buf, order.displayed_qty);
+    put_u32(buf, order.fills.len() as u32);
+    for fill in &order.fills {
+        put_decimal(buf, fill.qty);
+        put_decimal(buf, fill.price);
+        put_timestamp(buf, fill.timestamp);
+        put_uuid(buf, fill.order_id);
+    }
+    put_self_trade_prevention(buf, order.self_trade_prevention);
+    put_decimal(buf, order.prevented_qty);
+    put_bool(buf, order.self_trade_cancelled);
+    put_opt_timestamp(buf, order.expires_at);
+    put_timestamp(buf, order.creation_timestamp);
+    put_timestamp(buf, order.last_modified_timestamp);
+}
+
+fn read_snapshot(reader: &mut Reader) -> Option<OrderBookSnapshot> {
+    let scale = reader.u32()?;
+    let qty_scale = reader.u32()?;
+    let last_trade_price = reader.opt_decimal()?;
+    let in_auction = reader.bool()?;
+    let mut sides = Vec::with_capacity(4);
+    for _ in 0..4 {
+        let levels = (0..reader.u32()?)
+            .map(|_| {
+                let price = reader.decimal()?;
+                let orders = (0..reader.u32()?)
+                    .map(|_| read_order(reader))
+                    .collect::<Option<_>>()?;
+                Some(LevelSnapshot { price, orders })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        sides.push(levels);
+    }
+    let [bids, asks, stop_bids, stop_asks] = <[_; 4]>::try_from(sides).ok()?;
+    Some(OrderBookSnapshot {
+        scale,
+        qty_scale,
+        last_trade_price,
+        in_auction,
+        bids,
+        asks,
+        stop_bids,
+        stop_asks,
+    })
+}
+
+fn read_order(reader: &mut Reader) -> Option<OrderSnapshot> {
+    Some(OrderSnapshot {
+        id: reader.uuid()?,
+        side: reader.side()?,
+        order_type: reader.order_type()?,
+        account: reader.opt_u64()?,
+        post_only: reader.post_only()?,
+        initial_qty: reader.decimal()?,
+        remaining_qty: reader.decimal()?,
+        peak_qty: reader.opt_decimal()?,
+        displayed_qty: reader.decimal()?,
+        fills: (0..reader.u32()?)
+            .map(|_| {
+                Some(Fill {
+                    qty: reader.decimal()?,
+                    price: reader.decimal()?,
+                    timestamp: reader.timestamp()?,
+                    order_id: reader.uuid()?,
+                })
+            })
+            .collect::<Option<_>>()?,
+        self_trade_prevention: reader.self_trade_prevention()?,
+        prevented_qty: reader.decimal()?,
+        self_trade_cancelled: reader.bool()?,
+        expires_at: reader.opt_timestamp()?,
+        creation_timestamp: reader.timestamp()?,
+        last_modified_timestamp: reader.timestamp()?,
+    })
+}
+
+fn order_to_json(order: &OrderSnapshot) -> Value {
+    let order_type = match order.order_type {
+        OrderType::Market => json!({ "type": "Market" }),
+        OrderType::Limit(price)
+        | OrderType::IOC(price)
+        | OrderType::FOK(price)
+        | OrderType::SystemLevel(price) => {
+            json!({ "type": order.order_type.to_string(), "price": price.to_string() })
+        }
+        OrderType::Stop(trigger) => json!({ "type": "Stop", "trigger": trigger.to_string() }),
+        OrderType::StopLimit(trigger, price) => json!({
+            "type": "StopLimit",
+            "trigger": trigger.to_string(),
+            "price": price.to_string(),
+        }),
+    };
+    let post_only = order.post_only.map(|post_only| match post_only {
+        PostOnly::Reject => json!({ "mode": "Reject" }),
+        PostOnly::Reprice(tick) => json!({ "mode": "Reprice", "tick": tick.to_string() }),
+    });
+    let fills: Vec<Value> = order
+        .fills
+        .iter()
+        .map(|fill| {
+            json!({
+                "qty": fill.qty.to_string(),
+                "price": fill.price.to_string(),
+                "timestamp": to_nanos(fill.timestamp),
+                "order_id": fill.order_id.to_string(),
+            })
+        })
+        .collect();
+    json!({
+        "id": order.id.to_string(),
+        "side": format!("{:?}", order.side),
+        "order_type": order_type,
+        "account": order.account,
+        "post_only": post_only,
+        "initial_qty": order.initial_qty.to_string(),
+        "remaining_qty": order.remaining_qty.to_string(),
+        "peak_qty": order.peak_qty.map(|qty| qty.to_string()),
+        "displayed_qty": order.displayed_qty.to_string(),
+        "fills": fills,
+        "self_trade_prevention": format!("{:?}", order.self_trade_prevention),
+        "prevented_qty": order.prevented_qty.to_string(),
+        "self_trade_cancelled": order.self_trade_cancelled,
+        "expires_at": order.expires_at.map(to_nanos),
+        "creation_timestamp": to_nanos(order.creation_timestamp),
+        "last_modified_timestamp": to_nanos(order.last_modified_timestamp),
+    })
+}
+
+fn order_from_json(value: &Value) -> Result<OrderSnapshot> {
+    let order_type = field(value, "order_type")?;
+    let price = || json_decimal(field(order_type, "price")?);
+    let trigger = || json_decimal(field(order_type, "trigger")?);
+    let order_type = match json_str(field(order_type, "type")?)? {
+        "Market" => OrderType::Market,
+        "Limit" => OrderType::Limit(price()?),
+        "IOC" => OrderType::IOC(price()?),
+        "FOK" => OrderType::FOK(price()?),
+        "SystemLevel" => OrderType::SystemLevel(price()?),
+        "Stop" => OrderType::Stop(trigger()?),
+        "StopLimit" => OrderType::StopLimit(trigger()?, price()?),
+        other => return Err(invalid(format!("unknown order type {}", other))),
+    };
+    let side = match json_str(field(value, "side")?)? {
+        "Bid" => Side::Bid,
+        "Ask" => Side::Ask,
+        other => return Err(invalid(format!("unknown side {}", other))),
+    };
+    let self_trade_prevention = match json_str(field(value, "self_trade_prevention")?)? {
+        "CancelNewest" => SelfTradePrevention::CancelNewest,
+        "CancelOldest" => SelfTradePrevention::CancelOldest,
+        "CancelBoth" => SelfTradePrevention::CancelBoth,
+        "DecrementAndCancel" => SelfTradePrevention::DecrementAndCancel,
+        other => {
+            return Err(invalid(format!(
+                "unknown self-trade prevention mode {}",
+                other
+            )))
+        }
+    };
+    let post_only = json_opt(field(value, "post_only")?, |post_only| {
+        match json_str(field(post_only, "mode")?)? {
+            "Reject" => Ok(PostOnly::Reject),
+            "Reprice" => Ok(PostOnly::Reprice(json_decimal(field(post_only, "tick")?)?)),
+            other => Err(invalid(format!("unknown post only mode {}", other))),
+        }
+    })?;
+    let fills = json_array(field(value, "fills")?)?
+        .iter()
+        .map(|fill| {
+            Ok(Fill {
+                qty: json_decimal(field(fill, "qty")?)?,
+                price: json_decimal(field(fill, "price")?)?,
+                timestamp: json_timestamp(field(fill, "timestamp")?)?,
+                order_id: json_uuid(field(fill, "order_id")?)?,
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(OrderSnapshot {
+        id: json_uuid(field(value, "id")?)?,
+        side,
+        order_type,
+        account: json_opt(field(value, "account")?, json_u64)?,
+        post_only,
+        initial_qty: json_decimal(field(value, "initial_qty")?)?,
+        remaining_qty: json_decimal(field(value, "remaining_qty")?)?,
+        peak_qty: json_opt(field(value, "peak_qty")?, json_decimal)?,
+        displayed_qty: json_decimal(field(value, "displayed_qty")?)?,
+        fills,
+        self_trade_prevention,
+        prevented_qty: json_decimal(field(value, "prevented_qty")?)?,
+        self_trade_cancelled: field(value, "self_trade_cancelled")?
+            .as_bool()
+            .ok_or_else(|| invalid("self_trade_cancelled is not a boolean"))?,
+        expires_at: json_opt(field(value, "expires_at")?, json_timestamp)?,
+        creation_timestamp: json_timestamp(field(value, "creation_timestamp")?)?,
+        last_modified_timestamp: json_timestamp(field(value, "last_modified_timestamp")?)?,
+    })
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Result<&'a Value> {
+    value
+        .get(name)
+        .ok_or_else(|| invalid(format!("missing field {}", name)))
+}
+
+fn json_opt<T>(value: &Value, parse: impl Fn(&Value) -> Result<T>) -> Result<Option<T>> {
+    match value {
+        Value::Null => Ok(None),
+        value => parse(value).map(Some),
+    }
+}
+
+fn json_array(value: &Value) -> Result<&Vec<Value>> {
+    value
+        .as_array()
+        .ok_or_else(|| invalid(format!("{} is not an array", value)))
+}
+
+fn json_str(value: &Value) -> Result<&str> {
+    value
+        .as_str()
+        .ok_or_else(|| invalid(format!("{} is not a string", value)))
+}
+
+fn json_u64(value: &Value) -> Result<u64> {
+    value
+        .as_u64()
+        .ok_or_else(|| invalid(format!("{} is not an unsigned integer", value)))
+}
+
+fn json_bool(value: &Value) -> Result<bool> {
+    value
+        .as_bool()
+        .ok_or_else(|| invalid(format!("{} is not a boolean", value)))
+}
+
+fn json_decimal(value: &Value) -> Result<Decimal> {
+    let value = json_str(value)?;
+    value
+        .parse()
+        .map_err(|_| invalid(format!("{} is not a decimal", value)))
+}
+
+fn json_uuid(value: &Value) -> Result<OrderId> {
+    let value = json_str(value)?;
+    value
+        .parse()
+        .map_err(|_| invalid(format!("{} is not a uuid", value)))
+}
+
+fn json_timestamp(value: &Value) -> Result<Timestamp> {
+    json_u64(value).map(from_nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{ManualClock, OrderBook, OrderRequest, TimeInForce};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn book() -> OrderBook<ManualClock> {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut book = OrderBook::with_scale(clock.clone(), 1, 0);
+        let orders = [
+            OrderRequest::new(Side::Ask, 10, OrderType::limit(101)).account(1),
+            OrderRequest::new(Side::Ask, 20, OrderType::limit(101)).iceberg(5),
+            OrderRequest::new(Side::Ask, 5, OrderType::limit(Decimal::new(1025, 1))),
+            OrderRequest::new(Side::Bid, 8, OrderType::limit(99)).time_in_force(TimeInForce::GTD(
+                UNIX_EPOCH + Duration::from_secs(1_800_000_000),
+            )),
+            OrderRequest::new(Side::Bid, 4, OrderType::limit(99))
+                .post_only(PostOnly::Reprice(Decimal::new(1, 1))),
+            OrderRequest::new(Side::Bid, 12, OrderType::Market),
+            OrderRequest::new(Side::Bid, 3, OrderType::stop_limit(110, 111)),
+            OrderRequest::new(Side::Ask, 3, OrderType::stop(90)).time_in_force(TimeInForce::DAY),
+        ];
+        for order in orders {
+            clock.advance(Duration::from_millis(1));
+            book.add_order(order).unwrap();
+        }
+        book
+    }
+
+    #[test]
+    fn test_snapshot_round_trips() {
+        let snapshot = book().snapshot();
+        assert_eq!(snapshot.asks.len(), 2);
+        // The first ask was filled by the market order, the partially filled iceberg is still in the queue
+        let iceberg = &snapshot.asks[0].orders[0];
+        assert_eq!(iceberg.remaining_qty, 18.into());
+        assert_eq!(iceberg.displayed_qty, 3.into());
+        assert_eq!(iceberg.fills.len(), 1);
+        assert_eq!(snapshot.stop_bids[0].price, 110.into());
+        assert_eq!(snapshot.stop_asks[0].price, 90.into());
+        assert!(snapshot.stop_asks[0].orders[0].expires_at.is_some());
+
+        let bytes = snapshot.to_bytes();
+        assert_eq!(OrderBookSnapshot::from_bytes(&bytes), Ok(snapshot.clone()));
+        let json = snapshot.to_json();
+        assert_eq!(OrderBookSnapshot::from_json(&json), Ok(snapshot));
+    }
+
+    #[test]
+    fn test_restore_rebuilds_the_book() {
+        let mut book = book();
+        let clock = book.clock().clone();
+        let mut restored =
+            OrderBook::<ManualClock>::restore(clock.clone(), book.snapshot()).unwrap();
+        assert_eq!(restored.snapshot(), book.snapshot());
+        assert_eq!(restored.order_loc, book.order_loc);
+        assert_eq!(restored.stop_loc, book.stop_loc);
+        assert_eq!(restored.last_trade_price(), book.last_trade_price());
+
+        // Both books keep matching the same way, queue priority and expiries included
+        let order = OrderRequest::new(Side::Ask, 10, OrderType::Market);
+        let (_, executions) = book.add_order(order).unwrap();
+        let (_, restored_executions) = restored.add_order(order).unwrap();
+        assert_eq!(restored_executions, executions);
+        let now = UNIX_EPOCH + Duration::from_secs(1_900_000_000);
+        assert_eq!(
+            restored.expire_orders(now).len(),
+            book.expire_orders(now).len()
+        );
+        assert_eq!(restored.snapshot(), book.snapshot());
+    }
+
+    #[test]
+    fn test_restore_during_call_auction() {
+        let clock = ManualClock::new(UNIX_EPOCH);
+        let mut book = OrderBook::with_clock(clock.clone());
+        book.start_auction();
+        book.add_order(OrderRequest::new(Side::Bid, 10, OrderType::limit(101)))
+            .unwrap();
+        book.add_order(OrderRequest::new(Side::Ask, 6, OrderType::limit(100)))
+            .unwrap();
+        let snapshot = book.snapshot();
+        assert!(snapshot.in_auction);
+        for snapshot in [
+            OrderBookSnapshot::from_bytes(&snapshot.to_bytes()).unwrap(),
+            OrderBookSnapshot::from_json(&snapshot.to_json()).unwrap(),
+        ] {
+            assert_eq!(snapshot, book.snapshot());
+            let mut restored = OrderBook::<ManualClock>::restore(clock.clone(), snapshot).unwrap();
+            assert!(restored.in_auction());
+            assert_eq!(restored.indicative_uncross(), book.indicative_uncross());
+            // The crossed book keeps accumulating orders until it uncrosses
+            let (_, executions) = restored
+                .add_order(OrderRequest::new(Side::Ask, 2, OrderType::limit(99)))
+                .unwrap();
+            assert!(executions.is_empty());
+            assert_eq!(restored.uncross().len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_snapshot_rejects_bad_input() {
+        let snapshot = book().snapshot();
+        let mut bytes = snapshot.to_bytes();
+        bytes[20] ^= 0xFF;
+        assert_eq!(
+            OrderBookSnapshot::from_bytes(&bytes),
+            Err(invalid("checksum mismatch"))
+        );
+
+        let mut bytes = snapshot.to_bytes();
+        bytes[MAGIC.len()] = 9;
+        assert_eq!(
+            OrderBookSnapshot::from_bytes(&bytes),
+            Err(invalid("unsupported version 9"))
+        );
+
+        let json = snapshot.to_json().replace("\"version\":4", "\"version\":5");
+        assert_eq!(
+            OrderBookSnapshot::from_json(&json),
+            Err(invalid("unsupported version 5"))
+        );
+
+        let mut duplicated = snapshot.clone();
+        let order = duplicated.bids[0].orders[0].clone();
+        duplicated.bids[0].orders.push(order.clone());
+        assert_eq!(
+            OrderBook::<ManualClock>::restore(ManualClock::default(), duplicated).unwrap_err(),
+            OrderBookError::DuplicateOrderId(order.id)
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use super::orders::TradeOrder;
+use super::types::*;
+
+#[derive(Debug)]
+/// TriggerBook holds the stop orders of one side until the market trades through their trigger price.
+///
+/// Buy stops (Bid) trigger when a trade prints at or above the trigger price, sell stops (Ask) when a trade prints at or
+/// below it. Orders sharing a trigger price are released in the order they were added. Trigger prices are keyed by `P`
+/// at the scale of the book.
+pub struct TriggerBook<P: PriceType = Price> {
+    s: Side,
+    // Decimal places of the prices, used to convert the keys back to prices
+    scale: u32,
+    triggers: BTreeMap<P, VecDeque<TradeOrder<P::Qty>>>,
+}
+
+impl TriggerBook {
+    pub fn new(s: Side) -> TriggerBook {
+        Self::with_scale(s, 0)
+    }
+}
+
+impl<P: PriceType> TriggerBook<P> {
+    /// Creates an empty trigger book whose prices have at most `scale` decimal places.
+    pub fn with_scale(s: Side, scale: u32) -> Self {
+        TriggerBook {
+            s,
+            scale,
+            triggers: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_order(&mut self, trigger: P, order: TradeOrder<P::Qty>) {
+        self.triggers.entry(trigger).or_default().push_back(order);
+    }
+
+    pub fn remove_order(&mut self, trigger: &P, order_id: OrderId) -> Option<TradeOrder<P::Qty>> {
+        let level = self.triggers.get_mut(trigger)?;
+        let removed_order = level
+            .iter()
+            .position(|o| o.id == order_id)
+            .and_then(|i| level.remove(i));
+        if level.is_empty() {
+            self.triggers.remove(trigger);
+        }
+        removed_order
+    }
+
+    /// Returns true if a trade at `price` reaches the given trigger price.
+    pub fn is_triggered(&self, trigger: &P, price: &P) -> bool {
+        match self.s {
+            Side::Bid => price >= trigger,
+            Side::Ask => price <= trigger,
+        }
+    }
+
+    /// Removes and returns every order triggered by a trade at `price`.
+    /// Orders are returned closest trigger first, then in time priority.
+    pub fn take_triggered(&mut self, price: P) -> Vec<TradeOrder<P::Qty>> {
+        let triggered_prices: Vec<P> = match self.s {
+            Side::Bid => self.triggers.range(..=price).map(|(p, _)| *p).collect(),
+            Side::Ask => self
+                .triggers
+                .range(price..)
+                .rev()
+                .map(|(p, _)| *p)
+                .collect(),
+        };
+        triggered_prices
+            .iter()
+            .filter_map(|p| self.triggers.remove(p))
+            .flatten()
+            .collect()
+    }
+
+    pub fn get_order(&self, trigger: P, order_id: OrderId) -> Option<&TradeOrder<P::Qty>> {
+        self.triggers
+            .get(&trigger)
+            .and_then(|level| level.iter().find(|o| o.id == order_id))
+    }
+
+    /// Iterates over the trigger prices, lowest first, with their orders in the order they were added.
+    pub fn iter_orders(
+        &self,
+    ) -> impl Iterator<Item = (Price, impl Iterator<Item = &TradeOrder<P::Qty>>)> {
+        self.triggers
+            .iter()
+            .map(|(trigger, level)| (trigger.to_price(self.scale), level.iter()))
+    }
+
+    pub fn get_order_count(&self) -> usize {
+        self.triggers.values().map(|level| level.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triggers.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.triggers.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trigger_book_take_triggered_bid() {
+        let mut book = TriggerBook::new(Side::Bid);
+        book.add_order(10.into(), TradeOrder::new(100));
+        book.add_order(12.into(), TradeOrder::new(50));
+        book.add_order(11.into(), TradeOrder::new(75));
+
+        assert!(book.take_triggered(9.into()).is_empty());
+        let triggered = book.take_triggered(11.into());
+        assert_eq!(triggered.len(), 2);
+        assert_eq!(triggered[0].remaining_qty, 100.into());
+        assert_eq!(triggered[1].remaining_qty, 75.into());
+        assert_eq!(book.get_order_count(), 1);
+    }
+
+    #[test]
+    fn test_trigger_book_take_triggered_ask() {
+        let mut book = TriggerBook::new(Side::Ask);
+        book.add_order(10.into(), TradeOrder::new(100));
+        book.add_order(12.into(), TradeOrder::new(50));
+        book.add_order(11.into(), TradeOrder::new(75));
+
+        assert!(book.take_triggered(13.into()).is_empty());
+        let triggered = book.take_triggered(11.into());
+        assert_eq!(triggered.len(), 2);
+        assert_eq!(triggered[0].remaining_qty, 50.into());
+        assert_eq!(triggered[1].remaining_qty, 75.into());
+        assert_eq!(book.get_order_count(), 1);
+    }
+
+    #[test]
+    fn test_trigger_book_remove_order() {
+        let mut book = TriggerBook::new(Side::Ask);
+        let order = TradeOrder::new(100);
+        let order_id = order.id;
+        book.add_order(10.into(), order);
+        assert!(book.get_order(10.into(), order_id).is_some());
+        assert!(book.remove_order(&10.into(), order_id).is_some());
+        assert!(book.is_empty());
+    }
+}
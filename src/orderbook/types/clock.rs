@@ -0,0 +1,179 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use super::{timestamp, Timestamp};
+
+/// Clock is the source of every timestamp recorded by the order book.
+/// Swapping the clock lets replays and tests control the times stamped on orders, fills and executions.
+pub trait Clock: std::fmt::Debug {
+    fn now(&self) -> Timestamp;
+}
+
+/// Wall clock time, this is the default clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        timestamp()
+    }
+}
+
+/// A clock that only moves when it is told to, used to replay historical data or simulate time.
+/// Clones share the same time, so a handle can be kept to drive a clock owned by an order book.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new(UNIX_EPOCH)
+    }
+}
+
+impl ManualClock {
+    pub fn new(start: Timestamp) -> Self {
+        Self {
+            nanos: Arc::new(AtomicU64::new(to_nanos(start))),
+        }
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        self.nanos.store(to_nanos(now), Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        UNIX_EPOCH + Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+}
+
+/// Wall clock time anchored when the clock is created and advanced by a monotonic timer.
+/// Every reading is at least one nanosecond after the previous one, so timestamps can be used to order events.
+#[derive(Debug, Clone)]
+pub struct MonotonicClock {
+    origin: Timestamp,
+    start: Instant,
+    last: Arc<AtomicU64>,
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        Self {
+            origin: timestamp(),
+            start: Instant::now(),
+            last: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Timestamp {
+        let elapsed = self.start.elapsed().as_nanos() as u64;
+        let previous = self
+            .last
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(elapsed.max(last + 1))
+            })
+            .unwrap_or_default();
+        self.origin + Duration::from_nanos(elapsed.max(previous + 1))
+    }
+}
+
+// Replay time of a clock that is not replaying
+const LIVE: u64 = u64::MAX;
+
+/// A clock that reads the time it is set to while commands are replayed, then the live clock `C` once the replay
+/// hands over. Clones share the replay time.
+#[derive(Debug, Clone)]
+pub struct ReplayClock<C> {
+    live: C,
+    replayed: Arc<AtomicU64>,
+}
+
+impl<C> ReplayClock<C> {
+    pub fn new(live: C) -> Self {
+        Self {
+            live,
+            replayed: Arc::new(AtomicU64::new(LIVE)),
+        }
+    }
+
+    /// Reads `now` until the clock is set again or goes live.
+    pub fn replay_at(&self, now: Timestamp) {
+        self.replayed.store(to_nanos(now), Ordering::SeqCst);
+    }
+
+    pub fn go_live(&self) {
+        self.replayed.store(LIVE, Ordering::SeqCst);
+    }
+
+    pub fn live(&self) -> &C {
+        &self.live
+    }
+}
+
+impl<C: Clock> Clock for ReplayClock<C> {
+    fn now(&self) -> Timestamp {
+        match self.replayed.load(Ordering::SeqCst) {
+            LIVE => self.live.now(),
+            nanos => UNIX_EPOCH + Duration::from_nanos(nanos),
+        }
+    }
+}
+
+fn to_nanos(ts: Timestamp) -> u64 {
+    ts.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::default();
+        assert_eq!(clock.now(), UNIX_EPOCH);
+        let handle = clock.clone();
+        handle.advance(Duration::from_secs(5));
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_secs(5));
+        handle.set(UNIX_EPOCH + Duration::from_nanos(42));
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_nanos(42));
+    }
+
+    #[test]
+    fn test_replay_clock() {
+        let live = ManualClock::new(UNIX_EPOCH + Duration::from_secs(100));
+        let clock = ReplayClock::new(live.clone());
+        assert_eq!(clock.now(), live.now());
+        clock.clone().replay_at(UNIX_EPOCH + Duration::from_secs(7));
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_secs(7));
+        clock.go_live();
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_secs(100));
+    }
+
+    #[test]
+    fn test_monotonic_clock_strictly_increasing() {
+        let clock = MonotonicClock::new();
+        let mut last = clock.now();
+        for _ in 0..1_000 {
+            let now = clock.now();
+            assert!(now > last);
+            last = now;
+        }
+    }
+}
@@ -1,18 +1,21 @@
 use rust_decimal::Decimal;
 use uuid::Uuid;
 
-use super::TradeOrder;
-
 pub type OrderId = uuid::Uuid;
+// Owner of an order, used to stop an account trading with itself
+pub type AccountId = u64;
 
-pub type PriceLevel = std::collections::VecDeque<TradeOrder>;
 pub type Timestamp = std::time::SystemTime;
 
+mod clock;
+mod price_type;
 mod side;
 
 pub type Price = Decimal;
 pub type Quantity = Decimal;
 
+pub use clock::{Clock, ManualClock, MonotonicClock, ReplayClock, SystemClock};
+pub use price_type::{Lots, PriceType, QuantityType, Ticks};
 pub use side::Side;
 
 pub fn timestamp() -> Timestamp {
@@ -0,0 +1,176 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+
+use rust_decimal::Decimal;
+
+use super::{Price, Quantity};
+
+/// PriceType is the representation a book keeps its prices and quantities in.
+///
+/// Prices and quantities cross the book's API as `Price` and `Quantity` (both `Decimal`) and are converted once on
+/// entry, at per-book scales giving the number of decimal places each may have. Price levels, order locations and
+/// trigger prices are keyed by `Self`, the quantities of the orders held by the book are `Self::Qty`. `Decimal` keeps
+/// both as they are, `Ticks` stores prices as integer ticks and quantities as integer `Lots`, which are much cheaper to
+/// hash, compare and add up on the matching path.
+pub trait PriceType: Copy + Ord + Hash + Debug {
+    /// How the book holds the quantities of its orders.
+    type Qty: QuantityType;
+
+    /// Converts the price, returns None if it can not be represented at the scale.
+    fn from_price(price: Price, scale: u32) -> Option<Self>;
+
+    fn to_price(self, scale: u32) -> Price;
+}
+
+/// QuantityType is the representation a book keeps the quantities of its orders in, see `PriceType`.
+pub trait QuantityType:
+    Copy + Ord + Default + Debug + Add<Output = Self> + Sub<Output = Self> + AddAssign + SubAssign
+{
+    const ZERO: Self;
+
+    /// Converts the quantity, returns None if it can not be represented at the scale.
+    fn from_qty(qty: Quantity, scale: u32) -> Option<Self>;
+
+    fn to_qty(self, scale: u32) -> Quantity;
+}
+
+impl PriceType for Decimal {
+    type Qty = Decimal;
+
+    fn from_price(price: Price, _scale: u32) -> Option<Self> {
+        Some(price)
+    }
+
+    fn to_price(self, _scale: u32) -> Price {
+        self
+    }
+}
+
+impl QuantityType for Decimal {
+    const ZERO: Self = Decimal::ZERO;
+
+    fn from_qty(qty: Quantity, _scale: u32) -> Option<Self> {
+        Some(qty)
+    }
+
+    fn to_qty(self, _scale: u32) -> Quantity {
+        self
+    }
+}
+
+/// A price as a whole number of ticks, where a tick is `10^-scale`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ticks(pub i64);
+
+impl PriceType for Ticks {
+    type Qty = Lots;
+
+    fn from_price(price: Price, scale: u32) -> Option<Self> {
+        to_units(price, scale).map(Ticks)
+    }
+
+    fn to_price(self, scale: u32) -> Price {
+        Decimal::new(self.0, scale)
+    }
+}
+
+/// A quantity as a whole number of lots, where a lot is `10^-scale`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Lots(pub i64);
+
+impl QuantityType for Lots {
+    const ZERO: Self = Lots(0);
+
+    fn from_qty(qty: Quantity, scale: u32) -> Option<Self> {
+        to_units(qty, scale).map(Lots)
+    }
+
+    fn to_qty(self, scale: u32) -> Quantity {
+        Decimal::new(self.0, scale)
+    }
+}
+
+impl Add for Lots {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Lots(self.0 + other.0)
+    }
+}
+
+impl Sub for Lots {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Lots(self.0 - other.0)
+    }
+}
+
+impl AddAssign for Lots {
+    fn add_assign(&mut self, other: Self) {
+        self.0 += other.0;
+    }
+}
+
+impl SubAssign for Lots {
+    fn sub_assign(&mut self, other: Self) {
+        self.0 -= other.0;
+    }
+}
+
+// Number of `10^-scale` units in the value, None if it is not a whole number of them or does not fit an i64
+fn to_units(value: Decimal, scale: u32) -> Option<i64> {
+    // Trailing zeros beyond the scale are fine, any other digit is not on a unit
+    let value = if value.scale() > scale {
+        value.normalize()
+    } else {
+        value
+    };
+    let shift = scale.checked_sub(value.scale())?;
+    let units = value.mantissa().checked_mul(10i128.checked_pow(shift)?)?;
+    i64::try_from(units).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticks_round_trip() {
+        let price = Decimal::new(12345, 2);
+        let ticks = Ticks::from_price(price, 2).unwrap();
+        assert_eq!(ticks, Ticks(12345));
+        assert_eq!(ticks.to_price(2), price);
+
+        assert_eq!(Ticks::from_price(price, 4), Some(Ticks(1234500)));
+        assert_eq!(
+            Ticks::from_price(Decimal::new(12300, 3), 1),
+            Some(Ticks(123))
+        );
+        assert_eq!(Ticks::from_price(Decimal::from(7), 0), Some(Ticks(7)));
+    }
+
+    #[test]
+    fn test_ticks_rejects_prices_off_scale() {
+        assert_eq!(Ticks::from_price(Decimal::new(12345, 3), 2), None);
+        assert_eq!(Ticks::from_price(Decimal::MAX, 2), None);
+    }
+
+    #[test]
+    fn test_lots_round_trip() {
+        let qty = Decimal::new(25, 1);
+        let lots = Lots::from_qty(qty, 3).unwrap();
+        assert_eq!(lots, Lots(2500));
+        assert_eq!(lots.to_qty(3), qty);
+        assert_eq!(lots - Lots(500) + Lots(1), Lots(2001));
+        assert_eq!(Lots::from_qty(Decimal::new(1, 4), 3), None);
+    }
+
+    #[test]
+    fn test_ticks_order_matches_prices() {
+        let low = Ticks::from_price(Decimal::new(-5, 1), 1).unwrap();
+        let high = Ticks::from_price(Decimal::new(25, 1), 1).unwrap();
+        assert!(low < high);
+    }
+}
@@ -2,6 +2,7 @@
 
 /// Side of the order, either Ask or Bid.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Side {
     Ask,
     Bid,
@@ -0,0 +1,166 @@
+use crate::orderbook::Timestamp;
+use crate::{OrderType, RejectReason};
+
+/// Trading phase of a market, deciding which orders and actions it accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SessionPhase {
+    // Before the first open of the market, orders can be cancelled but not placed
+    PreOpen,
+    // A call auction is running, orders rest without matching until it uncrosses
+    Auction,
+    // Orders match as they arrive
+    #[default]
+    Continuous,
+    // Trading is suspended, orders can be cancelled but not placed
+    Halted,
+    // The market is closed to every order and cancel
+    Closed,
+}
+
+impl SessionPhase {
+    /// Returns whether a market can move from this phase to `next`.
+    /// A market opens from pre-open, is halted or auctioned from any open phase, and only reopens through pre-open once
+    /// closed.
+    pub fn can_transition_to(self, next: SessionPhase) -> bool {
+        use SessionPhase::*;
+        matches!(
+            (self, next),
+            (PreOpen, Auction | Continuous | Closed)
+                | (Auction, Continuous | Halted | Closed)
+                | (Continuous, Auction | Halted | Closed)
+                | (Halted, Auction | Continuous | Closed)
+                | (Closed, PreOpen)
+        )
+    }
+
+    /// Checks that an order of the type can be placed in this phase.
+    pub fn check_order(self, order_type: &OrderType) -> Result<(), RejectReason> {
+        match self {
+            SessionPhase::Continuous => Ok(()),
+            SessionPhase::Auction => match order_type {
+                OrderType::Market | OrderType::IOC(_) | OrderType::FOK(_) => {
+                    Err(RejectReason::AuctionInProgress)
+                }
+                _ => Ok(()),
+            },
+            SessionPhase::Halted => Err(RejectReason::MarketHalted),
+            SessionPhase::PreOpen | SessionPhase::Closed => Err(RejectReason::MarketNotOpen),
+        }
+    }
+
+    pub fn accepts_cancels(self) -> bool {
+        self != SessionPhase::Closed
+    }
+}
+
+/// Session holds the phase of a market and the transitions scheduled for it.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    phase: SessionPhase,
+    // Transitions waiting for their time, earliest first, in the order they were scheduled at equal times
+    schedule: Vec<(Timestamp, SessionPhase)>,
+}
+
+impl Session {
+    pub fn new(phase: SessionPhase) -> Self {
+        Self {
+            phase,
+            schedule: Vec::new(),
+        }
+    }
+
+    pub fn phase(&self) -> SessionPhase {
+        self.phase
+    }
+
+    /// Moves the session to `next`, returning the phase it left.
+    pub fn transition(&mut self, next: SessionPhase) -> Option<SessionPhase> {
+        if !self.phase.can_transition_to(next) {
+            return None;
+        }
+        Some(std::mem::replace(&mut self.phase, next))
+    }
+
+    /// Schedules a transition to `phase` at `at`. It is only checked against the phase the session is in when it is due.
+    pub fn schedule(&mut self, at: Timestamp, phase: SessionPhase) {
+        let index = self.schedule.partition_point(|(time, _)| *time <= at);
+        self.schedule.insert(index, (at, phase));
+    }
+
+    pub fn scheduled(&self) -> &[(Timestamp, SessionPhase)] {
+        &self.schedule
+    }
+
+    /// Takes the earliest scheduled transition that is due by `now`.
+    pub fn next_due(&mut self, now: Timestamp) -> Option<SessionPhase> {
+        match self.schedule.first() {
+            Some((at, _)) if *at <= now => Some(self.schedule.remove(0).1),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_session_transitions() {
+        let mut session = Session::new(SessionPhase::PreOpen);
+        assert_eq!(session.transition(SessionPhase::Halted), None);
+        assert_eq!(
+            session.transition(SessionPhase::Auction),
+            Some(SessionPhase::PreOpen)
+        );
+        assert_eq!(
+            session.transition(SessionPhase::Continuous),
+            Some(SessionPhase::Auction)
+        );
+        assert_eq!(session.transition(SessionPhase::Continuous), None);
+        assert_eq!(
+            session.transition(SessionPhase::Closed),
+            Some(SessionPhase::Continuous)
+        );
+        assert_eq!(session.transition(SessionPhase::Continuous), None);
+        assert_eq!(session.phase(), SessionPhase::Closed);
+    }
+
+    #[test]
+    fn test_session_phase_accepts_orders() {
+        let limit = OrderType::limit(100);
+        assert_eq!(
+            SessionPhase::Continuous.check_order(&OrderType::Market),
+            Ok(())
+        );
+        assert_eq!(SessionPhase::Auction.check_order(&limit), Ok(()));
+        assert_eq!(
+            SessionPhase::Auction.check_order(&OrderType::ioc(100)),
+            Err(RejectReason::AuctionInProgress)
+        );
+        assert_eq!(
+            SessionPhase::Halted.check_order(&limit),
+            Err(RejectReason::MarketHalted)
+        );
+        assert_eq!(
+            SessionPhase::PreOpen.check_order(&limit),
+            Err(RejectReason::MarketNotOpen)
+        );
+        assert!(SessionPhase::Halted.accepts_cancels());
+        assert!(!SessionPhase::Closed.accepts_cancels());
+    }
+
+    #[test]
+    fn test_session_schedule() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let mut session = Session::new(SessionPhase::PreOpen);
+        session.schedule(at(20), SessionPhase::Continuous);
+        session.schedule(at(10), SessionPhase::Auction);
+        session.schedule(at(20), SessionPhase::Halted);
+        assert_eq!(session.next_due(at(5)), None);
+        assert_eq!(session.next_due(at(25)), Some(SessionPhase::Auction));
+        assert_eq!(session.next_due(at(25)), Some(SessionPhase::Continuous));
+        assert_eq!(session.scheduled(), [(at(20), SessionPhase::Halted)]);
+    }
+}
@@ -174,7 +174,9 @@ impl App {
             OrderType::FOK(_)
             | OrderType::IOC(_)
             | OrderType::Limit(_)
-            | OrderType::SystemLevel(_) => {
+            | OrderType::SystemLevel(_)
+            | OrderType::Stop(_)
+            | OrderType::StopLimit(_, _) => {
                 let price = Paragraph::new(Span::raw(format!("Price: {}", self.input_price)))
                     .style(Style::default().fg(if self.input_mode == InputMode::Price {
                         Color::Green
@@ -370,14 +372,21 @@ impl App {
             OrderType::IOC(_) => OrderType::ioc(price),
             OrderType::FOK(_) => OrderType::fok(price),
             OrderType::SystemLevel(_) => OrderType::system_level(price),
+            OrderType::Stop(_) => OrderType::stop(price),
+            OrderType::StopLimit(trigger, _) => OrderType::stop_limit(trigger, price),
         };
         let order_type = self.current_order_type;
 
         let order = OrderRequest::new(self.current_side, quantity, order_type);
-        let (result, executions) = self.order_book.add_order(order);
-
-        self.update_order_history(price, quantity);
-        self.update_status(result, executions);
+        match self.order_book.add_order(order) {
+            Ok((result, executions)) => {
+                self.update_order_history(price, quantity);
+                self.update_status(result, executions);
+            }
+            Err(err) => {
+                self.status_message = format!("{}. Order not placed.", err);
+            }
+        }
 
         // Clear inputs
         self.input_price.clear();